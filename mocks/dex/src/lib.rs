@@ -0,0 +1,159 @@
+//! Ref Finance style exchange used by the sandbox integration tests.
+//! Tokens are deposited with `ft_transfer_call`, swapped at a fixed rate per pool and
+//! withdrawn back with `withdraw`. Do not deploy outside of tests.
+use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::U128;
+use near_sdk::{
+    assert_one_yocto, env, near, AccountId, Gas, NearToken, PanicOnDefault, Promise,
+    PromiseError, PromiseOrValue,
+};
+
+// same as Ref Finance's withdraw, so the sandbox tests check the gas divvy attaches to it
+const GAS_FOR_FT_TRANSFER: Gas = Gas::from_tgas(20);
+const GAS_FOR_RESOLVE_WITHDRAW: Gas = Gas::from_tgas(20);
+
+#[near(serializers = [borsh, json])]
+pub struct Pool {
+    pub token_in: AccountId,
+    pub token_out: AccountId,
+    /// `amount_out = amount_in * rate_numerator / rate_denominator`
+    pub rate_numerator: U128,
+    pub rate_denominator: U128,
+}
+
+/// Same shape as Ref Finance's `SwapAction`.
+#[near(serializers = [json])]
+pub struct SwapAction {
+    pub pool_id: u64,
+    pub token_in: AccountId,
+    pub amount_in: Option<U128>,
+    pub token_out: AccountId,
+    pub min_amount_out: U128,
+}
+
+#[near(contract_state)]
+#[derive(PanicOnDefault)]
+pub struct Contract {
+    pools: Vec<Pool>,
+    deposits: LookupMap<(AccountId, AccountId), u128>,
+}
+
+#[near]
+impl Contract {
+    #[init]
+    pub fn new() -> Self {
+        Self { pools: Vec::new(), deposits: LookupMap::new(b"d".to_vec()) }
+    }
+
+    pub fn add_pool(
+        &mut self,
+        token_in: AccountId,
+        token_out: AccountId,
+        rate_numerator: U128,
+        rate_denominator: U128,
+    ) -> u64 {
+        self.pools.push(Pool { token_in, token_out, rate_numerator, rate_denominator });
+        self.pools.len() as u64 - 1
+    }
+
+    /// Swaps the caller's deposits, fails if any action returns less than its `min_amount_out`.
+    #[payable]
+    pub fn swap(&mut self, actions: Vec<SwapAction>, referral_id: Option<AccountId>) -> U128 {
+        assert_one_yocto();
+        let _ = referral_id;
+        let account_id = env::predecessor_account_id();
+        let mut amount_out = 0;
+        let mut prev_amount_out = None;
+        for action in actions {
+            let pool = self.pools.get(action.pool_id as usize).expect("ERR_POOL_NOT_FOUND");
+            assert_eq!(pool.token_in, action.token_in, "ERR_WRONG_TOKEN_IN");
+            assert_eq!(pool.token_out, action.token_out, "ERR_WRONG_TOKEN_OUT");
+            let amount_in = action.amount_in.map(|amount| amount.0).or(prev_amount_out).expect("ERR_NO_AMOUNT_IN");
+            amount_out = amount_in * pool.rate_numerator.0 / pool.rate_denominator.0;
+            assert!(amount_out >= action.min_amount_out.0, "ERR_MIN_AMOUNT");
+
+            self.internal_withdraw(&account_id, &action.token_in, amount_in);
+            self.internal_deposit(&account_id, &action.token_out, amount_out);
+            prev_amount_out = Some(amount_out);
+        }
+        U128(amount_out)
+    }
+
+    /// Sends `amount` of the caller's deposit of `token_id` back, the deposit is only taken
+    /// once the transfer succeeded and the call fails with the transfer.
+    #[payable]
+    pub fn withdraw(
+        &mut self,
+        token_id: AccountId,
+        amount: U128,
+        unregister: Option<bool>,
+        skip_unwrap_near: Option<bool>,
+    ) -> Promise {
+        assert_one_yocto();
+        let _ = (unregister, skip_unwrap_near);
+        let account_id = env::predecessor_account_id();
+        assert!(self.get_deposit(account_id.clone(), token_id.clone()).0 >= amount.0, "ERR_NOT_ENOUGH_DEPOSIT");
+        ext_ft_core::ext(token_id.clone())
+            .with_attached_deposit(NearToken::from_yoctonear(1))
+            .with_static_gas(GAS_FOR_FT_TRANSFER)
+            .ft_transfer(account_id.clone(), amount, None)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_WITHDRAW)
+                    .on_withdraw(account_id, token_id, amount),
+            )
+    }
+
+    #[private]
+    pub fn on_withdraw(
+        &mut self,
+        account_id: AccountId,
+        token_id: AccountId,
+        amount: U128,
+        #[callback_result] result: Result<(), PromiseError>,
+    ) {
+        assert!(result.is_ok(), "ERR_WITHDRAW_FAILED");
+        self.internal_withdraw(&account_id, &token_id, amount.0);
+    }
+
+    pub fn get_deposit(&self, account_id: AccountId, token_id: AccountId) -> U128 {
+        U128(self.deposits.get(&(account_id, token_id)).unwrap_or(0))
+    }
+
+    pub fn get_pool(&self, pool_id: u64) -> Option<&Pool> {
+        self.pools.get(pool_id as usize)
+    }
+}
+
+#[near]
+impl FungibleTokenReceiver for Contract {
+    /// Deposits the received tokens to the sender's account, swaps are not supported inline.
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        assert!(msg.is_empty(), "ERR_MSG_NOT_SUPPORTED");
+        let token_id = env::predecessor_account_id();
+        self.internal_deposit(&sender_id, &token_id, amount.0);
+        PromiseOrValue::Value(U128(0))
+    }
+}
+
+impl Contract {
+    fn internal_deposit(&mut self, account_id: &AccountId, token_id: &AccountId, amount: u128) {
+        let key = (account_id.clone(), token_id.clone());
+        let balance = self.deposits.get(&key).unwrap_or(0);
+        self.deposits.insert(&key, &(balance + amount));
+    }
+
+    fn internal_withdraw(&mut self, account_id: &AccountId, token_id: &AccountId, amount: u128) {
+        let key = (account_id.clone(), token_id.clone());
+        let balance = self.deposits.get(&key).unwrap_or(0);
+        assert!(balance >= amount, "ERR_NOT_ENOUGH_DEPOSIT");
+        self.deposits.insert(&key, &(balance - amount));
+    }
+}
@@ -0,0 +1,24 @@
+#!/usr/bin/env bash
+# Builds the first release of the contract into res/divvy_v1.wasm, the fixture of the
+# `test_upgrade_from_v1` sandbox test. The release is built from its commit in a temporary
+# worktree with the build command of the README.
+set -euo pipefail
+
+V1_COMMIT=156c4d184cf8cdba4e97fa8349d1847b889d7932
+
+root=$(git rev-parse --show-toplevel)
+worktree=$(mktemp -d)
+trap 'git -C "$root" worktree remove --force "$worktree"' EXIT
+
+git -C "$root" worktree add --detach "$worktree" "$V1_COMMIT"
+# the release did not commit a lockfile, resolve its dependencies to the current ones
+if [ -f "$root/Cargo.lock" ]; then
+  cp "$root/Cargo.lock" "$worktree/Cargo.lock"
+fi
+(
+  cd "$worktree"
+  env RUSTFLAGS='-Ctarget-cpu=mvp' cargo +nightly build -Zbuild-std=panic_abort,std --target=wasm32-unknown-unknown --release
+)
+mkdir -p "$root/res"
+cp "$worktree/target/wasm32-unknown-unknown/release/divvy_wealth.wasm" "$root/res/divvy_v1.wasm"
+echo "wrote $root/res/divvy_v1.wasm"
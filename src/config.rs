@@ -0,0 +1,433 @@
+use near_sdk::{env, near, AccountId};
+
+use crate::events::DivvyEvent;
+use crate::stash::Stash;
+use crate::token_vault::VaultFees;
+use crate::{Contract, ContractExt};
+
+/// Owner-settable configuration of the contract.
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// Token whose stash balances are held as native NEAR.
+    pub wrap_near_id: Option<AccountId>,
+    /// Ref Finance style exchange used by `deposit_swap`.
+    pub dex_id: Option<AccountId>,
+    /// Account collecting the fees of the contract.
+    pub fee_receiver_id: Option<AccountId>,
+    /// Most members a stash can have, `None` for no limit.
+    pub max_members_per_stash: Option<u32>,
+    /// Highest fees a stash owner can set on a vault, see `set_vault_fees`.
+    pub max_fees: VaultFees,
+}
+
+/// Classes of methods which can be paused together.
+#[near(serializers = [json])]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MethodClass {
+    /// Moving tokens into deposits or vaults.
+    Deposits,
+    /// Moving tokens out of vaults or deposits, including payouts.
+    Withdrawals,
+    Swaps,
+}
+
+/// Paused method classes, of the whole contract or of a single stash.
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PauseFlags {
+    pub deposits: bool,
+    pub withdrawals: bool,
+    pub swaps: bool,
+}
+
+impl PauseFlags {
+    pub fn is_paused(&self, method: MethodClass) -> bool {
+        match method {
+            MethodClass::Deposits => self.deposits,
+            MethodClass::Withdrawals => self.withdrawals,
+            MethodClass::Swaps => self.swaps,
+        }
+    }
+
+    pub fn set(&mut self, methods: &[MethodClass], paused: bool) {
+        for method in methods {
+            match method {
+                MethodClass::Deposits => self.deposits = paused,
+                MethodClass::Withdrawals => self.withdrawals = paused,
+                MethodClass::Swaps => self.swaps = paused,
+            }
+        }
+    }
+}
+
+#[near(serializers = [json])]
+pub struct ConfigView {
+    pub owner_id: AccountId,
+    pub config: Config,
+    /// Method classes paused for all stashes.
+    pub paused: PauseFlags,
+}
+
+#[near]
+impl Contract {
+    /// Sets the token whose stash balances are held as native NEAR. Balances already held as
+    /// the old or the new token would be paid out in the wrong form, so this is refused while
+    /// any stash has a vault of either. Owner only.
+    pub fn set_wrap_near_id(&mut self, wrap_near_id: Option<AccountId>) {
+        self.assert_owner();
+        for token_id in [&self.config.wrap_near_id, &wrap_near_id].into_iter().flatten() {
+            assert!(
+                self.stashes.values().all(|stash| Stash::from(stash).get_vault(token_id).is_none()),
+                "ERR_WRAP_NEAR_IN_USE"
+            );
+        }
+        self.config.wrap_near_id = wrap_near_id;
+    }
+
+    /// Sets the exchange used by new swaps, swaps in flight finish on their own. Owner only.
+    pub fn set_dex_id(&mut self, dex_id: Option<AccountId>) {
+        self.assert_owner();
+        self.config.dex_id = dex_id;
+    }
+
+    /// Sets the account vault fees set from now on are paid to. Owner only.
+    pub fn set_fee_receiver_id(&mut self, fee_receiver_id: Option<AccountId>) {
+        self.assert_owner();
+        self.config.fee_receiver_id = fee_receiver_id;
+    }
+
+    /// Applies to members added from now on, existing members stay. Owner only.
+    pub fn set_max_members_per_stash(&mut self, max_members_per_stash: Option<u32>) {
+        self.assert_owner();
+        self.config.max_members_per_stash = max_members_per_stash;
+    }
+
+    /// Sets the highest fees a stash owner can set on a vault. Vaults charging more are lowered
+    /// to the new caps, after accruing their management fee at the old rate. Owner only.
+    pub fn set_max_fees(&mut self, max_fees: VaultFees) {
+        self.assert_owner();
+        max_fees.assert_valid();
+        for (stash_id, stash) in self.stashes.to_vec() {
+            let mut stash = Stash::from(stash);
+            if stash.cap_vault_fees(&max_fees) {
+                self.internal_save_stash(stash_id, stash);
+            }
+        }
+        self.config.max_fees = max_fees;
+    }
+
+    /// Pauses `methods` in every stash, or only in `stash_id`. Owner only.
+    pub fn pause(&mut self, stash_id: Option<u64>, methods: Vec<MethodClass>) {
+        self.internal_set_paused(stash_id, &methods, true);
+        DivvyEvent::Paused { stash_id, methods }.emit();
+    }
+
+    /// Lifts a pause set by `pause` with the same `stash_id`. Owner only.
+    pub fn unpause(&mut self, stash_id: Option<u64>, methods: Vec<MethodClass>) {
+        self.internal_set_paused(stash_id, &methods, false);
+        DivvyEvent::Unpaused { stash_id, methods }.emit();
+    }
+
+    /// Sets the fees of a vault, paid in shares to the current `fee_receiver_id` of the config.
+    /// Each fee is capped by `max_fees`, see `set_max_fees`.
+    /// Stash owner only.
+    pub fn set_vault_fees(&mut self, stash_id: u64, token_id: AccountId, fees: VaultFees) {
+        let prev_storage = env::storage_usage();
+        assert!(fees.is_within(&self.config.max_fees), "ERR_FEE_TOO_HIGH");
+        let fee_receiver_id = if fees.is_zero() { None } else { self.config.fee_receiver_id.clone() };
+        let mut stash = self.internal_get_stash(stash_id);
+        stash.set_vault_fees(&token_id, fees, fee_receiver_id);
+        self.internal_save_stash(stash_id, stash);
+        self.internal_check_storage(&env::predecessor_account_id(), prev_storage);
+    }
+
+    pub fn get_config(&self) -> ConfigView {
+        ConfigView { owner_id: self.owner_id.clone(), config: self.config.clone(), paused: self.paused }
+    }
+}
+
+// internal methods
+impl Contract {
+    fn internal_set_paused(&mut self, stash_id: Option<u64>, methods: &[MethodClass], paused: bool) {
+        self.assert_owner();
+        match stash_id {
+            Some(stash_id) => {
+                let mut stash = self.internal_get_stash(stash_id);
+                stash.set_paused(methods, paused);
+                self.internal_save_stash(stash_id, stash);
+            }
+            None => self.paused.set(methods, paused),
+        }
+    }
+
+    pub(crate) fn is_paused(&self, stash: &Stash, method: MethodClass) -> bool {
+        self.paused.is_paused(method) || stash.get_paused().is_paused(method)
+    }
+
+    /// Panics if `method` is paused for the whole contract or for the stash.
+    pub(crate) fn assert_not_paused(&self, stash: &Stash, method: MethodClass) {
+        assert!(!self.is_paused(stash, method), "ERR_PAUSED");
+    }
+
+    /// Panics if adding a member would take the stash over `max_members_per_stash`.
+    pub(crate) fn assert_member_limit(&self, stash: &Stash) {
+        if let Some(max_members) = self.config.max_members_per_stash {
+            assert!(stash.get_member_count() <= max_members as u64, "ERR_TOO_MANY_MEMBERS");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::json_types::U128;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, NearToken, PromiseOrValue};
+
+    use super::*;
+    use crate::proposal::ProposalKind;
+    use crate::stash::Role;
+    use crate::test_utils::{add_member, get_context, get_events, new_contract};
+
+    fn usdt() -> AccountId {
+        "usdt-token.near".parse().unwrap()
+    }
+
+    /// Stash 0 with a usdt vault, accounts(0) deposited 3_000 usdt and pooled 2_000 of it.
+    fn setup(context: &mut VMContextBuilder) -> Contract {
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = new_contract(None);
+        contract.storage_deposit(None, None);
+        contract.create_stash("Roommates".to_string());
+        contract.add_token_to_stash(0, usdt());
+        deposit(context, &mut contract, 3_000);
+        contract.add_liquidity_to_stash(0, usdt(), 2_000);
+        contract
+    }
+
+    /// Sends usdt of accounts(0) into stash 0, returns the refunded amount.
+    fn deposit(context: &mut VMContextBuilder, contract: &mut Contract, amount: u128) -> u128 {
+        testing_env!(context.predecessor_account_id(usdt()).attached_deposit(NearToken::from_yoctonear(0)).build());
+        let unused = contract.ft_on_transfer(accounts(0), U128(amount), r#"{"stash_id": 0}"#.to_string());
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        match unused {
+            PromiseOrValue::Value(unused) => unused.0,
+            PromiseOrValue::Promise(_) => panic!("expected a value"),
+        }
+    }
+
+    #[test]
+    fn test_set_config() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.set_wrap_near_id(None);
+        contract.set_dex_id(Some("dex.near".parse().unwrap()));
+        contract.set_fee_receiver_id(Some(accounts(4)));
+        contract.set_max_members_per_stash(Some(5));
+        contract.set_max_fees(VaultFees { management_fee_bps: 200, deposit_fee_bps: 50, withdrawal_fee_bps: 50 });
+        let view = contract.get_config();
+        assert_eq!(view.owner_id, accounts(0));
+        assert_eq!(
+            view.config,
+            Config {
+                wrap_near_id: None,
+                dex_id: Some("dex.near".parse().unwrap()),
+                fee_receiver_id: Some(accounts(4)),
+                max_members_per_stash: Some(5),
+                max_fees: VaultFees { management_fee_bps: 200, deposit_fee_bps: 50, withdrawal_fee_bps: 50 },
+            }
+        );
+        assert_eq!(view.paused, PauseFlags::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_OWNER")]
+    fn test_set_config_owner_only() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.set_dex_id(Some("dex.near".parse().unwrap()));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_WRAP_NEAR_IN_USE")]
+    fn test_set_wrap_near_id_in_use() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.add_token_to_stash(0, "wrap.near".parse().unwrap());
+        contract.set_wrap_near_id(Some("wnear.near".parse().unwrap()));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_WRAP_NEAR_IN_USE")]
+    fn test_set_wrap_near_id_to_held_token() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.set_wrap_near_id(Some(usdt()));
+    }
+
+    fn fees() -> VaultFees {
+        VaultFees { management_fee_bps: 100, deposit_fee_bps: 20, withdrawal_fee_bps: 20 }
+    }
+
+    /// Allows `fees()` and pays them to accounts(4).
+    fn enable_fees(contract: &mut Contract) {
+        contract.set_fee_receiver_id(Some(accounts(4)));
+        contract.set_max_fees(fees());
+    }
+
+    #[test]
+    fn test_set_vault_fees() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        enable_fees(&mut contract);
+        contract.set_vault_fees(0, usdt(), fees());
+        let vault = contract.get_vault(0, usdt()).unwrap();
+        assert_eq!(vault.fees, fees());
+        assert_eq!(vault.fee_receiver_id, Some(accounts(4)));
+        assert_eq!(
+            get_events().last(),
+            Some(&DivvyEvent::VaultFeesUpdated { stash_id: 0, token_id: usdt(), fees: fees() })
+        );
+
+        // 2_000 of the 1_000_000 minted shares go to the fee receiver
+        assert_eq!(contract.preview_deposit(0, usdt(), U128(1_000)).0, 998_000);
+        contract.add_liquidity_to_stash(0, usdt(), 1_000);
+        assert_eq!(contract.get_shares(0, usdt(), accounts(0)).0, 2_998_000);
+        assert_eq!(contract.get_shares(0, usdt(), accounts(4)).0, 2_000);
+
+        contract.set_vault_fees(0, usdt(), VaultFees::default());
+        assert_eq!(contract.get_vault(0, usdt()).unwrap().fee_receiver_id, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_FEE_TOO_HIGH")]
+    fn test_vault_fees_capped() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        enable_fees(&mut contract);
+        contract.set_vault_fees(0, usdt(), VaultFees { withdrawal_fee_bps: 21, ..fees() });
+    }
+
+    #[test]
+    fn test_lower_max_fees_caps_vault_fees() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        enable_fees(&mut contract);
+        contract.set_vault_fees(0, usdt(), fees());
+
+        let max_fees = VaultFees { management_fee_bps: 50, deposit_fee_bps: 30, withdrawal_fee_bps: 10 };
+        contract.set_max_fees(max_fees);
+        let capped = VaultFees { management_fee_bps: 50, deposit_fee_bps: 20, withdrawal_fee_bps: 10 };
+        assert_eq!(contract.get_vault(0, usdt()).unwrap().fees, capped);
+        assert_eq!(
+            get_events().last(),
+            Some(&DivvyEvent::VaultFeesUpdated { stash_id: 0, token_id: usdt(), fees: capped })
+        );
+        assert_eq!(contract.get_config().config.max_fees, max_fees);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_FEE_RECEIVER")]
+    fn test_vault_fees_need_receiver() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        enable_fees(&mut contract);
+        contract.set_fee_receiver_id(None);
+        contract.set_vault_fees(0, usdt(), fees());
+    }
+
+    #[test]
+    #[should_panic(expected = "Caller is not authorized")]
+    fn test_vault_fees_stash_owner_only() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        enable_fees(&mut contract);
+        add_member(&mut context, &mut contract, 0, accounts(1), Role::Admin);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.set_vault_fees(0, usdt(), fees());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_FEE")]
+    fn test_fee_caps_below_whole() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.set_max_fees(VaultFees { deposit_fee_bps: 10_000, ..VaultFees::default() });
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_OWNER")]
+    fn test_pause_owner_only() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.pause(None, vec![MethodClass::Deposits]);
+    }
+
+    #[test]
+    fn test_paused_deposits_allow_withdrawals() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.pause(None, vec![MethodClass::Deposits]);
+        assert!(contract.get_config().paused.deposits);
+        assert_eq!(
+            get_events().last(),
+            Some(&DivvyEvent::Paused { stash_id: None, methods: vec![MethodClass::Deposits] })
+        );
+
+        assert_eq!(deposit(&mut context, &mut contract, 1_000), 1_000);
+        contract.remove_liquidity_from_stash(0, usdt(), 1_000_000);
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(1)).build());
+        contract.withdraw_from_stash(0, usdt(), U128(2_000));
+        assert_eq!(contract.get_deposit(0, accounts(0), usdt()).0, 0);
+
+        contract.unpause(None, vec![MethodClass::Deposits]);
+        assert_eq!(deposit(&mut context, &mut contract, 1_000), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_PAUSED")]
+    fn test_paused_add_liquidity() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.pause(None, vec![MethodClass::Deposits]);
+        contract.add_liquidity_to_stash(0, usdt(), 1_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_PAUSED")]
+    fn test_paused_stash_withdrawals() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.pause(Some(0), vec![MethodClass::Withdrawals]);
+        assert!(contract.get_stash(0).unwrap().paused.withdrawals);
+        assert!(!contract.get_config().paused.withdrawals);
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(1)).build());
+        contract.withdraw_from_stash(0, usdt(), U128(1_000));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_PAUSED")]
+    fn test_paused_payout() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.pause(Some(0), vec![MethodClass::Withdrawals]);
+        contract.add_proposal(
+            0,
+            ProposalKind::Payout { token_id: usdt(), receiver_id: accounts(3), amount: U128(100) },
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOO_MANY_MEMBERS")]
+    fn test_max_members_per_stash() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.set_max_members_per_stash(Some(2));
+        add_member(&mut context, &mut contract, 0, accounts(1), Role::Contributor);
+        add_member(&mut context, &mut contract, 0, accounts(2), Role::Contributor);
+    }
+}
@@ -0,0 +1,574 @@
+use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_sdk::json_types::U128;
+use near_sdk::{
+    assert_one_yocto, env, ext_contract, log, near, AccountId, Gas, NearToken, Promise,
+    PromiseError, PromiseOrValue,
+};
+
+use crate::config::MethodClass;
+use crate::events::DivvyEvent;
+use crate::stash::Stash;
+use crate::{Contract, ContractExt};
+
+// Each callback reserves the gas of the calls it makes on top of its own execution, the
+// sandbox tests run `deposit_swap` with `DEPOSIT_SWAP_GAS` against the mock exchange.
+const GAS_FOR_FT_TRANSFER_CALL: Gas = Gas::from_tgas(50);
+const GAS_FOR_DEX_SWAP: Gas = Gas::from_tgas(20);
+// Ref Finance's withdraw attaches 20 Tgas to its ft_transfer and 20 Tgas to its own callback
+const GAS_FOR_DEX_WITHDRAW: Gas = Gas::from_tgas(50);
+// credits the deposit or records the stranded output
+const GAS_FOR_ON_SWAP_WITHDRAW: Gas = Gas::from_tgas(10);
+// withdraw, on_swap_withdraw and 10 Tgas of its own
+const GAS_FOR_ON_SWAP: Gas = Gas::from_tgas(70);
+// swap, on_swap and 10 Tgas of its own
+const GAS_FOR_ON_SWAP_DEPOSIT: Gas = Gas::from_tgas(100);
+/// Gas to attach to `deposit_swap`: the deposit into the exchange, the callback chain and
+/// 10 Tgas for `deposit_swap` itself.
+const DEPOSIT_SWAP_GAS: Gas = Gas::from_tgas(160);
+
+/// Single hop of a swap, same shape as Ref Finance's `SwapAction`.
+#[near(serializers = [json])]
+pub struct SwapAction {
+    pub pool_id: u64,
+    pub token_in: AccountId,
+    pub amount_in: Option<U128>,
+    pub token_out: AccountId,
+    pub min_amount_out: U128,
+}
+
+/// Subset of the Ref Finance exchange interface used for swaps.
+#[allow(dead_code)]
+#[ext_contract(ext_dex)]
+pub trait RefExchange {
+    fn swap(&mut self, actions: Vec<SwapAction>, referral_id: Option<AccountId>) -> U128;
+    fn withdraw(
+        &mut self,
+        token_id: AccountId,
+        amount: U128,
+        unregister: Option<bool>,
+        skip_unwrap_near: Option<bool>,
+    ) -> Promise;
+}
+
+/// Swap carried through the callback chain of `deposit_swap`.
+#[near(serializers = [json])]
+pub struct SwapRequest {
+    pub stash_id: u64,
+    pub account_id: AccountId,
+    /// Exchange the swap started on, the configured one may change meanwhile.
+    pub dex_id: AccountId,
+    pub pool_id: u64,
+    pub token_in: AccountId,
+    pub token_out: AccountId,
+    pub amount_in: U128,
+    pub min_amount_out: U128,
+}
+
+/// Swap output of `account_id` left on the exchange after its `withdraw` failed.
+#[near(serializers = [borsh, json])]
+#[derive(Clone)]
+pub struct StrandedSwap {
+    pub account_id: AccountId,
+    pub dex_id: AccountId,
+    pub token_id: AccountId,
+    pub amount: U128,
+}
+
+#[near]
+impl Contract {
+    /// Swaps `amount_in` of the caller's deposited `token_in` in the stash into `token_out`
+    /// through `pool_id` of the configured exchange. Requires 1 yoctoNEAR.
+    ///
+    /// The tokens are deposited into the exchange with `ft_transfer_call`, swapped and withdrawn
+    /// back, then `token_out` is credited to the caller's deposits in the stash. If any step
+    /// fails the input tokens are returned to the caller's deposits instead. Needs
+    /// `DEPOSIT_SWAP_GAS` attached.
+    ///
+    /// Should the exchange fail to send the tokens back, they are recorded as stranded and
+    /// the stash cannot be removed until `retry_swap_withdraw` succeeds.
+    #[payable]
+    pub fn deposit_swap(
+        &mut self,
+        stash_id: u64,
+        pool_id: u64,
+        token_in: AccountId,
+        token_out: AccountId,
+        amount_in: U128,
+        min_amount_out: U128,
+    ) -> Promise {
+        assert_one_yocto();
+        assert!(env::prepaid_gas() >= DEPOSIT_SWAP_GAS, "ERR_NOT_ENOUGH_GAS");
+        let dex_id = self.config.dex_id.clone().expect("ERR_DEX_NOT_CONFIGURED");
+        assert_ne!(token_in, token_out, "ERR_SAME_TOKEN");
+        assert!(amount_in.0 > 0, "ERR_ZERO_AMOUNT");
+
+        let prev_storage = env::storage_usage();
+        let sender_id = env::predecessor_account_id();
+        let mut stash = self.internal_get_stash(stash_id);
+        self.assert_not_paused(&stash, MethodClass::Swaps);
+        stash.debit_for_swap(&sender_id, &token_in, &token_out, amount_in.0);
+        self.internal_save_stash(stash_id, stash);
+        self.internal_check_storage(&sender_id, prev_storage);
+
+        ext_ft_core::ext(token_in.clone())
+            .with_attached_deposit(NearToken::from_yoctonear(1))
+            .with_static_gas(GAS_FOR_FT_TRANSFER_CALL)
+            .ft_transfer_call(dex_id.clone(), amount_in, None, String::new())
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_ON_SWAP_DEPOSIT)
+                    .on_swap_deposit(SwapRequest {
+                        stash_id,
+                        account_id: sender_id,
+                        dex_id,
+                        pool_id,
+                        token_in,
+                        token_out,
+                        amount_in,
+                        min_amount_out,
+                    }),
+            )
+    }
+
+    /// Swaps whatever the exchange accepted, refunding the rest to the user's deposits.
+    #[private]
+    pub fn on_swap_deposit(
+        &mut self,
+        request: SwapRequest,
+        #[callback_result] used_amount: Result<U128, PromiseError>,
+    ) -> PromiseOrValue<U128> {
+        let used_amount = used_amount.map(|used| used.0.min(request.amount_in.0)).unwrap_or(0);
+        if used_amount < request.amount_in.0 {
+            let refund = request.amount_in.0 - used_amount;
+            self.internal_credit_swap(request.stash_id, &request.account_id, &request.token_in, refund);
+        }
+        if used_amount == 0 {
+            log!("Deposit of {} {} into the exchange failed, refunded", request.amount_in.0, request.token_in);
+            self.internal_finish_swap(request.stash_id);
+            return PromiseOrValue::Value(U128(0));
+        }
+        let request = SwapRequest { amount_in: U128(used_amount), ..request };
+
+        let action = SwapAction {
+            pool_id: request.pool_id,
+            token_in: request.token_in.clone(),
+            amount_in: Some(request.amount_in),
+            token_out: request.token_out.clone(),
+            min_amount_out: request.min_amount_out,
+        };
+        ext_dex::ext(request.dex_id.clone())
+            .with_attached_deposit(NearToken::from_yoctonear(1))
+            .with_static_gas(GAS_FOR_DEX_SWAP)
+            .swap(vec![action], None)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_ON_SWAP)
+                    .on_swap(request),
+            )
+            .into()
+    }
+
+    /// Withdraws the swap output from the exchange, or the input when the swap failed or
+    /// returned less than `min_amount_out`.
+    #[private]
+    pub fn on_swap(
+        &mut self,
+        request: SwapRequest,
+        #[callback_result] amount_out: Result<U128, PromiseError>,
+    ) -> Promise {
+        let (token_id, amount) = match amount_out {
+            Ok(amount_out) if amount_out.0 >= request.min_amount_out.0 => {
+                DivvyEvent::Swap {
+                    stash_id: request.stash_id,
+                    account_id: request.account_id.clone(),
+                    token_in: request.token_in,
+                    amount_in: request.amount_in,
+                    token_out: request.token_out.clone(),
+                    amount_out,
+                }
+                .emit();
+                (request.token_out, amount_out)
+            }
+            Ok(amount_out) => {
+                log!(
+                    "ERR_MIN_AMOUNT_OUT swap returned {} {}, refunding",
+                    amount_out.0,
+                    request.token_out
+                );
+                (request.token_in, request.amount_in)
+            }
+            Err(_) => {
+                log!("Swap of {} {} failed, refunding", request.amount_in.0, request.token_in);
+                (request.token_in, request.amount_in)
+            }
+        };
+        Self::internal_dex_withdraw(StrandedSwap {
+            account_id: request.account_id,
+            dex_id: request.dex_id,
+            token_id,
+            amount,
+        }, request.stash_id)
+    }
+
+    /// Credits the tokens withdrawn from the exchange to the user's deposits and ends the swap.
+    /// If the withdrawal failed, the tokens are recorded as stranded for `retry_swap_withdraw`.
+    /// Returns the credited amount.
+    #[private]
+    pub fn on_swap_withdraw(
+        &mut self,
+        stash_id: u64,
+        swap: StrandedSwap,
+        #[callback_result] result: Result<(), PromiseError>,
+    ) -> U128 {
+        if result.is_err() {
+            log!(
+                "ERR_DEX_WITHDRAW_FAILED {} {} for {} remain on {}",
+                swap.amount.0,
+                swap.token_id,
+                swap.account_id,
+                swap.dex_id
+            );
+            self.internal_strand_swap(stash_id, swap);
+            return U128(0);
+        }
+        self.internal_credit_swap(stash_id, &swap.account_id, &swap.token_id, swap.amount.0);
+        self.internal_finish_swap(stash_id);
+        swap.amount
+    }
+
+    /// Withdraws again the caller's swap output of `token_id` the exchange failed to send
+    /// back, crediting it to the caller's deposits. Requires 1 yoctoNEAR.
+    #[payable]
+    pub fn retry_swap_withdraw(&mut self, stash_id: u64, token_id: AccountId) -> Promise {
+        assert_one_yocto();
+        let prev_storage = env::storage_usage();
+        let account_id = env::predecessor_account_id();
+        let mut stash = self.internal_get_stash(stash_id);
+        let swap = stash.take_stranded_swap(&account_id, &token_id);
+        self.internal_save_stash(stash_id, stash);
+        self.internal_record_storage(&account_id, prev_storage);
+        Self::internal_dex_withdraw(swap, stash_id)
+    }
+
+    /// Swap outputs of `account_id` in the stash waiting for `retry_swap_withdraw`.
+    pub fn get_stranded_swaps(&self, stash_id: u64, account_id: AccountId) -> Vec<StrandedSwap> {
+        self.stashes
+            .get(&stash_id)
+            .map(|stash| Stash::from(stash).get_stranded_swaps(&account_id))
+            .unwrap_or_default()
+    }
+}
+
+// internal methods
+impl Contract {
+    fn internal_dex_withdraw(swap: StrandedSwap, stash_id: u64) -> Promise {
+        ext_dex::ext(swap.dex_id.clone())
+            .with_attached_deposit(NearToken::from_yoctonear(1))
+            .with_static_gas(GAS_FOR_DEX_WITHDRAW)
+            .withdraw(swap.token_id.clone(), swap.amount, None, None)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_ON_SWAP_WITHDRAW)
+                    .on_swap_withdraw(stash_id, swap),
+            )
+    }
+
+    fn internal_credit_swap(&mut self, stash_id: u64, account_id: &AccountId, token_id: &AccountId, amount: u128) {
+        match self.stashes.get(&stash_id).map(Stash::from) {
+            Some(mut stash) => {
+                let prev_storage = env::storage_usage();
+                stash.credit_swap(account_id, token_id, amount);
+                self.internal_save_stash(stash_id, stash);
+                self.internal_record_storage(account_id, prev_storage);
+            }
+            None => log!(
+                "ERR_STASH_NOT_FOUND {}, unable to credit {} {} to {}",
+                stash_id,
+                amount,
+                token_id,
+                account_id
+            ),
+        }
+    }
+
+    fn internal_strand_swap(&mut self, stash_id: u64, swap: StrandedSwap) {
+        // a stash with a pending swap can't be removed
+        let mut stash = self.internal_get_stash(stash_id);
+        let prev_storage = env::storage_usage();
+        let account_id = swap.account_id.clone();
+        stash.strand_swap(swap);
+        self.internal_save_stash(stash_id, stash);
+        self.internal_record_storage(&account_id, prev_storage);
+    }
+
+    /// Ends a swap of the stash, which can only be removed once none is pending.
+    fn internal_finish_swap(&mut self, stash_id: u64) {
+        if let Some(mut stash) = self.stashes.get(&stash_id).map(Stash::from) {
+            stash.finish_swap();
+            self.internal_save_stash(stash_id, stash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{get_events, new_contract};
+
+    fn usdt() -> AccountId {
+        "usdt-token.near".parse().unwrap()
+    }
+
+    fn usdc() -> AccountId {
+        "usdc-token.near".parse().unwrap()
+    }
+
+    fn dex() -> AccountId {
+        "ref-finance.near".parse().unwrap()
+    }
+
+    fn setup(context: &mut VMContextBuilder, dex_id: Option<AccountId>) -> Contract {
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = new_contract(dex_id);
+        contract.storage_deposit(None, None);
+        let stash_id = contract.create_stash("Roommates".to_string());
+        contract.add_token_to_stash(stash_id, usdt());
+        contract.add_token_to_stash(stash_id, usdc());
+
+        testing_env!(context.predecessor_account_id(usdt()).attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract.ft_on_transfer(accounts(0), U128(1_000), r#"{"stash_id": 0}"#.to_string());
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(1)).build());
+        contract
+    }
+
+    /// Takes out everything the owner holds in the stash, so it can be removed.
+    fn withdraw_deposits(context: &mut VMContextBuilder, contract: &mut Contract) {
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(1)).build());
+        for token_id in [usdt(), usdc()] {
+            let amount = deposit_of(contract, &token_id);
+            if amount > 0 {
+                contract.withdraw_from_stash(0, token_id, U128(amount));
+            }
+        }
+    }
+
+    fn swap_request(amount_in: u128) -> SwapRequest {
+        SwapRequest {
+            stash_id: 0,
+            account_id: accounts(0),
+            dex_id: dex(),
+            pool_id: 1,
+            token_in: usdt(),
+            token_out: usdc(),
+            amount_in: U128(amount_in),
+            min_amount_out: U128(390),
+        }
+    }
+
+    fn stranded(token_id: AccountId, amount: u128) -> StrandedSwap {
+        StrandedSwap { account_id: accounts(0), dex_id: dex(), token_id, amount: U128(amount) }
+    }
+
+    fn deposit_of(contract: &Contract, token_id: &AccountId) -> u128 {
+        contract.internal_get_stash(0).get_deposit(&accounts(0), token_id)
+    }
+
+    #[test]
+    fn test_deposit_swap_debits_token_in() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, Some(dex()));
+
+        contract.deposit_swap(0, 1, usdt(), usdc(), U128(400), U128(390));
+        assert_eq!(deposit_of(&contract, &usdt()), 600);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_DEX_NOT_CONFIGURED")]
+    fn test_deposit_swap_requires_dex() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, None);
+        contract.deposit_swap(0, 1, usdt(), usdc(), U128(400), U128(390));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_VAULT_FOR_TOKEN_OUT")]
+    fn test_deposit_swap_requires_token_out_vault() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, Some(dex()));
+        contract.deposit_swap(0, 1, usdt(), "eth-token.near".parse().unwrap(), U128(400), U128(390));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_ENOUGH")]
+    fn test_deposit_swap_more_than_deposited() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, Some(dex()));
+        contract.deposit_swap(0, 1, usdt(), usdc(), U128(1_001), U128(390));
+    }
+
+    #[test]
+    fn test_failed_exchange_deposit_refunds() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, Some(dex()));
+        contract.deposit_swap(0, 1, usdt(), usdc(), U128(400), U128(390));
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        contract.on_swap_deposit(swap_request(400), Err(PromiseError::Failed));
+        assert_eq!(deposit_of(&contract, &usdt()), 1_000);
+    }
+
+    #[test]
+    fn test_partially_used_exchange_deposit_refunds_rest() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, Some(dex()));
+        contract.deposit_swap(0, 1, usdt(), usdc(), U128(400), U128(390));
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        contract.on_swap_deposit(swap_request(400), Ok(U128(300)));
+        assert_eq!(deposit_of(&contract, &usdt()), 700);
+    }
+
+    #[test]
+    fn test_swap_withdraw_credits_token_out() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, Some(dex()));
+        contract.deposit_swap(0, 1, usdt(), usdc(), U128(400), U128(390));
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        let credited = contract.on_swap_withdraw(0, stranded(usdc(), 395), Ok(()));
+        assert_eq!(credited.0, 395);
+        assert_eq!(deposit_of(&contract, &usdt()), 600);
+        assert_eq!(deposit_of(&contract, &usdc()), 395);
+    }
+
+    #[test]
+    fn test_swap_below_min_amount_out_refunds() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, Some(dex()));
+        contract.deposit_swap(0, 1, usdt(), usdc(), U128(400), U128(390));
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        contract.on_swap(swap_request(400), Ok(U128(389)));
+        assert!(get_logs().iter().any(|log| log.starts_with("ERR_MIN_AMOUNT_OUT")));
+        assert!(get_events().is_empty());
+    }
+
+    #[test]
+    fn test_swap_emits_event() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, Some(dex()));
+        contract.deposit_swap(0, 1, usdt(), usdc(), U128(400), U128(390));
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        contract.on_swap(swap_request(400), Ok(U128(395)));
+        assert_eq!(
+            get_events(),
+            vec![DivvyEvent::Swap {
+                stash_id: 0,
+                account_id: accounts(0),
+                token_in: usdt(),
+                amount_in: U128(400),
+                token_out: usdc(),
+                amount_out: U128(395),
+            }]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_ENOUGH_GAS")]
+    fn test_deposit_swap_requires_gas() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, Some(dex()));
+        testing_env!(context.prepaid_gas(Gas::from_tgas(100)).build());
+        contract.deposit_swap(0, 1, usdt(), usdc(), U128(400), U128(390));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_SWAP_PENDING")]
+    fn test_remove_stash_while_swap_pending() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, Some(dex()));
+        contract.deposit_swap(0, 1, usdt(), usdc(), U128(400), U128(390));
+        contract.remove_stash(0);
+    }
+
+    #[test]
+    fn test_remove_stash_after_swap() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, Some(dex()));
+        contract.deposit_swap(0, 1, usdt(), usdc(), U128(400), U128(390));
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        contract.on_swap_withdraw(0, stranded(usdc(), 395), Ok(()));
+        withdraw_deposits(&mut context, &mut contract);
+        contract.remove_stash(0);
+        assert!(contract.stashes.get(&0).is_none());
+    }
+
+    #[test]
+    fn test_failed_exchange_withdraw_credits_nothing() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, Some(dex()));
+        contract.deposit_swap(0, 1, usdt(), usdc(), U128(400), U128(390));
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        let credited = contract.on_swap_withdraw(0, stranded(usdc(), 395), Err(PromiseError::Failed));
+        assert_eq!(credited.0, 0);
+        assert_eq!(deposit_of(&contract, &usdc()), 0);
+        assert_eq!(contract.get_stranded_swaps(0, accounts(0))[0].amount.0, 395);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_SWAP_PENDING")]
+    fn test_remove_stash_with_stranded_swap() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, Some(dex()));
+        contract.deposit_swap(0, 1, usdt(), usdc(), U128(400), U128(390));
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        contract.on_swap_withdraw(0, stranded(usdc(), 395), Err(PromiseError::Failed));
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.remove_stash(0);
+    }
+
+    #[test]
+    fn test_retry_swap_withdraw() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, Some(dex()));
+        contract.deposit_swap(0, 1, usdt(), usdc(), U128(400), U128(390));
+        testing_env!(context.build());
+        contract.deposit_swap(0, 1, usdt(), usdc(), U128(100), U128(90));
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        contract.on_swap_withdraw(0, stranded(usdc(), 395), Err(PromiseError::Failed));
+        contract.on_swap_withdraw(0, stranded(usdc(), 95), Err(PromiseError::Failed));
+        let stranded_swaps = contract.get_stranded_swaps(0, accounts(0));
+        assert_eq!(stranded_swaps.len(), 1);
+        assert_eq!(stranded_swaps[0].amount.0, 490);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(1)).build());
+        contract.retry_swap_withdraw(0, usdc());
+        assert!(contract.get_stranded_swaps(0, accounts(0)).is_empty());
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        contract.on_swap_withdraw(0, stranded(usdc(), 490), Ok(()));
+        assert_eq!(deposit_of(&contract, &usdc()), 490);
+        // both swaps are done
+        withdraw_deposits(&mut context, &mut contract);
+        contract.remove_stash(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_STRANDED_SWAP")]
+    fn test_retry_swap_withdraw_without_stranded_swap() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, Some(dex()));
+        contract.retry_swap_withdraw(0, usdc());
+    }
+}
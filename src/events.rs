@@ -0,0 +1,167 @@
+use near_sdk::json_types::{Base58CryptoHash, U128, U64};
+use near_sdk::{near, AccountId};
+
+use crate::config::MethodClass;
+use crate::goal::Goal;
+use crate::lock::{PenaltyReceiver, StashLock};
+use crate::vesting::VestingGrant;
+use crate::proposal::{ProposalKind, ProposalStatus, Vote};
+use crate::stash::Role;
+use crate::token_vault::{FeeKind, VaultFees};
+
+/// NEP-297 events of the contract, logged as `EVENT_JSON:{"standard":"divvy",...}`.
+#[near(event_json(standard = "divvy"))]
+#[cfg_attr(test, derive(near_sdk::serde::Deserialize, Debug, PartialEq))]
+pub enum DivvyEvent {
+    #[event_version("1.0.0")]
+    StashCreated { stash_id: u64, owner_id: AccountId, name: String },
+    #[event_version("1.0.0")]
+    StashRemoved { stash_id: u64, owner_id: AccountId },
+    /// Deposits were frozen and the members' shares are being redeemed.
+    #[event_version("1.0.0")]
+    DissolutionStarted { stash_id: u64 },
+    /// All members' shares were redeemed, the stash only allows withdrawals.
+    #[event_version("1.0.0")]
+    StashDissolved { stash_id: u64 },
+    #[event_version("1.0.0")]
+    VaultAdded { stash_id: u64, token_id: AccountId },
+    /// Tokens received into a member's deposits.
+    #[event_version("1.0.0")]
+    Deposit { stash_id: u64, account_id: AccountId, token_id: AccountId, amount: U128 },
+    /// Tokens sent out of a member's deposits, emitted once the transfer succeeded.
+    #[event_version("1.0.0")]
+    Withdraw { stash_id: u64, account_id: AccountId, token_id: AccountId, amount: U128 },
+    #[event_version("1.0.0")]
+    VaultFeesUpdated { stash_id: u64, token_id: AccountId, fees: VaultFees },
+    /// Fee shares paid to `receiver_id`, minted for management and deposit fees, taken from
+    /// the redeemed shares for withdrawal fees.
+    #[event_version("1.0.0")]
+    FeeCharged { stash_id: u64, token_id: AccountId, kind: FeeKind, receiver_id: AccountId, shares: U128 },
+    #[event_version("1.0.0")]
+    LiquidityAdded { stash_id: u64, account_id: AccountId, token_id: AccountId, amount: U128, shares: U128 },
+    #[event_version("1.0.0")]
+    LiquidityRemoved { stash_id: u64, account_id: AccountId, token_id: AccountId, amount: U128, shares: U128 },
+    /// A member had their role changed.
+    #[event_version("1.0.0")]
+    ContributorAuthorized { stash_id: u64, account_id: AccountId, role: Role },
+    /// `account_id` was invited to join as `role` until `expires_at`, if set.
+    #[event_version("1.0.0")]
+    MemberInvited { stash_id: u64, account_id: AccountId, inviter_id: AccountId, role: Role, expires_at: Option<U64> },
+    /// An invitation was accepted.
+    #[event_version("1.0.0")]
+    MemberJoined { stash_id: u64, account_id: AccountId, role: Role },
+    #[event_version("1.0.0")]
+    InviteDeclined { stash_id: u64, account_id: AccountId },
+    #[event_version("1.0.0")]
+    InviteRevoked { stash_id: u64, account_id: AccountId },
+    /// An invite code for up to `max_uses` contributors was registered.
+    #[event_version("1.0.0")]
+    InviteCodeCreated {
+        stash_id: u64,
+        code_hash: Base58CryptoHash,
+        creator_id: AccountId,
+        max_uses: u32,
+        expires_at: Option<U64>,
+    },
+    /// `account_id` joined as a contributor with the secret of the code.
+    #[event_version("1.0.0")]
+    InviteCodeRedeemed { stash_id: u64, code_hash: Base58CryptoHash, account_id: AccountId },
+    #[event_version("1.0.0")]
+    InviteCodeRevoked { stash_id: u64, code_hash: Base58CryptoHash },
+    #[event_version("1.0.0")]
+    RoleRevoked { stash_id: u64, account_id: AccountId },
+    #[event_version("1.0.0")]
+    OwnershipTransferred { stash_id: u64, old_owner_id: AccountId, new_owner_id: AccountId },
+    #[event_version("1.0.0")]
+    MemberLeft { stash_id: u64, account_id: AccountId },
+    #[event_version("1.0.0")]
+    ProposalAdded { stash_id: u64, proposal_id: u64, proposer_id: AccountId, kind: ProposalKind, status: ProposalStatus },
+    #[event_version("1.0.0")]
+    ProposalVoted { stash_id: u64, proposal_id: u64, account_id: AccountId, vote: Vote, status: ProposalStatus },
+    /// The savings goal was set, or removed when `goal` is `None`.
+    #[event_version("1.0.0")]
+    GoalUpdated { stash_id: u64, goal: Option<Goal> },
+    /// Every vault holds the target of the goal.
+    #[event_version("1.0.0")]
+    GoalReached { stash_id: u64 },
+    /// The funds were locked until `lock.unlock_at`, or the lock was removed when `lock` is `None`.
+    #[event_version("1.0.0")]
+    StashLockUpdated { stash_id: u64, lock: Option<StashLock> },
+    /// Penalty withheld from a withdrawal before the stash unlocked, paid to `receiver`.
+    #[event_version("1.0.0")]
+    EarlyWithdrawalPenalty {
+        stash_id: u64,
+        account_id: AccountId,
+        token_id: AccountId,
+        amount: U128,
+        receiver: PenaltyReceiver,
+    },
+    /// Shares of the grantor now vest to `account_id`.
+    #[event_version("1.0.0")]
+    VestingGranted { stash_id: u64, token_id: AccountId, account_id: AccountId, grant: VestingGrant },
+    #[event_version("1.0.0")]
+    VestedSharesClaimed { stash_id: u64, token_id: AccountId, account_id: AccountId, shares: U128 },
+    /// The grant of `account_id` ended early, `returned` unvested shares went back to the grantor.
+    #[event_version("1.0.0")]
+    VestingRevoked { stash_id: u64, token_id: AccountId, account_id: AccountId, returned: U128 },
+    /// The owner paused `methods`, in all stashes when `stash_id` is `None`.
+    #[event_version("1.0.0")]
+    Paused { stash_id: Option<u64>, methods: Vec<MethodClass> },
+    #[event_version("1.0.0")]
+    Unpaused { stash_id: Option<u64>, methods: Vec<MethodClass> },
+    /// Swap on the exchange succeeded, `amount_out` is withdrawn into the member's deposits.
+    #[event_version("1.0.0")]
+    Swap {
+        stash_id: u64,
+        account_id: AccountId,
+        token_in: AccountId,
+        amount_in: U128,
+        token_out: AccountId,
+        amount_out: U128,
+    },
+}
+
+/// NEP-245 events of the vault share tokens, logged as `EVENT_JSON:{"standard":"nep245",...}`.
+/// Variants are named after the NEP-245 events, `mt_mint` and so on.
+#[allow(clippy::enum_variant_names)]
+#[near(event_json(standard = "nep245"))]
+#[cfg_attr(test, derive(near_sdk::serde::Deserialize, Debug, PartialEq))]
+pub enum MtEvent {
+    #[event_version("1.0.0")]
+    MtMint(Vec<MtMint>),
+    #[event_version("1.0.0")]
+    MtBurn(Vec<MtBurn>),
+    #[event_version("1.0.0")]
+    MtTransfer(Vec<MtTransfer>),
+}
+
+#[near(serializers = [json])]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct MtMint {
+    pub owner_id: AccountId,
+    pub token_ids: Vec<String>,
+    pub amounts: Vec<U128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+#[near(serializers = [json])]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct MtBurn {
+    pub owner_id: AccountId,
+    pub token_ids: Vec<String>,
+    pub amounts: Vec<U128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+#[near(serializers = [json])]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct MtTransfer {
+    pub old_owner_id: AccountId,
+    pub new_owner_id: AccountId,
+    pub token_ids: Vec<String>,
+    pub amounts: Vec<U128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
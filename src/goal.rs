@@ -0,0 +1,284 @@
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{env, near, AccountId};
+
+use crate::math::{mul_div, Rounding};
+use crate::{Contract, ContractExt};
+
+/// Savings goal of a stash, reached once every vault holds its target.
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Goal {
+    /// Pooled assets each token's vault should hold, see `TokenVault::get_total_assets`.
+    pub targets: Vec<GoalTarget>,
+    /// Block timestamp the goal should be reached by, in nanoseconds.
+    pub deadline: Option<U64>,
+    /// Refuse deposits and liquidity once the goal is reached.
+    #[serde(default)]
+    pub lock_on_reached: bool,
+}
+
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct GoalTarget {
+    pub token_id: AccountId,
+    pub amount: U128,
+}
+
+#[near(serializers = [json])]
+pub struct TargetProgress {
+    pub token_id: AccountId,
+    pub target: U128,
+    pub total_assets: U128,
+    /// Percent of the target pooled, capped at 100.
+    pub percent: u8,
+}
+
+#[near(serializers = [json])]
+pub struct GoalProgress {
+    pub goal: Goal,
+    pub targets: Vec<TargetProgress>,
+    /// Average of the targets' percents.
+    pub percent: u8,
+    /// Block timestamp the goal was first reached at, later withdrawals do not undo it.
+    pub reached_at: Option<U64>,
+    /// Whether the deadline passed before the goal was reached.
+    pub missed_deadline: bool,
+}
+
+impl Goal {
+    /// Panics unless every target is for a distinct token with a positive amount and the
+    /// deadline is still ahead.
+    pub fn assert_valid(&self) {
+        assert!(!self.targets.is_empty(), "ERR_NO_TARGETS");
+        for (index, target) in self.targets.iter().enumerate() {
+            assert!(target.amount.0 > 0, "ERR_ZERO_AMOUNT");
+            assert!(
+                self.targets[..index].iter().all(|other| other.token_id != target.token_id),
+                "ERR_DUPLICATE_TARGET"
+            );
+        }
+        if let Some(deadline) = self.deadline {
+            assert!(deadline.0 > env::block_timestamp(), "ERR_INVALID_DEADLINE");
+        }
+    }
+}
+
+/// Percent of `target` that `amount` makes up, capped at 100.
+pub(crate) fn percent_of(amount: u128, target: u128) -> u8 {
+    mul_div(amount.min(target), 100, target, Rounding::Down) as u8
+}
+
+#[near]
+impl Contract {
+    /// Sets the savings goal of the stash, or removes it with `None`, admins only.
+    /// A new goal starts unreached and is checked against the vaults right away.
+    pub fn set_stash_goal(&mut self, stash_id: u64, goal: Option<Goal>) {
+        let prev_storage = env::storage_usage();
+        let mut stash = self.internal_get_stash(stash_id);
+        stash.set_goal(goal);
+        self.internal_save_stash(stash_id, stash);
+        self.internal_check_storage(&env::predecessor_account_id(), prev_storage);
+    }
+
+    /// Progress of the stash's vaults towards its goal, `None` without a goal.
+    pub fn get_goal_progress(&self, stash_id: u64) -> Option<GoalProgress> {
+        self.internal_get_stash(stash_id).get_goal_progress()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, NearToken, PromiseOrValue};
+
+    use super::*;
+    use crate::events::DivvyEvent;
+    use crate::test_utils::{get_context, get_events, new_contract};
+
+    const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+    fn usdt() -> AccountId {
+        "usdt-token.near".parse().unwrap()
+    }
+
+    fn usdc() -> AccountId {
+        "usdc-token.near".parse().unwrap()
+    }
+
+    /// "Weekend getaway to Miami" with usdt and usdc vaults.
+    fn setup(context: &mut VMContextBuilder) -> Contract {
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = new_contract(None);
+        contract.storage_deposit(None, None);
+        contract.create_stash("Weekend getaway to Miami".to_string());
+        contract.add_token_to_stash(0, usdt());
+        contract.add_token_to_stash(0, usdc());
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract
+    }
+
+    fn goal(lock_on_reached: bool) -> Goal {
+        Goal {
+            targets: vec![
+                GoalTarget { token_id: usdt(), amount: U128(4_000) },
+                GoalTarget { token_id: usdc(), amount: U128(2_000) },
+            ],
+            deadline: Some(U64(30 * DAY)),
+            lock_on_reached,
+        }
+    }
+
+    /// Sends `amount` of the token into the vault as accounts(0), returns the refunded amount.
+    fn pool(context: &mut VMContextBuilder, contract: &mut Contract, token_id: AccountId, amount: u128) -> u128 {
+        testing_env!(context.predecessor_account_id(token_id).build());
+        let unused = contract.ft_on_transfer(
+            accounts(0),
+            U128(amount),
+            r#"{"stash_id": 0, "add_liquidity": true}"#.to_string(),
+        );
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        match unused {
+            PromiseOrValue::Value(unused) => unused.0,
+            PromiseOrValue::Promise(_) => panic!("expected a value"),
+        }
+    }
+
+    #[test]
+    fn test_percent_of() {
+        assert_eq!(percent_of(3_000, 4_000), 75);
+        assert_eq!(percent_of(5_000, 4_000), 100);
+        assert_eq!(percent_of(u128::MAX / 2, u128::MAX), 49);
+        assert_eq!(percent_of(u128::MAX, u128::MAX), 100);
+    }
+
+    #[test]
+    fn test_goal_progress() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        assert!(contract.get_goal_progress(0).is_none());
+        contract.set_stash_goal(0, Some(goal(false)));
+        pool(&mut context, &mut contract, usdt(), 3_000);
+        pool(&mut context, &mut contract, usdc(), 2_500);
+
+        let progress = contract.get_goal_progress(0).unwrap();
+        assert_eq!(progress.goal, goal(false));
+        let percents: Vec<_> = progress.targets.iter().map(|target| (target.total_assets.0, target.percent)).collect();
+        assert_eq!(percents, vec![(3_000, 75), (2_500, 100)]);
+        assert_eq!(progress.percent, 87);
+        assert_eq!(progress.reached_at, None);
+        assert!(!progress.missed_deadline);
+
+        testing_env!(context.block_timestamp(30 * DAY).build());
+        assert!(contract.get_goal_progress(0).unwrap().missed_deadline);
+    }
+
+    #[test]
+    fn test_goal_reached() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.set_stash_goal(0, Some(goal(false)));
+        pool(&mut context, &mut contract, usdc(), 2_000);
+        testing_env!(context.predecessor_account_id(usdt()).block_timestamp(DAY).build());
+        contract.ft_on_transfer(accounts(0), U128(4_000), r#"{"stash_id": 0, "add_liquidity": true}"#.to_string());
+        assert!(get_events().contains(&DivvyEvent::GoalReached { stash_id: 0 }));
+
+        let progress = contract.get_goal_progress(0).unwrap();
+        assert_eq!(progress.percent, 100);
+        assert_eq!(progress.reached_at, Some(U64(DAY)));
+
+        // more deposits are still taken without a lock
+        assert_eq!(pool(&mut context, &mut contract, usdt(), 1_000), 0);
+    }
+
+    #[test]
+    fn test_reached_goal_locks_deposits() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.set_stash_goal(0, Some(goal(true)));
+        pool(&mut context, &mut contract, usdt(), 4_000);
+        pool(&mut context, &mut contract, usdc(), 2_000);
+        assert!(contract.get_goal_progress(0).unwrap().reached_at.is_some());
+
+        assert_eq!(pool(&mut context, &mut contract, usdt(), 1_000), 1_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_GOAL_REACHED")]
+    fn test_reached_goal_locks_liquidity() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        testing_env!(context.predecessor_account_id(usdt()).build());
+        contract.ft_on_transfer(accounts(0), U128(1_000), r#"{"stash_id": 0}"#.to_string());
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.set_stash_goal(
+            0,
+            Some(Goal { targets: vec![GoalTarget { token_id: usdc(), amount: U128(1_000) }], deadline: None, lock_on_reached: true }),
+        );
+        pool(&mut context, &mut contract, usdc(), 1_000);
+        contract.add_liquidity_to_stash(0, usdt(), 1_000);
+    }
+
+    #[test]
+    fn test_goal_reached_when_set() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        pool(&mut context, &mut contract, usdt(), 5_000);
+        pool(&mut context, &mut contract, usdc(), 2_000);
+        contract.set_stash_goal(0, Some(goal(false)));
+        assert_eq!(
+            get_events(),
+            vec![
+                DivvyEvent::GoalUpdated { stash_id: 0, goal: Some(goal(false)) },
+                DivvyEvent::GoalReached { stash_id: 0 },
+            ]
+        );
+
+        contract.set_stash_goal(0, None);
+        assert!(contract.get_goal_progress(0).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_VAULT")]
+    fn test_goal_needs_vault() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.set_stash_goal(
+            0,
+            Some(Goal {
+                targets: vec![GoalTarget { token_id: "eth-token.near".parse().unwrap(), amount: U128(1) }],
+                deadline: None,
+                lock_on_reached: false,
+            }),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_DUPLICATE_TARGET")]
+    fn test_goal_duplicate_target() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        let mut goal = goal(false);
+        goal.targets[1].token_id = usdt();
+        contract.set_stash_goal(0, Some(goal));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_DEADLINE")]
+    fn test_goal_past_deadline() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        testing_env!(context.block_timestamp(30 * DAY).build());
+        contract.set_stash_goal(0, Some(goal(false)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Caller is not authorized")]
+    fn test_goal_admins_only() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.set_stash_goal(0, Some(goal(false)));
+    }
+}
@@ -0,0 +1,595 @@
+use near_sdk::collections::UnorderedMap;
+use near_sdk::json_types::{Base58CryptoHash, U64};
+use near_sdk::{env, near, AccountId};
+
+use crate::events::DivvyEvent;
+use crate::stash::{Role, Stash, StashStatus};
+use crate::{Contract, ContractExt, StorageKey};
+
+/// Invitation to join a stash, pending until the invitee accepts or declines it.
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Invite {
+    pub role: Role,
+    pub inviter_id: AccountId,
+    /// Block timestamp after which the invitation can no longer be accepted, in nanoseconds.
+    pub expires_at: Option<U64>,
+}
+
+impl Invite {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| env::block_timestamp() >= expires_at.0)
+    }
+}
+
+/// Invite link of a stash, redeemed by presenting the secret whose sha256 is its hash.
+/// Anyone redeeming it joins as a contributor.
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct InviteCode {
+    pub creator_id: AccountId,
+    /// Redemptions left, the code is removed after the last one.
+    pub uses_left: u32,
+    /// Block timestamp after which the code can no longer be redeemed, in nanoseconds.
+    pub expires_at: Option<U64>,
+}
+
+impl InviteCode {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| env::block_timestamp() >= expires_at.0)
+    }
+}
+
+#[near(serializers = [json])]
+pub struct InviteCodeView {
+    pub code_hash: Base58CryptoHash,
+    pub creator_id: AccountId,
+    pub uses_left: u32,
+    pub expires_at: Option<U64>,
+}
+
+#[near(serializers = [json])]
+pub struct PendingInvite {
+    pub stash_id: u64,
+    pub stash_name: String,
+    pub role: Role,
+    pub inviter_id: AccountId,
+    pub expires_at: Option<U64>,
+}
+
+#[near]
+impl Contract {
+    /// Invites `account_id` to join the stash as `role`, see `Stash::assert_can_manage` for
+    /// who may invite whom. The account only becomes a member once it calls `accept_invite`.
+    /// The inviter pays for the storage of the invitation until it is answered or revoked.
+    pub fn invite_member(&mut self, stash_id: u64, account_id: AccountId, role: Role, expires_at: Option<U64>) {
+        let prev_storage = env::storage_usage();
+        let inviter_id = env::predecessor_account_id();
+        let stash = self.internal_get_stash(stash_id);
+        stash.assert_can_manage(role);
+        assert!(stash.get_role(&account_id).is_none(), "ERR_ALREADY_MEMBER");
+        if let Some(expires_at) = expires_at {
+            assert!(expires_at.0 > env::block_timestamp(), "ERR_INVALID_EXPIRY");
+        }
+
+        let mut invites = self
+            .invites
+            .get(&account_id)
+            .unwrap_or_else(|| UnorderedMap::new(StorageKey::AccountInvites { account_id: account_id.clone() }));
+        assert!(invites.get(&stash_id).is_none(), "ERR_ALREADY_INVITED");
+        invites.insert(&stash_id, &Invite { role, inviter_id: inviter_id.clone(), expires_at });
+        self.invites.insert(&account_id, &invites);
+        DivvyEvent::MemberInvited { stash_id, account_id, inviter_id: inviter_id.clone(), role, expires_at }.emit();
+        self.internal_check_storage(&inviter_id, prev_storage);
+    }
+
+    /// Joins the stash with the role of the caller's invitation. The caller pays for the
+    /// storage of its membership.
+    pub fn accept_invite(&mut self, stash_id: u64) {
+        let account_id = env::predecessor_account_id();
+        let invite = self.internal_take_invite(&account_id, stash_id);
+        assert!(!invite.is_expired(), "ERR_INVITE_EXPIRED");
+
+        let prev_storage = env::storage_usage();
+        let mut stash = self.internal_get_stash(stash_id);
+        stash.add_member(account_id.clone(), invite.role);
+        self.assert_member_limit(&stash);
+        self.internal_save_stash(stash_id, stash);
+        self.internal_add_account_stash(&account_id, stash_id);
+        self.internal_check_storage(&account_id, prev_storage);
+    }
+
+    /// Turns down the caller's invitation, also clears expired ones and those of removed stashes.
+    pub fn decline_invite(&mut self, stash_id: u64) {
+        let account_id = env::predecessor_account_id();
+        self.internal_take_invite(&account_id, stash_id);
+        DivvyEvent::InviteDeclined { stash_id, account_id }.emit();
+    }
+
+    /// Withdraws a pending invitation, by anyone who could have sent it.
+    pub fn revoke_invite(&mut self, stash_id: u64, account_id: AccountId) {
+        let invite = self.internal_get_invite(&account_id, stash_id);
+        self.internal_get_stash(stash_id).assert_can_manage(invite.role);
+        self.internal_take_invite(&account_id, stash_id);
+        DivvyEvent::InviteRevoked { stash_id, account_id }.emit();
+    }
+
+    /// Registers an invite code for the stash, `code_hash` is the sha256 of the secret shared
+    /// with the invitees. Usable once unless `max_uses` says otherwise. Needs the rights to
+    /// invite contributors, the creator pays for its storage until it is used up or revoked.
+    pub fn create_invite_code(
+        &mut self,
+        stash_id: u64,
+        code_hash: Base58CryptoHash,
+        max_uses: Option<u32>,
+        expires_at: Option<U64>,
+    ) {
+        let prev_storage = env::storage_usage();
+        let creator_id = env::predecessor_account_id();
+        self.internal_get_stash(stash_id).assert_can_manage(Role::Contributor);
+        let max_uses = max_uses.unwrap_or(1);
+        assert!(max_uses > 0, "ERR_ZERO_USES");
+        if let Some(expires_at) = expires_at {
+            assert!(expires_at.0 > env::block_timestamp(), "ERR_INVALID_EXPIRY");
+        }
+
+        let mut codes = self
+            .invite_codes
+            .get(&stash_id)
+            .unwrap_or_else(|| UnorderedMap::new(StorageKey::StashInviteCodes { stash_id }));
+        assert!(codes.get(&code_hash).is_none(), "ERR_CODE_EXISTS");
+        codes.insert(&code_hash, &InviteCode { creator_id: creator_id.clone(), uses_left: max_uses, expires_at });
+        self.invite_codes.insert(&stash_id, &codes);
+        DivvyEvent::InviteCodeCreated { stash_id, code_hash, creator_id: creator_id.clone(), max_uses, expires_at }.emit();
+        self.internal_check_storage(&creator_id, prev_storage);
+    }
+
+    /// Joins the stash as a contributor with the secret of one of its invite codes. The caller
+    /// pays for the storage of its membership.
+    pub fn redeem_invite_code(&mut self, stash_id: u64, secret: String) {
+        let account_id = env::predecessor_account_id();
+        let code_hash = Base58CryptoHash::from(env::sha256_array(secret.as_bytes()));
+        let code = self.internal_use_invite_code(stash_id, &code_hash);
+        assert!(!code.is_expired(), "ERR_INVITE_EXPIRED");
+
+        let prev_storage = env::storage_usage();
+        let mut stash = self.internal_get_stash(stash_id);
+        stash.add_member(account_id.clone(), Role::Contributor);
+        self.assert_member_limit(&stash);
+        self.internal_save_stash(stash_id, stash);
+        self.internal_add_account_stash(&account_id, stash_id);
+        DivvyEvent::InviteCodeRedeemed { stash_id, code_hash, account_id: account_id.clone() }.emit();
+        self.internal_check_storage(&account_id, prev_storage);
+    }
+
+    /// Removes an invite code before it is used up, by anyone who could have created it.
+    pub fn revoke_invite_code(&mut self, stash_id: u64, code_hash: Base58CryptoHash) {
+        self.internal_get_stash(stash_id).assert_can_manage(Role::Contributor);
+        let prev_storage = env::storage_usage();
+        let mut codes = self.invite_codes.get(&stash_id).expect("ERR_NO_INVITE_CODE");
+        let code = codes.remove(&code_hash).expect("ERR_NO_INVITE_CODE");
+        self.internal_save_invite_codes(stash_id, codes);
+        self.internal_record_storage(&code.creator_id, prev_storage);
+        DivvyEvent::InviteCodeRevoked { stash_id, code_hash }.emit();
+    }
+
+    /// Invite codes of the stash which are not used up, including expired ones.
+    pub fn get_invite_codes(&self, stash_id: u64) -> Vec<InviteCodeView> {
+        self.invite_codes
+            .get(&stash_id)
+            .map(|codes| {
+                codes
+                    .iter()
+                    .map(|(code_hash, code)| InviteCodeView {
+                        code_hash,
+                        creator_id: code.creator_id,
+                        uses_left: code.uses_left,
+                        expires_at: code.expires_at,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Invitations the account can still accept, skipping expired ones and those of stashes
+    /// which were closed or removed.
+    pub fn get_pending_invites(&self, account_id: AccountId) -> Vec<PendingInvite> {
+        let Some(invites) = self.invites.get(&account_id) else {
+            return vec![];
+        };
+        invites
+            .iter()
+            .filter(|(_, invite)| !invite.is_expired())
+            .filter_map(|(stash_id, invite)| {
+                let stash = self.stashes.get(&stash_id).map(Stash::from)?;
+                (stash.get_status() == StashStatus::Open).then(|| PendingInvite {
+                    stash_id,
+                    stash_name: stash.get_name(),
+                    role: invite.role,
+                    inviter_id: invite.inviter_id,
+                    expires_at: invite.expires_at,
+                })
+            })
+            .collect()
+    }
+}
+
+// internal methods
+impl Contract {
+    fn internal_get_invite(&self, account_id: &AccountId, stash_id: u64) -> Invite {
+        self.invites.get(account_id).and_then(|invites| invites.get(&stash_id)).expect("ERR_NO_INVITE")
+    }
+
+    /// Removes the invitation, releasing its storage back to the inviter.
+    fn internal_take_invite(&mut self, account_id: &AccountId, stash_id: u64) -> Invite {
+        let prev_storage = env::storage_usage();
+        let mut invites = self.invites.get(account_id).expect("ERR_NO_INVITE");
+        let invite = invites.remove(&stash_id).expect("ERR_NO_INVITE");
+        if invites.is_empty() {
+            self.invites.remove(account_id);
+        } else {
+            self.invites.insert(account_id, &invites);
+        }
+        self.internal_record_storage(&invite.inviter_id, prev_storage);
+        invite
+    }
+
+    /// Counts one redemption of the code, removing it after the last one and releasing its
+    /// storage back to the creator. Returns the code as it was before.
+    fn internal_use_invite_code(&mut self, stash_id: u64, code_hash: &Base58CryptoHash) -> InviteCode {
+        let prev_storage = env::storage_usage();
+        let mut codes = self.invite_codes.get(&stash_id).expect("ERR_NO_INVITE_CODE");
+        let code = codes.get(code_hash).expect("ERR_NO_INVITE_CODE");
+        if code.uses_left > 1 {
+            codes.insert(code_hash, &InviteCode { uses_left: code.uses_left - 1, ..code.clone() });
+        } else {
+            codes.remove(code_hash);
+        }
+        self.internal_save_invite_codes(stash_id, codes);
+        self.internal_record_storage(&code.creator_id, prev_storage);
+        code
+    }
+
+    fn internal_save_invite_codes(&mut self, stash_id: u64, codes: UnorderedMap<Base58CryptoHash, InviteCode>) {
+        if codes.is_empty() {
+            self.invite_codes.remove(&stash_id);
+        } else {
+            self.invite_codes.insert(&stash_id, &codes);
+        }
+    }
+
+    /// Removes the invite codes of a removed stash, releasing their storage to the creators.
+    pub(crate) fn internal_clear_invite_codes(&mut self, stash_id: u64) {
+        let Some(mut codes) = self.invite_codes.remove(&stash_id) else {
+            return;
+        };
+        for (code_hash, code) in codes.to_vec() {
+            let prev_storage = env::storage_usage();
+            codes.remove(&code_hash);
+            self.internal_record_storage(&code.creator_id, prev_storage);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, NearToken};
+
+    use super::*;
+    use crate::test_utils::{get_context, get_events, new_contract};
+
+    const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+    /// Stash 0 owned by accounts(0), accounts(1) and accounts(2) are registered for storage.
+    fn setup(context: &mut VMContextBuilder) -> Contract {
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = new_contract(None);
+        contract.storage_deposit(None, None);
+        contract.storage_deposit(Some(accounts(1)), None);
+        contract.storage_deposit(Some(accounts(2)), None);
+        contract.create_stash("Roommates".to_string());
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract
+    }
+
+    fn code_hash(secret: &str) -> Base58CryptoHash {
+        env::sha256_array(secret.as_bytes()).into()
+    }
+
+    fn storage_used(contract: &Contract, account_id: &AccountId) -> u64 {
+        contract.storage_accounts.get(account_id).unwrap().used
+    }
+
+    #[test]
+    fn test_accept_invite() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        let used = storage_used(&contract, &accounts(0));
+        contract.invite_member(0, accounts(1), Role::Contributor, None);
+        assert!(storage_used(&contract, &accounts(0)) > used);
+        // nothing changes before the invitee accepts
+        assert_eq!(contract.internal_get_stash(0).get_role(&accounts(1)), None);
+        assert!(contract.get_stashes_for_account(accounts(1)).is_empty());
+        let pending = contract.get_pending_invites(accounts(1));
+        assert_eq!(pending.len(), 1);
+        assert_eq!((pending[0].stash_id, pending[0].stash_name.as_str()), (0, "Roommates"));
+        assert_eq!((pending[0].role, &pending[0].inviter_id), (Role::Contributor, &accounts(0)));
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.accept_invite(0);
+        assert_eq!(contract.internal_get_stash(0).get_role(&accounts(1)), Some(Role::Contributor));
+        assert_eq!(contract.get_stashes_for_account(accounts(1)), vec![0]);
+        assert!(contract.get_pending_invites(accounts(1)).is_empty());
+        // the invitation's storage went back to the inviter
+        assert_eq!(storage_used(&contract, &accounts(0)), used);
+        assert_eq!(
+            get_events(),
+            vec![DivvyEvent::MemberJoined { stash_id: 0, account_id: accounts(1), role: Role::Contributor }]
+        );
+    }
+
+    #[test]
+    fn test_invite_emits_event() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.invite_member(0, accounts(1), Role::Viewer, Some(U64(DAY)));
+        assert_eq!(
+            get_events(),
+            vec![DivvyEvent::MemberInvited {
+                stash_id: 0,
+                account_id: accounts(1),
+                inviter_id: accounts(0),
+                role: Role::Viewer,
+                expires_at: Some(U64(DAY)),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_decline_invite() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.invite_member(0, accounts(1), Role::Contributor, None);
+        contract.invite_member(0, accounts(2), Role::Contributor, None);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.decline_invite(0);
+        assert_eq!(contract.internal_get_stash(0).get_role(&accounts(1)), None);
+        assert!(contract.get_pending_invites(accounts(1)).is_empty());
+        assert_eq!(contract.get_pending_invites(accounts(2)).len(), 1);
+        assert_eq!(get_events(), vec![DivvyEvent::InviteDeclined { stash_id: 0, account_id: accounts(1) }]);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_INVITE")]
+    fn test_accept_declined_invite() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.invite_member(0, accounts(1), Role::Contributor, None);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.decline_invite(0);
+        contract.accept_invite(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVITE_EXPIRED")]
+    fn test_accept_expired_invite() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.invite_member(0, accounts(1), Role::Contributor, Some(U64(DAY)));
+        testing_env!(context.predecessor_account_id(accounts(1)).block_timestamp(DAY).build());
+        assert!(contract.get_pending_invites(accounts(1)).is_empty());
+        contract.accept_invite(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_EXPIRY")]
+    fn test_invite_already_expired() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        testing_env!(context.block_timestamp(DAY).build());
+        contract.invite_member(0, accounts(1), Role::Contributor, Some(U64(DAY)));
+    }
+
+    #[test]
+    fn test_revoke_invite() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.invite_member(0, accounts(1), Role::Contributor, None);
+        contract.revoke_invite(0, accounts(1));
+        assert!(contract.get_pending_invites(accounts(1)).is_empty());
+        assert_eq!(get_events().last(), Some(&DivvyEvent::InviteRevoked { stash_id: 0, account_id: accounts(1) }));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ROLE_NOT_ALLOWED")]
+    fn test_admin_cannot_revoke_admin_invite() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.invite_member(0, accounts(1), Role::Admin, None);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.accept_invite(0);
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.invite_member(0, accounts(2), Role::Admin, None);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.revoke_invite(0, accounts(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Caller is not authorized")]
+    fn test_invite_admins_only() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.invite_member(0, accounts(2), Role::Viewer, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ALREADY_MEMBER")]
+    fn test_invite_member() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.invite_member(0, accounts(0), Role::Viewer, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ALREADY_INVITED")]
+    fn test_invite_twice() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.invite_member(0, accounts(1), Role::Viewer, None);
+        contract.invite_member(0, accounts(1), Role::Contributor, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_STORAGE_NOT_REGISTERED")]
+    fn test_accept_needs_storage() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.invite_member(0, accounts(3), Role::Viewer, None);
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.accept_invite(0);
+    }
+
+    #[test]
+    fn test_invites_of_removed_stash_can_be_declined() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.invite_member(0, accounts(1), Role::Viewer, None);
+        contract.remove_stash(0);
+        assert!(contract.get_pending_invites(accounts(1)).is_empty());
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.decline_invite(0);
+        assert!(contract.invites.get(&accounts(1)).is_none());
+    }
+
+    #[test]
+    fn test_redeem_invite_code() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        let used = storage_used(&contract, &accounts(0));
+        contract.create_invite_code(0, code_hash("beach-house"), None, None);
+        assert!(storage_used(&contract, &accounts(0)) > used);
+        let codes = contract.get_invite_codes(0);
+        assert_eq!(codes.len(), 1);
+        assert_eq!((codes[0].code_hash, codes[0].uses_left), (code_hash("beach-house"), 1));
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.redeem_invite_code(0, "beach-house".to_string());
+        assert_eq!(contract.internal_get_stash(0).get_role(&accounts(1)), Some(Role::Contributor));
+        assert_eq!(contract.get_stashes_for_account(accounts(1)), vec![0]);
+        assert_eq!(
+            get_events(),
+            vec![
+                DivvyEvent::MemberJoined { stash_id: 0, account_id: accounts(1), role: Role::Contributor },
+                DivvyEvent::InviteCodeRedeemed { stash_id: 0, code_hash: code_hash("beach-house"), account_id: accounts(1) },
+            ]
+        );
+        // single use, its storage went back to the creator
+        assert!(contract.get_invite_codes(0).is_empty());
+        assert_eq!(storage_used(&contract, &accounts(0)), used);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_INVITE_CODE")]
+    fn test_invite_code_single_use() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.create_invite_code(0, code_hash("beach-house"), None, None);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.redeem_invite_code(0, "beach-house".to_string());
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.redeem_invite_code(0, "beach-house".to_string());
+    }
+
+    #[test]
+    fn test_invite_code_max_uses() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.create_invite_code(0, code_hash("beach-house"), Some(2), None);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.redeem_invite_code(0, "beach-house".to_string());
+        assert_eq!(contract.get_invite_codes(0)[0].uses_left, 1);
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.redeem_invite_code(0, "beach-house".to_string());
+        assert_eq!(contract.internal_get_stash(0).get_member_count(), 3);
+        assert!(contract.get_invite_codes(0).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_INVITE_CODE")]
+    fn test_redeem_wrong_secret() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.create_invite_code(0, code_hash("beach-house"), None, None);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.redeem_invite_code(0, "mountain-cabin".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVITE_EXPIRED")]
+    fn test_redeem_expired_invite_code() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.create_invite_code(0, code_hash("beach-house"), None, Some(U64(DAY)));
+        testing_env!(context.predecessor_account_id(accounts(1)).block_timestamp(DAY).build());
+        contract.redeem_invite_code(0, "beach-house".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ALREADY_MEMBER")]
+    fn test_member_cannot_redeem() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.create_invite_code(0, code_hash("beach-house"), None, None);
+        contract.redeem_invite_code(0, "beach-house".to_string());
+    }
+
+    #[test]
+    fn test_revoke_invite_code() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        let used = storage_used(&contract, &accounts(0));
+        contract.create_invite_code(0, code_hash("beach-house"), Some(5), None);
+        contract.revoke_invite_code(0, code_hash("beach-house"));
+        assert!(contract.get_invite_codes(0).is_empty());
+        assert_eq!(storage_used(&contract, &accounts(0)), used);
+        assert_eq!(
+            get_events().last(),
+            Some(&DivvyEvent::InviteCodeRevoked { stash_id: 0, code_hash: code_hash("beach-house") })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Caller is not authorized")]
+    fn test_create_invite_code_admins_only() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.create_invite_code(0, code_hash("beach-house"), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_CODE_EXISTS")]
+    fn test_create_invite_code_twice() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.create_invite_code(0, code_hash("beach-house"), None, None);
+        contract.create_invite_code(0, code_hash("beach-house"), Some(3), None);
+    }
+
+    #[test]
+    fn test_remove_stash_clears_invite_codes() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        let used = storage_used(&contract, &accounts(0));
+        contract.create_invite_code(0, code_hash("beach-house"), None, None);
+        contract.remove_stash(0);
+        assert!(contract.invite_codes.get(&0).is_none());
+        assert!(storage_used(&contract, &accounts(0)) < used);
+    }
+}
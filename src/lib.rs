@@ -1,130 +1,448 @@
 use near_contract_standards::fungible_token::Balance;
-use near_sdk::collections::{UnorderedMap, UnorderedSet};
-use near_sdk::{env, log, near, AccountId, NearToken, PanicOnDefault, Promise, StorageUsage};
-use stash::Stash;
+use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::collections::{LookupMap, UnorderedMap, UnorderedSet};
+use near_sdk::json_types::{Base58CryptoHash, U128};
+use near_sdk::{env, log, near, AccountId, BorshStorageKey, Gas, NearToken, PanicOnDefault, Promise, PromiseError, PromiseOrValue, StorageUsage};
+use config::{Config, MethodClass, PauseFlags};
+use events::DivvyEvent;
+use invites::{Invite, InviteCode};
+use stash::{Role, Stash, StashStatus, VersionedStash};
+use storage::StorageAccount;
+use token_vault::VaultFees;
+use tokens::TokenMetadata;
 
 
+mod config;
+mod dex;
+mod events;
+mod goal;
+mod invites;
+mod lock;
+mod math;
+mod multi_token;
+mod proposal;
 mod token_vault;
+mod views;
 mod stash;
+mod storage;
+#[cfg(test)]
+mod test_utils;
+mod tokens;
+mod upgrade;
+mod vesting;
+mod wrap_near;
+
+const GAS_FOR_FT_TRANSFER: Gas = Gas::from_tgas(10);
+const GAS_FOR_ON_WITHDRAW_COMPLETE: Gas = Gas::from_tgas(10);
+/// Members processed by a `dissolve_stash` call without a limit.
+const DEFAULT_DISSOLVE_BATCH: u64 = 20;
+
+/// Prefixes of all persistent collections. Nested collections derive theirs from the ids of the
+/// stash, vault or account owning them so they never overlap.
+#[near(serializers = [borsh])]
+#[derive(BorshStorageKey)]
+pub(crate) enum StorageKey {
+  Stashes,
+  Accounts,
+  AccountStashes { account_id: AccountId },
+  StorageAccounts,
+  SupportedTokens,
+  Members { stash_id: u64 },
+  Vaults { stash_id: u64 },
+  Shares { stash_id: u64, token_id: AccountId },
+  Deposits { stash_id: u64 },
+  AccountDeposits { stash_id: u64, account_id: AccountId },
+  Proposals { stash_id: u64 },
+  Invites,
+  AccountInvites { account_id: AccountId },
+  InviteCodes,
+  StashInviteCodes { stash_id: u64 },
+  Grants { stash_id: u64, token_id: AccountId },
+}
 
 #[near(contract_state)]
 #[derive(PanicOnDefault)]
 pub struct Contract {
-  stashes: UnorderedMap<u64, Stash>,
+  owner_id: AccountId,
+  stashes: UnorderedMap<u64, VersionedStash>,
   accounts: UnorderedMap<AccountId, UnorderedSet<u64>>,
+  /// NEP-145 storage balances of registered accounts.
+  storage_accounts: LookupMap<AccountId, StorageAccount>,
+  /// Bytes needed to register an account.
+  account_storage_usage: StorageUsage,
+  /// Allowlisted token contracts, with their metadata once fetched.
+  supported_tokens: UnorderedMap<AccountId, Option<TokenMetadata>>,
+  config: Config,
+  /// Method classes paused for all stashes, see `pause`.
+  paused: PauseFlags,
+  /// Id of the next stash, ids of removed stashes are never reused.
+  next_stash_id: u64,
+  /// Pending invitations of each account, by stash.
+  invites: LookupMap<AccountId, UnorderedMap<u64, Invite>>,
+  /// Invite codes of each stash, by the hash of their secret.
+  invite_codes: LookupMap<u64, UnorderedMap<Base58CryptoHash, InviteCode>>,
 }
 
-
 #[near]
 impl Contract {
 
+  /// Seeds the token allowlist with `supported_tokens` and fetches their metadata.
   #[init]
-  pub fn new() -> Self {
+  pub fn new(owner_id: AccountId, supported_tokens: Vec<AccountId>, wrap_near_id: Option<AccountId>, dex_id: Option<AccountId>) -> Self {
     assert!(!env::state_exists(), "ERR_CONTRACT_IS_INITIALIZED");
-    Self {
-      stashes: UnorderedMap::new(b"s".to_vec()),
-      accounts: UnorderedMap::new(b"a".to_vec()),
+    let mut this = Self {
+      owner_id,
+      stashes: UnorderedMap::new(StorageKey::Stashes),
+      accounts: UnorderedMap::new(StorageKey::Accounts),
+      storage_accounts: LookupMap::new(StorageKey::StorageAccounts),
+      account_storage_usage: 0,
+      supported_tokens: UnorderedMap::new(StorageKey::SupportedTokens),
+      config: Config {
+        wrap_near_id,
+        dex_id,
+        fee_receiver_id: None,
+        max_members_per_stash: None,
+        max_fees: VaultFees::default(),
+      },
+      paused: PauseFlags::default(),
+      next_stash_id: 0,
+      invites: LookupMap::new(StorageKey::Invites),
+      invite_codes: LookupMap::new(StorageKey::InviteCodes),
+    };
+    Self::internal_write_state_version();
+    this.internal_measure_account_storage_usage();
+    for token_id in supported_tokens {
+      this.supported_tokens.insert(&token_id, &None);
+      this.internal_fetch_token_metadata(token_id);
     }
+    this
   }
 
-  //TODO impolement deposit and withdraw payable methods
-  #[payable]
+  // storage is drawn from the caller's prepaid storage balance, see `storage_deposit`
   pub fn create_stash(&mut self, name: String) -> u64 {
     let prev_storage = env::storage_usage();
-    let stash_id = self.stashes.len();
-    self.stashes.insert(&stash_id, &Stash::new(stash_id, name));
+    let stash_id = self.next_stash_id;
+    self.next_stash_id += 1;
+    self.internal_save_stash(stash_id, Stash::new(stash_id, name.clone()));
+    self.internal_add_account_stash(&env::predecessor_account_id(), stash_id);
+    DivvyEvent::StashCreated { stash_id, owner_id: env::predecessor_account_id(), name }.emit();
 
-    let mut set: UnorderedSet<u64> = self.accounts.get(&env::predecessor_account_id()).unwrap_or_else(|| UnorderedSet::new(b"s".to_vec()));
-    set.insert(&stash_id);
-    self.accounts.insert(&env::predecessor_account_id(), &set);
-
-    self.internal_check_storage(prev_storage);
+    self.internal_check_storage(&env::predecessor_account_id(), prev_storage);
     stash_id
   }
 
-  // add tokenVault into a stash
-  #[payable]
+  // add tokenVault into a stash, admins only
   pub fn add_token_to_stash(&mut self, stash_id: u64, token_id: AccountId) {
     let prev_storage = env::storage_usage();
-    let mut stash = self.stashes.get(&stash_id).expect("ERR_STASH_NOT_FOUND");
+    assert!(self.is_supported_token(&token_id), "Token is not on the allowed list");
+    let mut stash = self.internal_get_stash(stash_id);
     stash.add_vault(token_id);
-    self.internal_check_storage(prev_storage);
-  }
-
-  // TODO swaps given amount_in of token_in into token_out
-  pub fn deposit_swap(&mut self, _stash_id:u64, _token_in: AccountId, _token_out: AccountId, _amount_in: Balance, _min_amount_out: Balance) {
-
-    // how to swap this via an agent and update stash.deposits
+    self.internal_save_stash(stash_id, stash);
+    self.internal_check_storage(&env::predecessor_account_id(), prev_storage);
   }
 
   // add liquidity to a given stash
-  #[payable]
   pub fn add_liquidity_to_stash(&mut self, stash_id: u64, token_id: AccountId, amount: Balance) {
     let prev_storage = env::storage_usage();
-    let mut stash = self.stashes.get(&stash_id).expect("ERR_STASH_NOT_FOUND");
+    let mut stash = self.internal_get_stash(stash_id);
+    self.assert_not_paused(&stash, MethodClass::Deposits);
     stash.add_liquidity(token_id, amount);
-    self.internal_check_storage(prev_storage);
+    self.internal_save_stash(stash_id, stash);
+    self.internal_check_storage(&env::predecessor_account_id(), prev_storage);
   }
 
   // remove liquidity from a given stash
-  #[payable]
   pub fn remove_liquidity_from_stash(&mut self, stash_id: u64, token_id: AccountId, amount: Balance) {
     let prev_storage = env::storage_usage();
-    let mut stash = self.stashes.get(&stash_id).expect("ERR_STASH_NOT_FOUND");
+    let mut stash = self.internal_get_stash(stash_id);
+    self.assert_not_paused(&stash, MethodClass::Withdrawals);
     stash.remove_liquidity(token_id, amount);
-    self.internal_check_storage(prev_storage);
+    self.internal_save_stash(stash_id, stash);
+    self.internal_check_storage(&env::predecessor_account_id(), prev_storage);
   }
 
-  // authorize additional stash contributor
-  #[payable]
+  // invite an account to contribute to the stash, admins only, see `invite_member`
   pub fn authorize_contributor(&mut self, stash_id: u64, account_id: AccountId) {
+    self.invite_member(stash_id, account_id, Role::Contributor, None);
+  }
+
+  // change the role of a member below the caller, see `Stash::grant_role`
+  pub fn grant_role(&mut self, stash_id: u64, account_id: AccountId, role: Role) {
+    let prev_storage = env::storage_usage();
+    let mut stash = self.internal_get_stash(stash_id);
+    stash.grant_role(account_id, role);
+    self.internal_save_stash(stash_id, stash);
+    self.internal_check_storage(&env::predecessor_account_id(), prev_storage);
+  }
+
+  // remove a member ranked below the caller
+  pub fn revoke_role(&mut self, stash_id: u64, account_id: AccountId) {
     let prev_storage = env::storage_usage();
-    let mut stash = self.stashes.get(&stash_id).expect("ERR_STASH_NOT_FOUND");
-    stash.authorize_contributor(account_id);
-    self.internal_check_storage(prev_storage);
+    let mut stash = self.internal_get_stash(stash_id);
+    stash.revoke_role(&account_id);
+    self.internal_save_stash(stash_id, stash);
+    self.internal_remove_account_stash(&account_id, stash_id);
+    self.internal_check_storage(&env::predecessor_account_id(), prev_storage);
   }
 
-  pub fn get_stashes_for_account(&self, account_id: AccountId) -> Vec<u64> {
-    log!("stash keys are {:?}", self.accounts.keys().collect::<Vec<AccountId>>());
-    self.accounts.get(&account_id).unwrap_or_else(|| UnorderedSet::new(b"s".to_vec())).to_vec()
+  // hand the stash over to another account, the caller stays on as admin
+  pub fn transfer_ownership(&mut self, stash_id: u64, new_owner_id: AccountId) {
+    let prev_storage = env::storage_usage();
+    let mut stash = self.internal_get_stash(stash_id);
+    if stash.transfer_ownership(new_owner_id.clone()) {
+      self.assert_member_limit(&stash);
+      self.internal_add_account_stash(&new_owner_id, stash_id);
+    }
+    self.internal_save_stash(stash_id, stash);
+    self.internal_check_storage(&env::predecessor_account_id(), prev_storage);
   }
 
- // TODO add helper methods to fetch shares per vault by accountId, decide what methods should be here vs in an indexer.
+  // leave a stash, deposits and shares can still be taken out afterwards with `exit_stash`
+  pub fn leave_stash(&mut self, stash_id: u64) {
+    let prev_storage = env::storage_usage();
+    let account_id = env::predecessor_account_id();
+    let mut stash = self.internal_get_stash(stash_id);
+    stash.leave();
+    self.internal_save_stash(stash_id, stash);
+    self.internal_remove_account_stash(&account_id, stash_id);
+    self.internal_check_storage(&account_id, prev_storage);
+  }
 
+  // withdraw deposited tokens from a stash back to the caller, requires 1 yoctoNEAR
+  // a contributor withdrawing their last deposit stops being a member
   #[payable]
+  pub fn withdraw_from_stash(&mut self, stash_id: u64, token_id: AccountId, amount: U128) -> Promise {
+    let prev_storage = env::storage_usage();
+    let sender_id = env::predecessor_account_id();
+    let mut stash = self.internal_get_stash(stash_id);
+    self.assert_not_paused(&stash, MethodClass::Withdrawals);
+    let sent = stash.withdraw(token_id.clone(), amount);
+    let deauthorized = stash.get_role(&sender_id).is_none();
+    self.internal_save_stash(stash_id, stash);
+    if deauthorized {
+      self.internal_remove_account_stash(&sender_id, stash_id);
+    }
+    self.internal_record_storage(&sender_id, prev_storage);
+    self.internal_send_withdrawal(stash_id, sender_id, token_id, U128(sent), U128(amount.0 - sent), deauthorized)
+  }
+
+  // for accounts which left or were removed: redeem all their shares of the token and send
+  // back their whole deposit of it, requires 1 yoctoNEAR
+  #[payable]
+  pub fn exit_stash(&mut self, stash_id: u64, token_id: AccountId) -> Promise {
+    let prev_storage = env::storage_usage();
+    let sender_id = env::predecessor_account_id();
+    let mut stash = self.internal_get_stash(stash_id);
+    let (sent, penalty) = stash.exit(&token_id);
+    self.internal_save_stash(stash_id, stash);
+    self.internal_record_storage(&sender_id, prev_storage);
+    self.internal_send_withdrawal(stash_id, sender_id, token_id, U128(sent), U128(penalty), false)
+  }
+
+  /// Pays out the early withdrawal `penalty` withheld from the transfer of `amount`, or restores
+  /// both to the user's deposits, and their membership if the withdrawal ended it, if the
+  /// transfer failed. Returns whether the transfer succeeded.
+  #[private]
+  #[allow(clippy::too_many_arguments)]
+  pub fn on_withdraw_complete(
+    &mut self,
+    stash_id: u64,
+    account_id: AccountId,
+    token_id: AccountId,
+    amount: U128,
+    penalty: U128,
+    deauthorized: bool,
+    #[callback_result] result: Result<(), PromiseError>,
+  ) -> bool {
+    let succeeded = result.is_ok();
+    if succeeded && penalty.0 == 0 {
+      DivvyEvent::Withdraw { stash_id, account_id, token_id, amount }.emit();
+      return true;
+    }
+    let Some(mut stash) = self.stashes.get(&stash_id).map(Stash::from) else {
+      log!("ERR_STASH_NOT_FOUND {}, unable to settle {} {} of {}", stash_id, amount.0 + penalty.0, token_id, account_id);
+      return succeeded;
+    };
+    let prev_storage = env::storage_usage();
+    if succeeded {
+      stash.collect_penalty(&account_id, &token_id, penalty.0);
+      DivvyEvent::Withdraw { stash_id, account_id: account_id.clone(), token_id, amount }.emit();
+    } else {
+      log!("Withdrawal of {} {} to {} failed, restoring deposit", amount.0, token_id, account_id);
+      stash.restore_withdrawal(&account_id, &token_id, amount.0 + penalty.0, deauthorized);
+      if deauthorized {
+        self.internal_add_account_stash(&account_id, stash_id);
+      }
+    }
+    self.internal_save_stash(stash_id, stash);
+    self.internal_record_storage(&account_id, prev_storage);
+    succeeded
+  }
+
+  // winds the stash down, started by the owner or an approved `Dissolve` proposal
+  // each call redeems the shares of the next `limit` members into their deposits, anyone can
+  // continue a started dissolution and pays for the storage it uses
+  // returns whether the stash is closed
+  pub fn dissolve_stash(&mut self, stash_id: u64, limit: Option<u64>) -> bool {
+    let prev_storage = env::storage_usage();
+    let limit = limit.unwrap_or(DEFAULT_DISSOLVE_BATCH);
+    assert!(limit > 0, "ERR_ZERO_LIMIT");
+    let mut stash = self.internal_get_stash(stash_id);
+    if stash.get_status() == StashStatus::Open {
+      stash.assert_owner();
+      stash.start_dissolution();
+    }
+    let closed = stash.dissolve(limit);
+    self.internal_save_stash(stash_id, stash);
+    self.internal_check_storage(&env::predecessor_account_id(), prev_storage);
+    closed
+  }
+
+  // owner only, once no vault has shares and no member has deposits left, see `Stash::clear`
+  // the storage of each member's entries is released to that member, the rest to the owner
   pub fn remove_stash(&mut self, stash_id: u64) {
+    let mut stash = self.internal_get_stash(stash_id);
+    stash.assert_removable();
+    for (account_id, _) in stash.get_members() {
+      let prev_storage = env::storage_usage();
+      stash.clear_member(&account_id);
+      self.internal_remove_account_stash(&account_id, stash_id);
+      self.internal_record_storage(&account_id, prev_storage);
+    }
     let prev_storage = env::storage_usage();
+    stash.clear();
     self.stashes.remove(&stash_id);
-    self.internal_check_storage(prev_storage);
+    self.internal_check_storage(&env::predecessor_account_id(), prev_storage);
+    // released to whoever created the codes
+    self.internal_clear_invite_codes(stash_id);
+    DivvyEvent::StashRemoved { stash_id, owner_id: env::predecessor_account_id() }.emit();
   }
 
 }
 
 // internal methods
 impl Contract {
+  /// Sends `amount` of the token held by the contract to `receiver_id`, wNEAR is unwrapped
+  /// and sent as native NEAR.
+  pub(crate) fn internal_transfer(&self, receiver_id: &AccountId, token_id: &AccountId, amount: U128) -> Promise {
+    if self.config.wrap_near_id.as_ref() == Some(token_id) {
+      self.internal_unwrap_and_transfer(token_id, receiver_id, amount)
+    } else {
+      ext_ft_core::ext(token_id.clone())
+        .with_attached_deposit(NearToken::from_yoctonear(1))
+        .with_static_gas(GAS_FOR_FT_TRANSFER)
+        .ft_transfer(receiver_id.clone(), amount, None)
+    }
+  }
+
+  pub(crate) fn internal_get_stash(&self, stash_id: u64) -> Stash {
+    self.stashes.get(&stash_id).expect("ERR_STASH_NOT_FOUND").into()
+  }
+
+  pub(crate) fn internal_save_stash(&mut self, stash_id: u64, stash: Stash) {
+    self.stashes.insert(&stash_id, &stash.into());
+  }
 
-  fn internal_check_storage(&self, prev_storage: StorageUsage) -> u128 {
-      let storage_needed = env::storage_usage().checked_sub(prev_storage);
-      let storage_cost = storage_needed.unwrap_or(0) as u128 * env::storage_byte_cost().as_yoctonear();
+  pub(crate) fn internal_add_account_stash(&mut self, account_id: &AccountId, stash_id: u64) {
+    let mut set: UnorderedSet<u64> = self
+      .accounts
+      .get(account_id)
+      .unwrap_or_else(|| UnorderedSet::new(StorageKey::AccountStashes { account_id: account_id.clone() }));
+    set.insert(&stash_id);
+    self.accounts.insert(account_id, &set);
+  }
 
-      let refund = env::attached_deposit()
-          .checked_sub(NearToken::from_yoctonear(storage_cost))
-          .unwrap_or_else(|| panic!("ERR_STORAGE_DEPOSIT need {}, attatched {}",
-                  storage_cost, env::attached_deposit()));
-      if !refund.is_zero() {
-          Promise::new(env::predecessor_account_id()).transfer(refund);
+  pub(crate) fn internal_remove_account_stash(&mut self, account_id: &AccountId, stash_id: u64) {
+    if let Some(mut set) = self.accounts.get(account_id) {
+      set.remove(&stash_id);
+      if set.is_empty() {
+        self.accounts.remove(account_id);
+      } else {
+        self.accounts.insert(account_id, &set);
       }
-      storage_cost
+    }
+  }
+
+  // sends a withdrawn amount, `on_withdraw_complete` restores it if the transfer fails
+  fn internal_send_withdrawal(
+    &self,
+    stash_id: u64,
+    account_id: AccountId,
+    token_id: AccountId,
+    amount: U128,
+    penalty: U128,
+    deauthorized: bool,
+  ) -> Promise {
+    self.internal_transfer(&account_id, &token_id, amount).then(
+      Self::ext(env::current_account_id())
+        .with_static_gas(GAS_FOR_ON_WITHDRAW_COMPLETE)
+        .on_withdraw_complete(stash_id, account_id, token_id, amount, penalty, deauthorized)
+    )
   }
 }
 
+/// Message attached to `ft_transfer_call` when sending tokens into a stash.
+#[near(serializers = [json])]
+pub struct FtDepositMsg {
+  pub stash_id: u64,
+  /// Move the received tokens straight into the stash's vault.
+  #[serde(default)]
+  pub add_liquidity: bool,
+}
+
+#[near]
+impl FungibleTokenReceiver for Contract {
+
+  /// Credits the transferred tokens to the sender's deposits in the stash named by `msg`.
+  /// Returns the full amount, refunding the sender, if the message is malformed, the stash
+  /// is unknown, the sender is not authorized or registered, or the token is not supported.
+  fn ft_on_transfer(&mut self, sender_id: AccountId, amount: U128, msg: String) -> PromiseOrValue<U128> {
+    let token_id = env::predecessor_account_id();
+    let deposit_msg: FtDepositMsg = match near_sdk::serde_json::from_str(&msg) {
+      Ok(deposit_msg) => deposit_msg,
+      Err(_) => {
+        log!("ERR_INVALID_MSG {}", msg);
+        return PromiseOrValue::Value(amount);
+      }
+    };
+    let mut stash = match self.stashes.get(&deposit_msg.stash_id).map(Stash::from) {
+      Some(stash) => stash,
+      None => {
+        log!("ERR_STASH_NOT_FOUND {}", deposit_msg.stash_id);
+        return PromiseOrValue::Value(amount);
+      }
+    };
+    if self.is_paused(&stash, MethodClass::Deposits) {
+      log!("ERR_PAUSED deposits into stash {}", deposit_msg.stash_id);
+      return PromiseOrValue::Value(amount);
+    }
+    if !self.is_supported_token(&token_id) || !stash.accepts_deposit(&sender_id, &token_id) {
+      log!("ERR_DEPOSIT_NOT_ACCEPTED {} from {}", token_id, sender_id);
+      return PromiseOrValue::Value(amount);
+    }
+    if self.storage_accounts.get(&sender_id).is_none() {
+      log!("ERR_STORAGE_NOT_REGISTERED {}", sender_id);
+      return PromiseOrValue::Value(amount);
+    }
+
+    let prev_storage = env::storage_usage();
+    stash.ft_deposit(&sender_id, &token_id, amount.0, deposit_msg.add_liquidity);
+    self.internal_save_stash(deposit_msg.stash_id, stash);
+    // a panic here rolls back the deposit and `ft_resolve_transfer` refunds the sender
+    self.internal_check_storage(&sender_id, prev_storage);
+    PromiseOrValue::Value(U128(0))
+  }
+}
 
 #[cfg(test)]
 mod tests {
 
+    use near_contract_standards::storage_management::StorageManagement;
     use near_sdk::{test_utils::{accounts, VMContextBuilder}, NearToken, testing_env};
 
     use super::*;
+    use crate::test_utils::{add_member, get_events, new_contract};
 
     fn get_context(predecessor: AccountId) -> VMContextBuilder {
       let mut builder = VMContextBuilder::new();
@@ -136,7 +454,7 @@ mod tests {
     fn test_new_contract() {
       let context = get_context(accounts(0));
       testing_env!(context.build());
-      let contract = Contract::new();
+      let contract = new_contract(None);
       assert!(contract.stashes.is_empty());
       assert!(contract.accounts.is_empty());
     }
@@ -145,23 +463,389 @@ mod tests {
     fn test_create_stash() {
       let mut context = get_context(accounts(0));
       testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
-      let mut contract = Contract::new();
+      let mut contract = new_contract(None);
+      contract.storage_deposit(None, None);
       assert_eq!(contract.stashes.len(), 0);
       assert_eq!(contract.accounts.len(), 0);
       contract.create_stash("Roommates".to_string());
       assert_eq!(contract.stashes.len(), 1);
       assert_eq!(contract.accounts.len(), 1);
+      assert_eq!(
+        get_events(),
+        vec![DivvyEvent::StashCreated { stash_id: 0, owner_id: accounts(0), name: "Roommates".to_string() }]
+      );
+    }
+
+    fn usdt() -> AccountId {
+      "usdt-token.near".parse().unwrap()
+    }
+
+    fn setup_stash_with_vault(context: &mut VMContextBuilder) -> Contract {
+      testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+      let mut contract = new_contract(None);
+      contract.storage_deposit(None, None);
+      let stash_id = contract.create_stash("Roommates".to_string());
+      contract.add_token_to_stash(stash_id, usdt());
+      contract
+    }
+
+    fn unwrap_value(result: PromiseOrValue<U128>) -> u128 {
+      match result {
+        PromiseOrValue::Value(value) => value.0,
+        PromiseOrValue::Promise(_) => panic!("expected a value"),
+      }
+    }
+
+    #[test]
+    fn test_ft_on_transfer_deposit() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+
+      testing_env!(context.predecessor_account_id(usdt()).attached_deposit(NearToken::from_yoctonear(0)).build());
+      let unused = contract.ft_on_transfer(accounts(0), U128(1_000), r#"{"stash_id": 0}"#.to_string());
+      assert_eq!(unwrap_value(unused), 0);
+
+      let stash = contract.internal_get_stash(0);
+      assert_eq!(stash.get_deposit(&accounts(0), &usdt()), 1_000);
+      assert_eq!(
+        get_events(),
+        vec![DivvyEvent::Deposit { stash_id: 0, account_id: accounts(0), token_id: usdt(), amount: U128(1_000) }]
+      );
+    }
+
+    #[test]
+    fn test_ft_on_transfer_add_liquidity() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+
+      testing_env!(context.predecessor_account_id(usdt()).attached_deposit(NearToken::from_yoctonear(0)).build());
+      let unused = contract.ft_on_transfer(accounts(0), U128(1_000), r#"{"stash_id": 0, "add_liquidity": true}"#.to_string());
+      assert_eq!(unwrap_value(unused), 0);
+
+      let stash = contract.internal_get_stash(0);
+      assert_eq!(stash.get_deposit(&accounts(0), &usdt()), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Token is not on the allowed list")]
+    fn test_add_unsupported_token_to_stash() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      contract.add_token_to_stash(0, "usdt.tether-token.near".parse().unwrap());
+    }
+
+    #[test]
+    fn test_ft_on_transfer_refunds_removed_token() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      contract.remove_supported_token(usdt());
+
+      testing_env!(context.predecessor_account_id(usdt()).attached_deposit(NearToken::from_yoctonear(0)).build());
+      let unused = contract.ft_on_transfer(accounts(0), U128(1_000), r#"{"stash_id": 0}"#.to_string());
+      assert_eq!(unwrap_value(unused), 1_000);
+    }
+
+    #[test]
+    fn test_ft_on_transfer_refunds() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+
+      testing_env!(context.predecessor_account_id(usdt()).attached_deposit(NearToken::from_yoctonear(0)).build());
+      // unknown stash
+      let unused = contract.ft_on_transfer(accounts(0), U128(1_000), r#"{"stash_id": 7}"#.to_string());
+      assert_eq!(unwrap_value(unused), 1_000);
+      // malformed message
+      let unused = contract.ft_on_transfer(accounts(0), U128(1_000), "".to_string());
+      assert_eq!(unwrap_value(unused), 1_000);
+      // sender is not authorized on the stash
+      let unused = contract.ft_on_transfer(accounts(1), U128(1_000), r#"{"stash_id": 0}"#.to_string());
+      assert_eq!(unwrap_value(unused), 1_000);
+
+      // token without a vault in the stash
+      testing_env!(context.predecessor_account_id("usdc-token.near".parse().unwrap()).build());
+      let unused = contract.ft_on_transfer(accounts(0), U128(1_000), r#"{"stash_id": 0}"#.to_string());
+      assert_eq!(unwrap_value(unused), 1_000);
+    }
+
+    fn deposit_usdt(context: &mut VMContextBuilder, contract: &mut Contract, sender_id: AccountId, amount: u128) {
+      testing_env!(context.predecessor_account_id(usdt()).attached_deposit(NearToken::from_yoctonear(0)).build());
+      contract.ft_on_transfer(sender_id.clone(), U128(amount), r#"{"stash_id": 0}"#.to_string());
+      testing_env!(context.predecessor_account_id(sender_id).build());
+    }
+
+    #[test]
+    fn test_withdraw_from_stash() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      deposit_usdt(&mut context, &mut contract, accounts(0), 1_000);
+
+      testing_env!(context.attached_deposit(NearToken::from_yoctonear(1)).build());
+      contract.withdraw_from_stash(0, usdt(), U128(400));
+      assert_eq!(contract.internal_get_stash(0).get_deposit(&accounts(0), &usdt()), 600);
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires attached deposit of exactly 1 yoctoNEAR")]
+    fn test_withdraw_from_stash_requires_one_yocto() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      deposit_usdt(&mut context, &mut contract, accounts(0), 1_000);
+
+      testing_env!(context.attached_deposit(NearToken::from_yoctonear(0)).build());
+      contract.withdraw_from_stash(0, usdt(), U128(400));
+    }
+
+    #[test]
+    fn test_on_withdraw_complete_restores_deposit() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      deposit_usdt(&mut context, &mut contract, accounts(0), 1_000);
+
+      testing_env!(context.attached_deposit(NearToken::from_yoctonear(1)).build());
+      contract.withdraw_from_stash(0, usdt(), U128(1_000));
+      assert_eq!(contract.internal_get_stash(0).get_deposit(&accounts(0), &usdt()), 0);
+
+      testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+      let succeeded = contract.on_withdraw_complete(0, accounts(0), usdt(), U128(1_000), U128(0), false, Err(PromiseError::Failed));
+      assert!(!succeeded);
+      assert_eq!(contract.internal_get_stash(0).get_deposit(&accounts(0), &usdt()), 1_000);
+    }
+
+    #[test]
+    fn test_on_withdraw_complete_success() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      deposit_usdt(&mut context, &mut contract, accounts(0), 1_000);
+
+      testing_env!(context.attached_deposit(NearToken::from_yoctonear(1)).build());
+      contract.withdraw_from_stash(0, usdt(), U128(1_000));
+
+      testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+      assert!(contract.on_withdraw_complete(0, accounts(0), usdt(), U128(1_000), U128(0), false, Ok(())));
+      assert_eq!(contract.internal_get_stash(0).get_deposit(&accounts(0), &usdt()), 0);
+      assert_eq!(
+        get_events(),
+        vec![DivvyEvent::Withdraw { stash_id: 0, account_id: accounts(0), token_id: usdt(), amount: U128(1_000) }]
+      );
     }
 
     #[test]
     fn test_remove_stash() {
       let mut context = get_context(accounts(0));
       testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
-      let mut contract = Contract::new();
+      let mut contract = new_contract(None);
+      contract.storage_deposit(None, None);
       contract.create_stash("Roommates".to_string());
       let stash_id = 0;
       contract.remove_stash(stash_id);
       assert!(contract.stashes.get(&stash_id).is_none());
     }
-}
 
+    #[test]
+    fn test_remove_stash_clears_members_and_storage() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      let used = contract.storage_accounts.get(&accounts(0)).unwrap().used;
+      add_member(&mut context, &mut contract, 0, accounts(1), Role::Contributor);
+      deposit_usdt(&mut context, &mut contract, accounts(0), 1_000);
+      contract.add_liquidity_to_stash(0, usdt(), 1_000);
+      contract.remove_liquidity_from_stash(0, usdt(), 1_000_000);
+      testing_env!(context.attached_deposit(NearToken::from_yoctonear(1)).build());
+      contract.withdraw_from_stash(0, usdt(), U128(1_000));
+
+      contract.remove_stash(0);
+      assert!(contract.get_stashes_for_account(accounts(0)).is_empty());
+      assert!(contract.get_stashes_for_account(accounts(1)).is_empty());
+      assert!(contract.accounts.is_empty());
+      assert!(contract.storage_accounts.get(&accounts(0)).unwrap().used < used);
+    }
+
+    #[test]
+    fn test_remove_stash_releases_member_storage() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      add_member(&mut context, &mut contract, 0, accounts(1), Role::Admin);
+      deposit_usdt(&mut context, &mut contract, accounts(1), 1_000);
+      testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_yoctonear(1)).build());
+      contract.withdraw_from_stash(0, usdt(), U128(1_000));
+      assert!(contract.storage_accounts.get(&accounts(1)).unwrap().used > 0);
+      let owner_used = contract.storage_accounts.get(&accounts(0)).unwrap().used;
+
+      testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).build());
+      contract.remove_stash(0);
+      assert_eq!(contract.storage_accounts.get(&accounts(1)).unwrap().used, 0);
+      assert!(contract.storage_accounts.get(&accounts(0)).unwrap().used < owner_used);
+
+      testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_yoctonear(1)).build());
+      assert!(contract.storage_unregister(None));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_DEPOSITS_NOT_EMPTY")]
+    fn test_remove_stash_with_deposits() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      deposit_usdt(&mut context, &mut contract, accounts(0), 1_000);
+      contract.remove_stash(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_VAULT_NOT_EMPTY")]
+    fn test_remove_stash_with_shares() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      deposit_usdt(&mut context, &mut contract, accounts(0), 1_000);
+      contract.add_liquidity_to_stash(0, usdt(), 1_000);
+      contract.remove_stash(0);
+    }
+
+    #[test]
+    fn test_stash_ids_are_not_reused() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      assert_eq!(contract.create_stash("Trip".to_string()), 1);
+      contract.remove_stash(0);
+      assert_eq!(contract.create_stash("Close Friends".to_string()), 2);
+      assert_eq!(contract.get_stashes_for_account(accounts(0)), vec![1, 2]);
+      assert_eq!(contract.get_stash(1).unwrap().name, "Trip");
+    }
+
+    #[test]
+    #[should_panic(expected = "Caller is not authorized")]
+    fn test_remove_stash_owner_only() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      add_member(&mut context, &mut contract, 0, accounts(1), Role::Admin);
+
+      testing_env!(context.predecessor_account_id(accounts(1)).build());
+      contract.remove_stash(0);
+    }
+
+    #[test]
+    fn test_grant_and_revoke_role() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      contract.authorize_contributor(0, accounts(1));
+      // the invitee only joins once accepting
+      assert_eq!(contract.internal_get_stash(0).get_role(&accounts(1)), None);
+      testing_env!(context.predecessor_account_id(accounts(1)).build());
+      contract.storage_deposit(None, None);
+      contract.accept_invite(0);
+      assert_eq!(contract.internal_get_stash(0).get_role(&accounts(1)), Some(Role::Contributor));
+      assert_eq!(contract.get_stashes_for_account(accounts(1)), vec![0]);
+
+      testing_env!(context.predecessor_account_id(accounts(0)).build());
+      contract.grant_role(0, accounts(1), Role::Viewer);
+      assert_eq!(contract.internal_get_stash(0).get_role(&accounts(1)), Some(Role::Viewer));
+      contract.revoke_role(0, accounts(1));
+      assert_eq!(contract.internal_get_stash(0).get_role(&accounts(1)), None);
+      assert!(contract.get_stashes_for_account(accounts(1)).is_empty());
+    }
+
+    #[test]
+    fn test_transfer_ownership_and_leave() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      contract.transfer_ownership(0, accounts(1));
+      let stash = contract.internal_get_stash(0);
+      assert_eq!(stash.get_role(&accounts(1)), Some(Role::Owner));
+      assert_eq!(stash.get_role(&accounts(0)), Some(Role::Admin));
+      assert_eq!(contract.get_stashes_for_account(accounts(1)), vec![0]);
+
+      contract.leave_stash(0);
+      assert_eq!(contract.internal_get_stash(0).get_role(&accounts(0)), None);
+      assert!(contract.get_stashes_for_account(accounts(0)).is_empty());
+    }
+
+    #[test]
+    fn test_dissolve_stash() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      add_member(&mut context, &mut contract, 0, accounts(1), Role::Contributor);
+      deposit_usdt(&mut context, &mut contract, accounts(0), 3_000);
+      contract.add_liquidity_to_stash(0, usdt(), 2_000);
+      testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_near(1)).build());
+      contract.storage_deposit(None, None);
+      deposit_usdt(&mut context, &mut contract, accounts(1), 1_000);
+      contract.add_liquidity_to_stash(0, usdt(), 1_000);
+
+      testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).build());
+      assert!(!contract.dissolve_stash(0, Some(1)));
+      assert_eq!(contract.get_stash(0).unwrap().status, StashStatus::Dissolving { next_index: 1 });
+      assert_eq!(contract.get_deposit(0, accounts(0), usdt()).0, 3_000);
+      assert_eq!(contract.get_shares(0, usdt(), accounts(1)).0, 1_000_000);
+
+      // no further deposits once dissolving
+      testing_env!(context.predecessor_account_id(usdt()).build());
+      let unused = contract.ft_on_transfer(accounts(0), U128(1_000), r#"{"stash_id": 0}"#.to_string());
+      assert_eq!(unwrap_value(unused), 1_000);
+
+      testing_env!(context.predecessor_account_id(accounts(1)).build());
+      assert!(contract.dissolve_stash(0, Some(1)));
+      assert_eq!(contract.get_stash(0).unwrap().status, StashStatus::Closed);
+      assert_eq!(contract.get_deposit(0, accounts(1), usdt()).0, 1_000);
+      assert_eq!(contract.get_vault(0, usdt()).unwrap().shares_total_supply.0, 0);
+      assert!(get_events().contains(&DivvyEvent::StashDissolved { stash_id: 0 }));
+
+      testing_env!(context.attached_deposit(NearToken::from_yoctonear(1)).build());
+      contract.withdraw_from_stash(0, usdt(), U128(1_000));
+      assert_eq!(contract.get_deposit(0, accounts(1), usdt()).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Caller is not authorized")]
+    fn test_dissolve_stash_owner_only() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      add_member(&mut context, &mut contract, 0, accounts(1), Role::Admin);
+
+      testing_env!(context.predecessor_account_id(accounts(1)).build());
+      contract.dissolve_stash(0, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_STASH_NOT_DISSOLVING")]
+    fn test_dissolve_closed_stash() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      assert!(contract.dissolve_stash(0, None));
+      contract.dissolve_stash(0, None);
+    }
+
+    #[test]
+    fn test_removed_member_can_exit() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      add_member(&mut context, &mut contract, 0, accounts(1), Role::Contributor);
+      deposit_usdt(&mut context, &mut contract, accounts(1), 2_000);
+      testing_env!(context.predecessor_account_id(accounts(1)).build());
+      contract.add_liquidity_to_stash(0, usdt(), 1_500);
+
+      testing_env!(context.predecessor_account_id(accounts(0)).build());
+      contract.revoke_role(0, accounts(1));
+
+      testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_yoctonear(1)).build());
+      contract.exit_stash(0, usdt());
+      assert_eq!(contract.internal_get_stash(0).get_deposit(&accounts(1), &usdt()), 0);
+    }
+
+    #[test]
+    fn test_withdrawing_last_deposit_ends_contributor_membership() {
+      let mut context = get_context(accounts(0));
+      let mut contract = setup_stash_with_vault(&mut context);
+      add_member(&mut context, &mut contract, 0, accounts(1), Role::Contributor);
+      testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_near(1)).build());
+      contract.storage_deposit(None, None);
+      deposit_usdt(&mut context, &mut contract, accounts(1), 1_000);
+
+      testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_yoctonear(1)).build());
+      contract.withdraw_from_stash(0, usdt(), U128(1_000));
+      assert!(contract.get_stashes_for_account(accounts(1)).is_empty());
+
+      // the transfer failed
+      testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+      assert!(!contract.on_withdraw_complete(0, accounts(1), usdt(), U128(1_000), U128(0), true, Err(PromiseError::Failed)));
+      assert_eq!(contract.internal_get_stash(0).get_role(&accounts(1)), Some(Role::Contributor));
+      assert_eq!(contract.get_stashes_for_account(accounts(1)), vec![0]);
+    }
+}
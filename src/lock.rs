@@ -0,0 +1,407 @@
+use near_contract_standards::fungible_token::Balance;
+use near_sdk::json_types::U64;
+use near_sdk::{env, near, AccountId};
+
+use crate::math::{mul_div, Rounding};
+use crate::token_vault::FEE_DENOMINATOR;
+use crate::{Contract, ContractExt};
+
+/// Commitment of a stash to keep its funds until `unlock_at`.
+/// Before then withdrawals of deposits are refused, or charged the early withdrawal penalty,
+/// and payout proposals cannot be executed.
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct StashLock {
+    /// Block timestamp the lock ends at, in nanoseconds.
+    pub unlock_at: U64,
+    /// `None` refuses withdrawals and liquidity removals until `unlock_at`.
+    pub early_withdrawal_penalty: Option<EarlyWithdrawalPenalty>,
+}
+
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct EarlyWithdrawalPenalty {
+    /// Part of every early withdrawal withheld, in basis points.
+    pub penalty_bps: u16,
+    pub receiver: PenaltyReceiver,
+}
+
+/// Where early withdrawal penalties go.
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Debug, PartialEq)]
+pub enum PenaltyReceiver {
+    /// Pooled into the token's vault, raising the value of the remaining members' shares.
+    Members,
+    /// Credited to the deposits of `account_id`, which can withdraw them despite the lock.
+    Treasury { account_id: AccountId },
+}
+
+#[near(serializers = [json])]
+pub struct LockStatus {
+    pub lock: StashLock,
+    /// Whether `unlock_at` is still ahead.
+    pub locked: bool,
+    /// Nanoseconds left until `unlock_at`.
+    pub remaining: U64,
+}
+
+impl StashLock {
+    /// Panics unless `unlock_at` is ahead and the penalty, if any, is between 1 and 10000 bps.
+    pub fn assert_valid(&self) {
+        assert!(self.unlock_at.0 > env::block_timestamp(), "ERR_INVALID_UNLOCK");
+        if let Some(penalty) = &self.early_withdrawal_penalty {
+            assert!(
+                penalty.penalty_bps > 0 && penalty.penalty_bps <= FEE_DENOMINATOR,
+                "ERR_INVALID_PENALTY"
+            );
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        env::block_timestamp() < self.unlock_at.0
+    }
+
+    pub fn get_status(&self) -> LockStatus {
+        LockStatus {
+            lock: self.clone(),
+            locked: self.is_active(),
+            remaining: U64(self.unlock_at.0.saturating_sub(env::block_timestamp())),
+        }
+    }
+}
+
+impl EarlyWithdrawalPenalty {
+    /// Penalty withheld from an early withdrawal of `amount`, rounded up.
+    pub fn penalty_of(&self, amount: Balance) -> Balance {
+        mul_div(amount, self.penalty_bps as u128, FEE_DENOMINATOR as u128, Rounding::Up)
+    }
+
+    /// Whether `account_id` collects the penalties, and is therefore not charged any.
+    pub fn is_receiver(&self, account_id: &AccountId) -> bool {
+        matches!(&self.receiver, PenaltyReceiver::Treasury { account_id: treasury_id } if treasury_id == account_id)
+    }
+}
+
+#[near]
+impl Contract {
+    /// Locks the funds of the stash until `lock.unlock_at`, or removes an expired lock with
+    /// `None`, owner only. An active lock can only be extended, see `Stash::set_lock`.
+    pub fn set_stash_lock(&mut self, stash_id: u64, lock: Option<StashLock>) {
+        let prev_storage = env::storage_usage();
+        let mut stash = self.internal_get_stash(stash_id);
+        stash.set_lock(lock);
+        self.internal_save_stash(stash_id, stash);
+        self.internal_check_storage(&env::predecessor_account_id(), prev_storage);
+    }
+
+    /// Lock of the stash and whether it still holds, `None` without a lock.
+    pub fn get_lock_status(&self, stash_id: u64) -> Option<LockStatus> {
+        self.internal_get_stash(stash_id).get_lock_status()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::json_types::U128;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, NearToken, PromiseError};
+
+    use super::*;
+    use crate::events::DivvyEvent;
+    use crate::goal::{Goal, GoalTarget};
+    use crate::stash::Role;
+    use crate::test_utils::{add_member, get_context, get_events, new_contract};
+
+    const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+    fn usdt() -> AccountId {
+        "usdt-token.near".parse().unwrap()
+    }
+
+    fn lock(penalty: Option<(u16, PenaltyReceiver)>) -> StashLock {
+        StashLock {
+            unlock_at: U64(365 * DAY),
+            early_withdrawal_penalty: penalty
+                .map(|(penalty_bps, receiver)| EarlyWithdrawalPenalty { penalty_bps, receiver }),
+        }
+    }
+
+    /// "College fund" with a usdt vault, accounts(0) and accounts(1) each pooled 10_000 and
+    /// deposited another 10_000.
+    fn setup(context: &mut VMContextBuilder) -> Contract {
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = new_contract(None);
+        contract.storage_deposit(None, None);
+        contract.create_stash("College fund".to_string());
+        contract.add_token_to_stash(0, usdt());
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(0)).build());
+        add_member(context, &mut contract, 0, accounts(1), Role::Contributor);
+        for account_id in [accounts(0), accounts(1)] {
+            let mut stash = contract.internal_get_stash(0);
+            stash.ft_deposit(&account_id, &usdt(), 20_000, false);
+            contract.internal_save_stash(0, stash);
+            testing_env!(context.predecessor_account_id(account_id).build());
+            contract.add_liquidity_to_stash(0, usdt(), 10_000);
+        }
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract
+    }
+
+    fn withdraw(context: &mut VMContextBuilder, contract: &mut Contract, account_id: AccountId, amount: u128) {
+        testing_env!(context.predecessor_account_id(account_id).attached_deposit(NearToken::from_yoctonear(1)).build());
+        contract.withdraw_from_stash(0, usdt(), U128(amount));
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).build());
+    }
+
+    #[test]
+    fn test_penalty_math() {
+        let penalty = EarlyWithdrawalPenalty { penalty_bps: 250, receiver: PenaltyReceiver::Members };
+        assert_eq!(penalty.penalty_of(10_000), 250);
+        assert_eq!(penalty.penalty_of(1_001), 26);
+        assert_eq!(penalty.penalty_of(1), 1);
+        assert_eq!(penalty.penalty_of(0), 0);
+        let penalty = EarlyWithdrawalPenalty { penalty_bps: FEE_DENOMINATOR, receiver: PenaltyReceiver::Members };
+        assert_eq!(penalty.penalty_of(u128::MAX), u128::MAX);
+    }
+
+    #[test]
+    fn test_lock_status() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        assert!(contract.get_lock_status(0).is_none());
+        contract.set_stash_lock(0, Some(lock(None)));
+        assert_eq!(get_events(), vec![DivvyEvent::StashLockUpdated { stash_id: 0, lock: Some(lock(None)) }]);
+
+        testing_env!(context.block_timestamp(100 * DAY).build());
+        let status = contract.get_lock_status(0).unwrap();
+        assert!(status.locked);
+        assert_eq!(status.remaining, U64(265 * DAY));
+        assert_eq!(contract.get_stash(0).unwrap().lock.unwrap().remaining, U64(265 * DAY));
+
+        testing_env!(context.block_timestamp(365 * DAY).build());
+        let status = contract.get_lock_status(0).unwrap();
+        assert!(!status.locked);
+        assert_eq!(status.remaining, U64(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_STASH_LOCKED")]
+    fn test_locked_withdrawal() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.set_stash_lock(0, Some(lock(None)));
+        testing_env!(context.block_timestamp(364 * DAY).build());
+        withdraw(&mut context, &mut contract, accounts(1), 1_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_STASH_LOCKED")]
+    fn test_locked_liquidity() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.set_stash_lock(0, Some(lock(None)));
+        contract.remove_liquidity_from_stash(0, usdt(), 1_000_000);
+    }
+
+    #[test]
+    fn test_withdrawal_after_unlock() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.set_stash_lock(0, Some(lock(Some((500, PenaltyReceiver::Members)))));
+        testing_env!(context.block_timestamp(365 * DAY).build());
+        contract.remove_liquidity_from_stash(0, usdt(), 10_000_000);
+        withdraw(&mut context, &mut contract, accounts(0), 20_000);
+        assert_eq!(contract.internal_get_stash(0).get_deposit(&accounts(0), &usdt()), 0);
+    }
+
+    #[test]
+    fn test_penalty_to_members() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.set_stash_lock(0, Some(lock(Some((500, PenaltyReceiver::Members)))));
+        testing_env!(context.block_timestamp(30 * DAY).build());
+        // shares can leave the vault, the penalty is withheld once the tokens leave the stash
+        contract.remove_liquidity_from_stash(0, usdt(), 10_000_000);
+        withdraw(&mut context, &mut contract, accounts(0), 20_000);
+        assert_eq!(contract.internal_get_stash(0).get_deposit(&accounts(0), &usdt()), 0);
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        contract.on_withdraw_complete(0, accounts(0), usdt(), U128(19_000), U128(1_000), false, Ok(()));
+        assert_eq!(
+            get_events(),
+            vec![
+                DivvyEvent::EarlyWithdrawalPenalty {
+                    stash_id: 0,
+                    account_id: accounts(0),
+                    token_id: usdt(),
+                    amount: U128(1_000),
+                    receiver: PenaltyReceiver::Members,
+                },
+                DivvyEvent::Withdraw { stash_id: 0, account_id: accounts(0), token_id: usdt(), amount: U128(19_000) },
+            ]
+        );
+        // the only shareholder left gets the penalty
+        assert_eq!(contract.internal_get_stash(0).get_vault_assets(&usdt()), 11_000);
+    }
+
+    #[test]
+    fn test_penalty_to_members_reaches_goal() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        let goal = Goal {
+            targets: vec![GoalTarget { token_id: usdt(), amount: U128(20_500) }],
+            deadline: None,
+            lock_on_reached: false,
+        };
+        contract.set_stash_goal(0, Some(goal));
+        contract.set_stash_lock(0, Some(lock(Some((500, PenaltyReceiver::Members)))));
+        withdraw(&mut context, &mut contract, accounts(1), 10_000);
+        assert!(contract.get_goal_progress(0).unwrap().reached_at.is_none());
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).block_timestamp(DAY).build());
+        contract.on_withdraw_complete(0, accounts(1), usdt(), U128(9_500), U128(500), false, Ok(()));
+        assert_eq!(contract.get_goal_progress(0).unwrap().reached_at, Some(U64(DAY)));
+        assert!(get_events().contains(&DivvyEvent::GoalReached { stash_id: 0 }));
+    }
+
+    #[test]
+    fn test_penalty_returned_without_shares() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.set_stash_lock(0, Some(lock(Some((500, PenaltyReceiver::Members)))));
+        withdraw(&mut context, &mut contract, accounts(1), 5_000);
+        // every share is redeemed before the transfer completes
+        for account_id in [accounts(0), accounts(1)] {
+            testing_env!(context.predecessor_account_id(account_id).build());
+            contract.remove_liquidity_from_stash(0, usdt(), 10_000_000);
+        }
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        contract.on_withdraw_complete(0, accounts(1), usdt(), U128(4_750), U128(250), false, Ok(()));
+        let stash = contract.internal_get_stash(0);
+        assert_eq!(stash.get_deposit(&accounts(1), &usdt()), 15_250);
+        assert_eq!(stash.get_vault_assets(&usdt()), 0);
+    }
+
+    #[test]
+    fn test_penalty_to_treasury() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        let treasury = PenaltyReceiver::Treasury { account_id: accounts(2) };
+        contract.set_stash_lock(0, Some(lock(Some((1_000, treasury)))));
+        withdraw(&mut context, &mut contract, accounts(1), 5_001);
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        contract.on_withdraw_complete(0, accounts(1), usdt(), U128(4_500), U128(501), false, Ok(()));
+        let stash = contract.internal_get_stash(0);
+        assert_eq!(stash.get_deposit(&accounts(1), &usdt()), 4_999);
+        assert_eq!(stash.get_deposit(&accounts(2), &usdt()), 501);
+        assert_eq!(stash.get_vault_assets(&usdt()), 20_000);
+
+        // the treasury is not charged, it takes the penalties out like former members
+        testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(NearToken::from_yoctonear(1)).build());
+        contract.exit_stash(0, usdt());
+        assert_eq!(contract.internal_get_stash(0).get_deposit(&accounts(2), &usdt()), 0);
+    }
+
+    #[test]
+    fn test_failed_early_withdrawal_is_not_charged() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.set_stash_lock(0, Some(lock(Some((500, PenaltyReceiver::Members)))));
+        withdraw(&mut context, &mut contract, accounts(1), 10_000);
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        contract.on_withdraw_complete(0, accounts(1), usdt(), U128(9_500), U128(500), false, Err(PromiseError::Failed));
+        let stash = contract.internal_get_stash(0);
+        assert_eq!(stash.get_deposit(&accounts(1), &usdt()), 10_000);
+        assert_eq!(stash.get_vault_assets(&usdt()), 20_000);
+    }
+
+    #[test]
+    fn test_penalty_on_exit() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.revoke_role(0, accounts(1));
+        contract.set_stash_lock(0, Some(lock(Some((500, PenaltyReceiver::Members)))));
+
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_yoctonear(1)).build());
+        contract.exit_stash(0, usdt());
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        contract.on_withdraw_complete(0, accounts(1), usdt(), U128(19_000), U128(1_000), false, Ok(()));
+        let stash = contract.internal_get_stash(0);
+        assert_eq!(stash.get_deposit(&accounts(1), &usdt()), 0);
+        assert_eq!(stash.get_vault_assets(&usdt()), 11_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_STASH_LOCKED")]
+    fn test_locked_exit() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.revoke_role(0, accounts(1));
+        contract.set_stash_lock(0, Some(lock(None)));
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_yoctonear(1)).build());
+        contract.exit_stash(0, usdt());
+    }
+
+    #[test]
+    fn test_extend_lock() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.set_stash_lock(0, Some(lock(None)));
+        let mut extended = lock(None);
+        extended.unlock_at = U64(400 * DAY);
+        contract.set_stash_lock(0, Some(extended.clone()));
+        assert_eq!(contract.get_lock_status(0).unwrap().lock, extended);
+
+        testing_env!(context.block_timestamp(400 * DAY).build());
+        contract.set_stash_lock(0, None);
+        assert!(contract.get_lock_status(0).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_LOCK_ACTIVE")]
+    fn test_active_lock_cannot_be_removed() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.set_stash_lock(0, Some(lock(None)));
+        contract.set_stash_lock(0, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_LOCK_ACTIVE")]
+    fn test_active_lock_cannot_add_penalty() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.set_stash_lock(0, Some(lock(None)));
+        contract.set_stash_lock(0, Some(lock(Some((10, PenaltyReceiver::Members)))));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_PENALTY")]
+    fn test_invalid_penalty() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.set_stash_lock(0, Some(lock(Some((FEE_DENOMINATOR + 1, PenaltyReceiver::Members)))));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_UNLOCK")]
+    fn test_past_unlock() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        testing_env!(context.block_timestamp(365 * DAY).build());
+        contract.set_stash_lock(0, Some(lock(None)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Caller is not authorized")]
+    fn test_lock_owner_only() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.set_stash_lock(0, Some(lock(None)));
+    }
+}
@@ -0,0 +1,100 @@
+// the expansion of `construct_uint!` trips a few style lints
+#![allow(clippy::assign_op_pattern, clippy::manual_div_ceil)]
+
+
+use uint::construct_uint;
+
+construct_uint! {
+    /// 256-bit unsigned integer, wide enough for the product of two `u128`.
+    pub struct U256(4);
+}
+
+/// Rounding direction of share conversions. Conversions always round in favour of the vault:
+/// down when the user receives shares or assets, up when the user has to provide them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rounding {
+    Down,
+    Up,
+}
+
+/// `a * b / denominator` with a 256-bit intermediate, rounded as requested.
+/// Panics if `denominator` is 0 or the result does not fit in a `u128`.
+pub fn mul_div(a: u128, b: u128, denominator: u128, rounding: Rounding) -> u128 {
+    assert!(denominator > 0, "ERR_DIVISION_BY_ZERO");
+    let product = U256::from(a) * U256::from(b);
+    let denominator = U256::from(denominator);
+    let mut result = product / denominator;
+    if rounding == Rounding::Up && !(product % denominator).is_zero() {
+        result += U256::one();
+    }
+    assert!(result <= U256::from(u128::MAX), "ERR_MUL_DIV_OVERFLOW");
+    result.as_u128()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_mul_div() {
+        assert_eq!(mul_div(10, 3, 4, Rounding::Down), 7);
+        assert_eq!(mul_div(10, 3, 4, Rounding::Up), 8);
+        assert_eq!(mul_div(10, 2, 4, Rounding::Up), 5);
+        assert_eq!(mul_div(0, u128::MAX, 1, Rounding::Up), 0);
+    }
+
+    #[test]
+    fn test_mul_div_wide_intermediate() {
+        // 1B wNEAR worth of assets and shares, the product alone overflows u128
+        let amount = 1_000_000_000 * 10u128.pow(24);
+        assert_eq!(mul_div(amount, amount, amount, Rounding::Down), amount);
+        assert_eq!(mul_div(u128::MAX, u128::MAX, u128::MAX, Rounding::Up), u128::MAX);
+        assert_eq!(mul_div(u128::MAX, u128::MAX - 1, u128::MAX, Rounding::Up), u128::MAX - 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MUL_DIV_OVERFLOW")]
+    fn test_mul_div_result_overflow() {
+        mul_div(u128::MAX, 2, 1, Rounding::Down);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_DIVISION_BY_ZERO")]
+    fn test_mul_div_by_zero() {
+        mul_div(1, 1, 0, Rounding::Down);
+    }
+
+    fn near_max() -> impl Strategy<Value = u128> {
+        prop_oneof![any::<u128>(), (0..=u64::MAX as u128).prop_map(|offset| u128::MAX - offset)]
+    }
+
+    proptest! {
+        #[test]
+        fn prop_mul_div_matches_exact(a in near_max(), b in near_max(), denominator in near_max()) {
+            prop_assume!(denominator > 0);
+            let product = U256::from(a) * U256::from(b);
+            let exact_down = product / U256::from(denominator);
+            prop_assume!(exact_down < U256::from(u128::MAX));
+
+            let down = mul_div(a, b, denominator, Rounding::Down);
+            let up = mul_div(a, b, denominator, Rounding::Up);
+            prop_assert_eq!(U256::from(down), exact_down);
+            // up is down, plus one when there is a remainder
+            prop_assert!(up == down || up == down + 1);
+            prop_assert_eq!(up == down, (product % U256::from(denominator)).is_zero());
+        }
+
+        #[test]
+        fn prop_mul_div_by_own_factor(a in any::<u128>(), b in 1..=u128::MAX) {
+            prop_assert_eq!(mul_div(a, b, b, Rounding::Down), a);
+            prop_assert_eq!(mul_div(a, b, b, Rounding::Up), a);
+        }
+
+        #[test]
+        fn prop_mul_div_by_larger_factor(a in 1..=u128::MAX, b in 1..=u128::MAX) {
+            // a * b / max(a, b) is exactly the smaller factor
+            prop_assert_eq!(mul_div(a, b, a.max(b), Rounding::Down), a.min(b));
+        }
+    }
+}
@@ -0,0 +1,482 @@
+use near_sdk::json_types::U128;
+use near_sdk::{
+    assert_one_yocto, env, ext_contract, near, AccountId, Gas, PromiseError, PromiseOrValue,
+};
+
+use crate::config::MethodClass;
+use crate::events::{MtEvent, MtTransfer};
+use crate::stash::Stash;
+use crate::token_vault::TokenVault;
+use crate::{Contract, ContractExt};
+
+/// NEP-245 token id of vault shares, `stash_id:token_account` such as `0:usdt.tether-token.near`.
+pub type TokenId = String;
+
+const GAS_FOR_MT_RESOLVE_TRANSFER: Gas = Gas::from_tgas(10);
+/// Least gas `mt_transfer_call` needs, the receiver gets whatever is left on top.
+const GAS_FOR_MT_TRANSFER_CALL: Gas = Gas::from_tgas(30);
+
+/// Token id of the shares of the `token_id` vault of a stash.
+pub fn share_token_id(stash_id: u64, token_id: &AccountId) -> TokenId {
+    format!("{}:{}", stash_id, token_id)
+}
+
+/// Stash id and vault token of a share token id. Account ids never contain `:`.
+pub fn parse_share_token_id(token_id: &str) -> (u64, AccountId) {
+    token_id
+        .split_once(':')
+        .and_then(|(stash_id, token_id)| Some((stash_id.parse().ok()?, token_id.parse().ok()?)))
+        .unwrap_or_else(|| env::panic_str("ERR_INVALID_TOKEN_ID"))
+}
+
+#[near(serializers = [json])]
+pub struct MtToken {
+    pub token_id: TokenId,
+    /// Always `None`, shares are fungible.
+    pub owner_id: Option<AccountId>,
+}
+
+/// NEP-245 receiver interface called by `mt_transfer_call` and `mt_batch_transfer_call`.
+#[allow(dead_code)]
+#[ext_contract(ext_mt_receiver)]
+pub trait MultiTokenReceiver {
+    /// Returns the amounts of each token to send back to their previous owners.
+    fn mt_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_ids: Vec<AccountId>,
+        token_ids: Vec<TokenId>,
+        amounts: Vec<U128>,
+        msg: String,
+    ) -> PromiseOrValue<Vec<U128>>;
+}
+
+/// Vault shares as NEP-245 multi tokens. Shares can only be sent to members allowed to
+/// deposit into the stash, see `Stash::transfer_shares`. Approvals are not supported and
+/// transfers are paused along with withdrawals.
+#[near]
+impl Contract {
+    /// Sends shares of the caller to `receiver_id`. Requires 1 yoctoNEAR.
+    #[payable]
+    pub fn mt_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        amount: U128,
+        approval: Option<(AccountId, u64)>,
+        memo: Option<String>,
+    ) {
+        assert_one_yocto();
+        assert!(approval.is_none(), "ERR_APPROVALS_NOT_SUPPORTED");
+        self.internal_mt_transfer(&env::predecessor_account_id(), &receiver_id, &[token_id], &[amount], memo);
+    }
+
+    #[payable]
+    pub fn mt_batch_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_ids: Vec<TokenId>,
+        amounts: Vec<U128>,
+        approvals: Option<Vec<Option<(AccountId, u64)>>>,
+        memo: Option<String>,
+    ) {
+        assert_one_yocto();
+        assert_no_approvals(approvals);
+        self.internal_mt_transfer(&env::predecessor_account_id(), &receiver_id, &token_ids, &amounts, memo);
+    }
+
+    /// Sends shares and calls `mt_on_transfer` on the receiver, the shares it does not use are
+    /// returned. Requires 1 yoctoNEAR. Returns the shares the receiver kept.
+    #[payable]
+    pub fn mt_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        amount: U128,
+        approval: Option<(AccountId, u64)>,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<Vec<U128>> {
+        self.mt_batch_transfer_call(
+            receiver_id,
+            vec![token_id],
+            vec![amount],
+            approval.map(|approval| vec![Some(approval)]),
+            memo,
+            msg,
+        )
+    }
+
+    #[payable]
+    pub fn mt_batch_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        token_ids: Vec<TokenId>,
+        amounts: Vec<U128>,
+        approvals: Option<Vec<Option<(AccountId, u64)>>>,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<Vec<U128>> {
+        assert_one_yocto();
+        assert_no_approvals(approvals);
+        assert!(env::prepaid_gas() >= GAS_FOR_MT_TRANSFER_CALL, "ERR_NOT_ENOUGH_GAS");
+        let sender_id = env::predecessor_account_id();
+        self.internal_mt_transfer(&sender_id, &receiver_id, &token_ids, &amounts, memo);
+
+        let previous_owner_ids = vec![sender_id.clone(); token_ids.len()];
+        ext_mt_receiver::ext(receiver_id.clone())
+            .mt_on_transfer(sender_id, previous_owner_ids.clone(), token_ids.clone(), amounts.clone(), msg)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_MT_RESOLVE_TRANSFER)
+                    .with_unused_gas_weight(0)
+                    .mt_resolve_transfer(previous_owner_ids, receiver_id, token_ids, amounts),
+            )
+            .into()
+    }
+
+    /// Returns the shares the receiver did not use to their previous owners, all of them if
+    /// `mt_on_transfer` failed. Returns the shares the receiver kept, by token.
+    #[private]
+    pub fn mt_resolve_transfer(
+        &mut self,
+        previous_owner_ids: Vec<AccountId>,
+        receiver_id: AccountId,
+        token_ids: Vec<TokenId>,
+        amounts: Vec<U128>,
+        #[callback_result] unused: Result<Vec<U128>, PromiseError>,
+    ) -> Vec<U128> {
+        let unused = unused.unwrap_or_else(|_| amounts.clone());
+        let mut refunds = Vec::new();
+        let mut used = Vec::with_capacity(amounts.len());
+        for (index, ((token_id, amount), owner_id)) in token_ids.iter().zip(&amounts).zip(&previous_owner_ids).enumerate() {
+            let unused = unused.get(index).map_or(0, |unused| unused.0.min(amount.0));
+            let (stash_id, vault_token) = parse_share_token_id(token_id);
+            let returned = match self.stashes.get(&stash_id).map(Stash::from) {
+                Some(mut stash) if unused > 0 => {
+                    let prev_storage = env::storage_usage();
+                    let returned = stash.return_shares(&receiver_id, owner_id, &vault_token, unused);
+                    self.internal_save_stash(stash_id, stash);
+                    self.internal_record_storage(owner_id, prev_storage);
+                    returned
+                }
+                _ => 0,
+            };
+            if returned > 0 {
+                refunds.push(MtTransfer {
+                    old_owner_id: receiver_id.clone(),
+                    new_owner_id: owner_id.clone(),
+                    token_ids: vec![token_id.clone()],
+                    amounts: vec![U128(returned)],
+                    memo: None,
+                });
+            }
+            used.push(U128(amount.0 - returned));
+        }
+        if !refunds.is_empty() {
+            MtEvent::MtTransfer(refunds).emit();
+        }
+        used
+    }
+
+    /// Shares of the account, 0 for unknown stashes or vaults.
+    pub fn mt_balance_of(&self, account_id: AccountId, token_id: TokenId) -> U128 {
+        U128(self.internal_get_share_vault(&token_id).map_or(0, |vault| vault.get_shares(&account_id)))
+    }
+
+    pub fn mt_batch_balance_of(&self, account_id: AccountId, token_ids: Vec<TokenId>) -> Vec<U128> {
+        token_ids.into_iter().map(|token_id| self.mt_balance_of(account_id.clone(), token_id)).collect()
+    }
+
+    /// Shares issued by the vault, `None` for unknown stashes or vaults.
+    pub fn mt_supply(&self, token_id: TokenId) -> Option<U128> {
+        self.internal_get_share_vault(&token_id).map(|vault| U128(vault.get_shares_total_supply()))
+    }
+
+    pub fn mt_batch_supply(&self, token_ids: Vec<TokenId>) -> Vec<Option<U128>> {
+        token_ids.into_iter().map(|token_id| self.mt_supply(token_id)).collect()
+    }
+
+    pub fn mt_token(&self, token_ids: Vec<TokenId>) -> Vec<Option<MtToken>> {
+        token_ids
+            .into_iter()
+            .map(|token_id| {
+                self.internal_get_share_vault(&token_id).map(|_| MtToken { token_id, owner_id: None })
+            })
+            .collect()
+    }
+}
+
+fn assert_no_approvals(approvals: Option<Vec<Option<(AccountId, u64)>>>) {
+    assert!(approvals.unwrap_or_default().iter().all(Option::is_none), "ERR_APPROVALS_NOT_SUPPORTED");
+}
+
+// internal methods
+impl Contract {
+    /// Moves shares of each token from `sender_id` to `receiver_id`, charging any new storage
+    /// to the sender.
+    fn internal_mt_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        token_ids: &[TokenId],
+        amounts: &[U128],
+        memo: Option<String>,
+    ) {
+        assert_ne!(sender_id, receiver_id, "ERR_SELF_TRANSFER");
+        assert!(!token_ids.is_empty() && token_ids.len() == amounts.len(), "ERR_INVALID_AMOUNTS");
+        let prev_storage = env::storage_usage();
+        for (token_id, amount) in token_ids.iter().zip(amounts) {
+            let (stash_id, vault_token) = parse_share_token_id(token_id);
+            let mut stash = self.internal_get_stash(stash_id);
+            self.assert_not_paused(&stash, MethodClass::Withdrawals);
+            stash.transfer_shares(sender_id, receiver_id, &vault_token, amount.0);
+            self.internal_save_stash(stash_id, stash);
+        }
+        self.internal_check_storage(sender_id, prev_storage);
+        MtEvent::MtTransfer(vec![MtTransfer {
+            old_owner_id: sender_id.clone(),
+            new_owner_id: receiver_id.clone(),
+            token_ids: token_ids.to_vec(),
+            amounts: amounts.to_vec(),
+            memo,
+        }])
+        .emit();
+    }
+
+    fn internal_get_share_vault(&self, token_id: &str) -> Option<TokenVault> {
+        let (stash_id, vault_token) = parse_share_token_id(token_id);
+        self.stashes.get(&stash_id).map(Stash::from)?.get_vault(&vault_token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, NearToken};
+
+    use super::*;
+    use crate::events::{MtBurn, MtMint};
+    use crate::stash::Role;
+    use crate::test_utils::{add_member, get_context, get_mt_events, new_contract};
+
+    fn usdt() -> AccountId {
+        "usdt-token.near".parse().unwrap()
+    }
+
+    fn usdc() -> AccountId {
+        "usdc-token.near".parse().unwrap()
+    }
+
+    fn usdt_shares() -> TokenId {
+        share_token_id(0, &usdt())
+    }
+
+    fn usdc_shares() -> TokenId {
+        share_token_id(0, &usdc())
+    }
+
+    /// Stash 0 with usdt and usdc vaults where accounts(0) pooled 2_000 of each, accounts(1) is
+    /// a registered contributor and accounts(2) a viewer. Attaches 1 yoctoNEAR.
+    fn setup(context: &mut VMContextBuilder) -> Contract {
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = new_contract(None);
+        contract.storage_deposit(None, None);
+        contract.storage_deposit(Some(accounts(1)), None);
+        contract.create_stash("Roommates".to_string());
+        add_member(context, &mut contract, 0, accounts(1), Role::Contributor);
+        add_member(context, &mut contract, 0, accounts(2), Role::Viewer);
+        for token_id in [usdt(), usdc()] {
+            contract.add_token_to_stash(0, token_id.clone());
+            testing_env!(context.predecessor_account_id(token_id).attached_deposit(NearToken::from_yoctonear(0)).build());
+            contract.ft_on_transfer(accounts(0), U128(2_000), r#"{"stash_id": 0, "add_liquidity": true}"#.to_string());
+            testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(1)).build());
+        }
+        contract
+    }
+
+    #[test]
+    fn test_share_token_id() {
+        assert_eq!(usdt_shares(), "0:usdt-token.near");
+        assert_eq!(parse_share_token_id("12:usdt-token.near"), (12, usdt()));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_TOKEN_ID")]
+    fn test_invalid_token_id() {
+        let mut context = get_context(accounts(0));
+        let contract = setup(&mut context);
+        contract.mt_balance_of(accounts(0), "usdt-token.near".to_string());
+    }
+
+    #[test]
+    fn test_liquidity_mints_and_burns_shares() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.remove_liquidity_from_stash(0, usdt(), 500_000);
+        contract.add_liquidity_to_stash(0, usdt(), 300);
+        assert_eq!(
+            get_mt_events(),
+            vec![
+                MtEvent::MtBurn(vec![MtBurn {
+                    owner_id: accounts(0),
+                    token_ids: vec![usdt_shares()],
+                    amounts: vec![U128(500_000)],
+                    memo: None,
+                }]),
+                MtEvent::MtMint(vec![MtMint {
+                    owner_id: accounts(0),
+                    token_ids: vec![usdt_shares()],
+                    amounts: vec![U128(300_000)],
+                    memo: None,
+                }]),
+            ]
+        );
+        assert_eq!(contract.mt_supply(usdt_shares()), Some(U128(1_800_000)));
+        assert_eq!(contract.mt_batch_supply(vec![usdc_shares(), share_token_id(1, &usdt())]), vec![Some(U128(2_000_000)), None]);
+        let tokens = contract.mt_token(vec![usdc_shares(), share_token_id(0, &accounts(3))]);
+        assert_eq!(tokens[0].as_ref().map(|token| token.token_id.clone()), Some(usdc_shares()));
+        assert!(tokens[1].is_none());
+    }
+
+    #[test]
+    fn test_mt_transfer() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.mt_transfer(accounts(1), usdt_shares(), U128(500_000), None, Some("rent".to_string()));
+        assert_eq!(
+            get_mt_events().last(),
+            Some(&MtEvent::MtTransfer(vec![MtTransfer {
+                old_owner_id: accounts(0),
+                new_owner_id: accounts(1),
+                token_ids: vec![usdt_shares()],
+                amounts: vec![U128(500_000)],
+                memo: Some("rent".to_string()),
+            }]))
+        );
+        assert_eq!(contract.mt_balance_of(accounts(0), usdt_shares()).0, 1_500_000);
+        assert_eq!(contract.mt_balance_of(accounts(1), usdt_shares()).0, 500_000);
+        assert_eq!(contract.get_shares(0, usdt(), accounts(1)).0, 500_000);
+        assert_eq!(contract.mt_supply(usdt_shares()), Some(U128(2_000_000)));
+
+        // the receiver can redeem the shares like their own
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.remove_liquidity_from_stash(0, usdt(), 500_000);
+        assert_eq!(contract.get_deposit(0, accounts(1), usdt()).0, 500);
+    }
+
+    #[test]
+    fn test_mt_batch_transfer() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.mt_batch_transfer(accounts(1), vec![usdt_shares(), usdc_shares()], vec![U128(100), U128(200)], None, None);
+        assert_eq!(
+            contract.mt_batch_balance_of(accounts(1), vec![usdt_shares(), usdc_shares(), share_token_id(0, &accounts(3))]),
+            vec![U128(100), U128(200), U128(0)]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_RECEIVER_NOT_AUTHORIZED")]
+    fn test_mt_transfer_to_viewer() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.mt_transfer(accounts(2), usdt_shares(), U128(500), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_RECEIVER_NOT_AUTHORIZED")]
+    fn test_mt_transfer_to_non_member() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.mt_transfer(accounts(3), usdt_shares(), U128(500), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_ENOUGH_SHARES")]
+    fn test_mt_transfer_not_enough_shares() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.mt_transfer(accounts(1), usdt_shares(), U128(2_000_001), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_STASH_CLOSED")]
+    fn test_mt_transfer_dissolving() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.dissolve_stash(0, Some(1));
+        contract.mt_transfer(accounts(1), usdt_shares(), U128(500), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_PAUSED")]
+    fn test_mt_transfer_paused() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.pause(Some(0), vec![MethodClass::Withdrawals]);
+        contract.mt_transfer(accounts(1), usdt_shares(), U128(500), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_APPROVALS_NOT_SUPPORTED")]
+    fn test_mt_transfer_with_approval() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.mt_transfer(accounts(1), usdt_shares(), U128(500), Some((accounts(3), 0)), None);
+    }
+
+    #[test]
+    fn test_mt_resolve_transfer_returns_unused() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.mt_batch_transfer(accounts(1), vec![usdt_shares(), usdc_shares()], vec![U128(500_000), U128(500_000)], None, None);
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        let used = contract.mt_resolve_transfer(
+            vec![accounts(0), accounts(0)],
+            accounts(1),
+            vec![usdt_shares(), usdc_shares()],
+            vec![U128(500_000), U128(500_000)],
+            Ok(vec![U128(200_000), U128(0)]),
+        );
+        assert_eq!(used, vec![U128(300_000), U128(500_000)]);
+        assert_eq!(contract.mt_balance_of(accounts(0), usdt_shares()).0, 1_700_000);
+        assert_eq!(contract.mt_balance_of(accounts(1), usdt_shares()).0, 300_000);
+        assert_eq!(contract.mt_balance_of(accounts(1), usdc_shares()).0, 500_000);
+        assert_eq!(
+            get_mt_events().last(),
+            Some(&MtEvent::MtTransfer(vec![MtTransfer {
+                old_owner_id: accounts(1),
+                new_owner_id: accounts(0),
+                token_ids: vec![usdt_shares()],
+                amounts: vec![U128(200_000)],
+                memo: None,
+            }]))
+        );
+    }
+
+    #[test]
+    fn test_mt_resolve_transfer_failed_call() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context);
+        contract.mt_transfer(accounts(1), usdt_shares(), U128(500_000), None, None);
+        // the receiver already redeemed some of the shares, only the rest can be returned
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.remove_liquidity_from_stash(0, usdt(), 100_000);
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        let used = contract.mt_resolve_transfer(
+            vec![accounts(0)],
+            accounts(1),
+            vec![usdt_shares()],
+            vec![U128(500_000)],
+            Err(PromiseError::Failed),
+        );
+        assert_eq!(used, vec![U128(100_000)]);
+        assert_eq!(contract.mt_balance_of(accounts(0), usdt_shares()).0, 1_900_000);
+        assert_eq!(contract.mt_balance_of(accounts(1), usdt_shares()).0, 0);
+    }
+}
@@ -0,0 +1,470 @@
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{env, log, near, AccountId, Gas, PromiseError};
+
+use crate::config::MethodClass;
+use crate::stash::Stash;
+use crate::{Contract, ContractExt};
+
+/// How long a proposal stays open for votes, in nanoseconds.
+pub const PROPOSAL_PERIOD: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+
+const GAS_FOR_ON_PAYOUT_COMPLETE: Gas = Gas::from_tgas(10);
+
+/// Action a proposal executes once approved.
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProposalKind {
+    /// Pays `amount` of the pooled assets of the token's vault out to `receiver_id`.
+    Payout { token_id: AccountId, receiver_id: AccountId, amount: U128 },
+    /// Adds a vault for an allowlisted token.
+    AddToken { token_id: AccountId },
+    /// Sets the number of approvals needed to pass a proposal.
+    ChangeThreshold { threshold: u32 },
+    /// Removes a member, their deposits and shares are untouched.
+    RemoveMember { account_id: AccountId },
+    /// Starts dissolving the stash, see `Contract::dissolve_stash`.
+    Dissolve,
+}
+
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProposalStatus {
+    InProgress,
+    /// Passed and executed.
+    Approved,
+    Rejected,
+    /// Not decided within `PROPOSAL_PERIOD`.
+    Expired,
+}
+
+#[near(serializers = [json])]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Vote {
+    Approve,
+    Reject,
+}
+
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Proposal {
+    pub id: u64,
+    pub proposer: AccountId,
+    pub kind: ProposalKind,
+    pub status: ProposalStatus,
+    pub approvals: Vec<AccountId>,
+    pub rejections: Vec<AccountId>,
+    /// Block timestamp of the submission, in nanoseconds.
+    pub submission_time: U64,
+}
+
+#[near]
+impl Contract {
+    /// Submits a proposal to the stash, approved by the caller. Executes right away if that
+    /// already meets the threshold, e.g. in a stash with a single contributor.
+    pub fn add_proposal(&mut self, stash_id: u64, kind: ProposalKind) -> Proposal {
+        let prev_storage = env::storage_usage();
+        if let ProposalKind::AddToken { token_id } = &kind {
+            assert!(self.is_supported_token(token_id), "Token is not on the allowed list");
+        }
+        let mut stash = self.internal_get_stash(stash_id);
+        let proposal = stash.add_proposal(kind);
+        self.internal_execute_proposal(stash_id, stash, &proposal);
+        self.internal_check_storage(&env::predecessor_account_id(), prev_storage);
+        proposal
+    }
+
+    /// Approves or rejects a proposal, executing it once the threshold is met.
+    pub fn act_proposal(&mut self, stash_id: u64, proposal_id: u64, vote: Vote) -> Proposal {
+        let prev_storage = env::storage_usage();
+        let mut stash = self.internal_get_stash(stash_id);
+        let proposal = stash.act_proposal(proposal_id, vote);
+        self.internal_execute_proposal(stash_id, stash, &proposal);
+        self.internal_check_storage(&env::predecessor_account_id(), prev_storage);
+        proposal
+    }
+
+    /// Puts the assets back into the vault if the payout transfer failed.
+    /// Returns whether the transfer succeeded.
+    #[private]
+    pub fn on_payout_complete(
+        &mut self,
+        stash_id: u64,
+        proposal_id: u64,
+        token_id: AccountId,
+        amount: U128,
+        #[callback_result] result: Result<(), PromiseError>,
+    ) -> bool {
+        if result.is_ok() {
+            return true;
+        }
+        log!("Payout of proposal {} in stash {} failed, restoring {} {}", proposal_id, stash_id, amount.0, token_id);
+        match self.stashes.get(&stash_id).map(Stash::from) {
+            Some(mut stash) => {
+                stash.restore_payout(&token_id, amount.0);
+                self.internal_save_stash(stash_id, stash);
+            }
+            None => log!("ERR_STASH_NOT_FOUND {}, unable to restore {} {}", stash_id, amount.0, token_id),
+        }
+        false
+    }
+
+    pub fn get_proposal(&self, stash_id: u64, proposal_id: u64) -> Option<Proposal> {
+        let stash = self.internal_get_stash(stash_id);
+        stash.get_proposal(proposal_id)
+    }
+
+    pub fn get_proposals(&self, stash_id: u64, from_index: u64, limit: u64) -> Vec<Proposal> {
+        let stash = self.internal_get_stash(stash_id);
+        stash.get_proposals(from_index, limit)
+    }
+
+    /// Approvals currently needed to pass a proposal in the stash.
+    pub fn get_threshold(&self, stash_id: u64) -> u32 {
+        let stash = self.internal_get_stash(stash_id);
+        stash.get_threshold()
+    }
+}
+
+// internal methods
+impl Contract {
+    /// Writes the stash back, executing the proposal first if it was just approved.
+    fn internal_execute_proposal(&mut self, stash_id: u64, mut stash: Stash, proposal: &Proposal) {
+        if proposal.status != ProposalStatus::Approved {
+            self.internal_save_stash(stash_id, stash);
+            return;
+        }
+        if let ProposalKind::Payout { .. } = proposal.kind {
+            self.assert_not_paused(&stash, MethodClass::Withdrawals);
+        }
+        stash.execute_proposal(proposal);
+        self.internal_save_stash(stash_id, stash);
+        match &proposal.kind {
+            ProposalKind::Payout { token_id, receiver_id, amount } => {
+                self.internal_transfer(receiver_id, token_id, *amount).then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(GAS_FOR_ON_PAYOUT_COMPLETE)
+                        .on_payout_complete(stash_id, proposal.id, token_id.clone(), *amount),
+                );
+            }
+            ProposalKind::RemoveMember { account_id } => {
+                self.internal_remove_account_stash(account_id, stash_id);
+            }
+            ProposalKind::AddToken { .. } | ProposalKind::ChangeThreshold { .. } | ProposalKind::Dissolve => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, NearToken};
+
+    use super::*;
+    use crate::lock::{EarlyWithdrawalPenalty, PenaltyReceiver, StashLock};
+    use crate::stash::{Role, StashStatus};
+    use crate::test_utils::{add_member, new_contract};
+
+    fn usdt() -> AccountId {
+        "usdt-token.near".parse().unwrap()
+    }
+
+    /// Stash 0 owned by accounts(0) with contributors accounts(1) and accounts(2), and 1_000 usdt
+    /// pooled in its vault by the owner.
+    fn setup(context: &mut VMContextBuilder) -> Contract {
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = new_contract(None);
+        contract.storage_deposit(None, None);
+        contract.storage_deposit(Some(accounts(1)), None);
+        contract.storage_deposit(Some(accounts(2)), None);
+        contract.create_stash("Roommates".to_string());
+        contract.add_token_to_stash(0, usdt());
+        add_member(context, &mut contract, 0, accounts(1), Role::Contributor);
+        add_member(context, &mut contract, 0, accounts(2), Role::Contributor);
+
+        testing_env!(context.predecessor_account_id(usdt()).attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract.ft_on_transfer(accounts(0), U128(1_000), r#"{"stash_id": 0, "add_liquidity": true}"#.to_string());
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract
+    }
+
+    fn payout(amount: u128) -> ProposalKind {
+        ProposalKind::Payout { token_id: usdt(), receiver_id: accounts(3), amount: U128(amount) }
+    }
+
+    fn vault_assets(contract: &Contract) -> u128 {
+        contract.internal_get_stash(0).get_vault_assets(&usdt())
+    }
+
+    #[test]
+    fn test_default_threshold_is_majority() {
+        let mut context = VMContextBuilder::new();
+        let contract = setup(&mut context);
+        assert_eq!(contract.get_threshold(0), 2);
+    }
+
+    #[test]
+    fn test_single_contributor_executes_immediately() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = new_contract(None);
+        contract.storage_deposit(None, None);
+        contract.create_stash("Solo".to_string());
+
+        let proposal = contract.add_proposal(0, ProposalKind::AddToken { token_id: usdt() });
+        assert_eq!(proposal.status, ProposalStatus::Approved);
+        assert!(contract.internal_get_stash(0).accepts_deposit(&accounts(0), &usdt()));
+    }
+
+    #[test]
+    fn test_payout_needs_approval() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+
+        let proposal = contract.add_proposal(0, payout(400));
+        assert_eq!(proposal.status, ProposalStatus::InProgress);
+        assert_eq!(proposal.approvals, vec![accounts(0)]);
+        assert_eq!(vault_assets(&contract), 1_000);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let proposal = contract.act_proposal(0, proposal.id, Vote::Approve);
+        assert_eq!(proposal.status, ProposalStatus::Approved);
+        assert_eq!(vault_assets(&contract), 600);
+        assert_eq!(contract.get_proposal(0, proposal.id).unwrap().status, ProposalStatus::Approved);
+    }
+
+    #[test]
+    fn test_failed_payout_restores_vault() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        let proposal = contract.add_proposal(0, payout(400));
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.act_proposal(0, proposal.id, Vote::Approve);
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        assert!(!contract.on_payout_complete(0, proposal.id, usdt(), U128(400), Err(PromiseError::Failed)));
+        assert_eq!(vault_assets(&contract), 1_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_ENOUGH_ASSETS")]
+    fn test_payout_more_than_pooled() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        let proposal = contract.add_proposal(0, payout(1_001));
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.act_proposal(0, proposal.id, Vote::Approve);
+    }
+
+    #[test]
+    fn test_reject() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        let proposal = contract.add_proposal(0, payout(400));
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let proposal = contract.act_proposal(0, proposal.id, Vote::Reject);
+        assert_eq!(proposal.status, ProposalStatus::InProgress);
+
+        // with 2 of 3 against, the threshold of 2 can no longer be reached
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        let proposal = contract.act_proposal(0, proposal.id, Vote::Reject);
+        assert_eq!(proposal.status, ProposalStatus::Rejected);
+        assert_eq!(vault_assets(&contract), 1_000);
+    }
+
+    #[test]
+    fn test_expired() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        let proposal = contract.add_proposal(0, payout(400));
+
+        testing_env!(context.predecessor_account_id(accounts(1)).block_timestamp(PROPOSAL_PERIOD).build());
+        let proposal = contract.act_proposal(0, proposal.id, Vote::Approve);
+        assert_eq!(proposal.status, ProposalStatus::Expired);
+        assert_eq!(proposal.approvals, vec![accounts(0)]);
+        assert_eq!(vault_assets(&contract), 1_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_PROPOSAL_NOT_IN_PROGRESS")]
+    fn test_vote_on_decided_proposal() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        let proposal = contract.add_proposal(0, payout(400));
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.act_proposal(0, proposal.id, Vote::Approve);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.act_proposal(0, proposal.id, Vote::Approve);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ALREADY_VOTED")]
+    fn test_vote_twice() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        let proposal = contract.add_proposal(0, payout(400));
+        contract.act_proposal(0, proposal.id, Vote::Approve);
+    }
+
+    #[test]
+    #[should_panic(expected = "Caller is not authorized")]
+    fn test_viewer_cannot_vote() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        add_member(&mut context, &mut contract, 0, accounts(3), Role::Viewer);
+        let proposal = contract.add_proposal(0, payout(400));
+
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.act_proposal(0, proposal.id, Vote::Approve);
+    }
+
+    #[test]
+    fn test_change_threshold() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        let proposal = contract.add_proposal(0, ProposalKind::ChangeThreshold { threshold: 3 });
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.act_proposal(0, proposal.id, Vote::Approve);
+        assert_eq!(contract.get_threshold(0), 3);
+
+        let proposal = contract.add_proposal(0, payout(400));
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        let proposal = contract.act_proposal(0, proposal.id, Vote::Approve);
+        assert_eq!(proposal.status, ProposalStatus::InProgress);
+    }
+
+    #[test]
+    fn test_threshold_capped_when_members_leave() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        let proposal = contract.add_proposal(0, ProposalKind::ChangeThreshold { threshold: 3 });
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.act_proposal(0, proposal.id, Vote::Approve);
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.leave_stash(0);
+        assert_eq!(contract.get_threshold(0), 2);
+
+        // approvals of both remaining contributors pass a payout again
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        let proposal = contract.add_proposal(0, payout(400));
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let proposal = contract.act_proposal(0, proposal.id, Vote::Approve);
+        assert_eq!(proposal.status, ProposalStatus::Approved);
+        assert_eq!(vault_assets(&contract), 600);
+    }
+
+    #[test]
+    fn test_votes_of_removed_members_do_not_count() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let proposal = contract.add_proposal(0, payout(400));
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.revoke_role(0, accounts(1));
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        let proposal = contract.act_proposal(0, proposal.id, Vote::Approve);
+        assert_eq!(proposal.status, ProposalStatus::InProgress);
+        assert_eq!(vault_assets(&contract), 1_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_STASH_LOCKED")]
+    fn test_payout_refused_while_locked() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        let penalty = EarlyWithdrawalPenalty { penalty_bps: 500, receiver: PenaltyReceiver::Members };
+        contract.set_stash_lock(0, Some(StashLock { unlock_at: U64(1_000), early_withdrawal_penalty: Some(penalty) }));
+        let proposal = contract.add_proposal(0, payout(400));
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.act_proposal(0, proposal.id, Vote::Approve);
+    }
+
+    #[test]
+    fn test_payout_after_unlock() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.set_stash_lock(0, Some(StashLock { unlock_at: U64(1_000), early_withdrawal_penalty: None }));
+        let proposal = contract.add_proposal(0, payout(400));
+        testing_env!(context.predecessor_account_id(accounts(1)).block_timestamp(1_000).build());
+        let proposal = contract.act_proposal(0, proposal.id, Vote::Approve);
+        assert_eq!(proposal.status, ProposalStatus::Approved);
+        assert_eq!(vault_assets(&contract), 600);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_THRESHOLD_TOO_HIGH")]
+    fn test_threshold_above_contributors() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.add_proposal(0, ProposalKind::ChangeThreshold { threshold: 4 });
+    }
+
+    #[test]
+    fn test_remove_member() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        let proposal = contract.add_proposal(0, ProposalKind::RemoveMember { account_id: accounts(2) });
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.act_proposal(0, proposal.id, Vote::Approve);
+
+        assert_eq!(contract.internal_get_stash(0).get_role(&accounts(2)), None);
+        assert!(contract.get_stashes_for_account(accounts(2)).is_empty());
+        assert_eq!(contract.get_threshold(0), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_CANNOT_REMOVE_OWNER")]
+    fn test_remove_owner() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.add_proposal(0, ProposalKind::RemoveMember { account_id: accounts(0) });
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_CANNOT_REMOVE_OWNER")]
+    fn test_remove_member_who_became_owner() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let proposal = contract.add_proposal(0, ProposalKind::RemoveMember { account_id: accounts(2) });
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.transfer_ownership(0, accounts(2));
+        contract.act_proposal(0, proposal.id, Vote::Approve);
+    }
+
+    #[test]
+    fn test_dissolve_proposal() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        let proposal = contract.add_proposal(0, ProposalKind::Dissolve);
+        assert_eq!(contract.get_stash(0).unwrap().status, StashStatus::Open);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.act_proposal(0, proposal.id, Vote::Approve);
+        assert_eq!(contract.get_stash(0).unwrap().status, StashStatus::Dissolving { next_index: 0 });
+
+        // any member can carry the dissolution on
+        assert!(contract.dissolve_stash(0, None));
+        assert_eq!(contract.get_stash(0).unwrap().status, StashStatus::Closed);
+        assert_eq!(contract.get_deposit(0, accounts(0), usdt()).0, 1_000);
+    }
+
+    #[test]
+    fn test_get_proposals() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.add_proposal(0, payout(100));
+        contract.add_proposal(0, payout(200));
+        contract.add_proposal(0, payout(300));
+
+        let proposals = contract.get_proposals(0, 1, 5);
+        assert_eq!(proposals.iter().map(|proposal| proposal.id).collect::<Vec<_>>(), vec![2, 3]);
+    }
+}
@@ -1,130 +1,991 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::{LookupMap, UnorderedMap};
-use near_sdk::json_types::U128;
+use near_sdk::json_types::{U128, U64};
 use near_sdk::{
-    assert_one_yocto, env, AccountId, NearToken, PanicOnDefault, Promise
+    assert_one_yocto, env, near, AccountId, PanicOnDefault
 };
 use near_contract_standards::fungible_token::Balance;
 
-use crate::token_vault::TokenVault;
+use crate::config::{MethodClass, PauseFlags};
+use crate::dex::StrandedSwap;
+use crate::events::DivvyEvent;
+use crate::goal::{percent_of, Goal, GoalProgress, TargetProgress};
+use crate::lock::{LockStatus, PenaltyReceiver, StashLock};
+use crate::proposal::{Proposal, ProposalKind, ProposalStatus, Vote, PROPOSAL_PERIOD};
+use crate::token_vault::{TokenVault, VaultFees, VersionedTokenVault};
+use crate::vesting::VestingSchedule;
+use crate::StorageKey;
+
+/// Role of a stash member, ordered from least to most privileged.
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Can see the stash but not move funds into it.
+    Viewer,
+    /// Can deposit and add liquidity.
+    Contributor,
+    /// Can also add vaults and manage contributors and viewers.
+    Admin,
+    /// Can also manage admins and remove the stash. There is exactly one owner.
+    Owner,
+}
+
+/// Lifecycle of a stash, see `Stash::dissolve`.
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StashStatus {
+    Open,
+    /// Winding down, the shares of the members from `next_index` on are still to be redeemed.
+    Dissolving { next_index: u64 },
+    /// Dissolved, members can only withdraw their deposits.
+    Closed,
+}
 
 #[derive(BorshSerialize, BorshDeserialize, PanicOnDefault)]
 pub struct Stash {
     id: u64,
     name: String,
-    vaults: LookupMap<AccountId, TokenVault>,
+    vaults: UnorderedMap<AccountId, VersionedTokenVault>,
     /// Balances of deposited tokens for each account.
     deposited_amounts: LookupMap<AccountId, UnorderedMap<AccountId, Balance>>,
-    // Authorized users
-    authorized_users: LookupMap<AccountId, bool>,
+    /// Members and their roles.
+    members: UnorderedMap<AccountId, Role>,
+    /// Swaps started with `debit_for_swap` whose output was not credited yet, stranded ones included.
+    pending_swaps: u64,
+    /// Swap outputs the exchange failed to send back, see `Contract::retry_swap_withdraw`.
+    stranded_swaps: Vec<StrandedSwap>,
+    /// Proposals by id, see `add_proposal`.
+    proposals: UnorderedMap<u64, Proposal>,
+    last_proposal_id: u64,
+    /// Approvals needed to pass a proposal, `None` requires a majority of the contributors.
+    threshold: Option<u32>,
+    status: StashStatus,
+    /// Method classes paused for this stash only.
+    paused: PauseFlags,
+    goal: Option<Goal>,
+    /// Block timestamp the current goal was reached at.
+    goal_reached_at: Option<u64>,
+    lock: Option<StashLock>,
+}
+
+/// Borsh layouts of `Stash`. Stashes are stored tagged with their layout so a changed layout
+/// only needs a variant for the previous one, upgraded to the current as it is read. Stashes of
+/// the first release were stored untagged, `Contract::migrate` rewrites them.
+#[near(serializers = [borsh])]
+pub enum VersionedStash {
+    Current(Stash),
+}
+
+impl From<VersionedStash> for Stash {
+    fn from(stash: VersionedStash) -> Self {
+        match stash {
+            VersionedStash::Current(stash) => stash,
+        }
+    }
+}
+
+impl From<Stash> for VersionedStash {
+    fn from(stash: Stash) -> Self {
+        VersionedStash::Current(stash)
+    }
 }
 
-#[allow(dead_code)] //TODO
 impl Stash {
     pub fn new(id: u64, name: String) -> Self {
-        let mut authorized_users = LookupMap::new(b"a".to_vec());
-        authorized_users.insert(&env::predecessor_account_id(), &true);
+        let mut members = UnorderedMap::new(StorageKey::Members { stash_id: id });
+        members.insert(&env::predecessor_account_id(), &Role::Owner);
         Self {
             id,
             name,
-            vaults: LookupMap::new(b"v".to_vec()),
-            deposited_amounts: LookupMap::new(b"d".to_vec()),
-            authorized_users,
+            vaults: UnorderedMap::new(StorageKey::Vaults { stash_id: id }),
+            deposited_amounts: LookupMap::new(StorageKey::Deposits { stash_id: id }),
+            members,
+            pending_swaps: 0,
+            stranded_swaps: Vec::new(),
+            proposals: UnorderedMap::new(StorageKey::Proposals { stash_id: id }),
+            last_proposal_id: 0,
+            threshold: None,
+            status: StashStatus::Open,
+            paused: PauseFlags::default(),
+            goal: None,
+            goal_reached_at: None,
+            lock: None,
         }
     }
 
-    /// Adds new TokenVault with given token
-    /// Attached NEAR should be enough to cover the added storage.
+    /// Adds new TokenVault with given token, admins only.
     pub fn add_vault(&mut self, token: AccountId) {
-        self.internal_add_vault(TokenVault::new(token))
+        self.assert_open();
+        self.assert_role(&env::predecessor_account_id(), Role::Admin);
+        self.internal_add_vault(TokenVault::new(self.id, token))
     }
 
-    // invites another accountId to be an authorized contributor to the vault
-    pub fn authorize_contributor(&mut self, user: AccountId) {
-        self.authorized_users.insert(&user, &true);
+    /// Panics unless the caller may hand out `role`, the owner can appoint admins while admins
+    /// can only manage contributors and viewers. Returns the caller's role.
+    pub fn assert_can_manage(&self, role: Role) -> Role {
+        self.assert_open();
+        let caller_role = self.assert_role(&env::predecessor_account_id(), Role::Admin);
+        assert_ne!(role, Role::Owner, "ERR_USE_TRANSFER_OWNERSHIP");
+        assert!(role < caller_role, "ERR_ROLE_NOT_ALLOWED");
+        caller_role
     }
 
-    fn assert_authorized(&self, caller: AccountId) {
+    /// Changes the role of a member ranked below the caller to another one below the caller's
+    /// own, see `assert_can_manage`. New members join through invitations.
+    pub fn grant_role(&mut self, account_id: AccountId, role: Role) {
+        let caller_role = self.assert_can_manage(role);
+        let current_role = self.members.get(&account_id).expect("ERR_NOT_A_MEMBER");
+        assert!(current_role < caller_role, "ERR_ROLE_NOT_ALLOWED");
+        self.members.insert(&account_id, &role);
+        DivvyEvent::ContributorAuthorized { stash_id: self.id, account_id, role }.emit();
+    }
+
+    /// Adds `account_id` with `role`, once it accepted an invitation.
+    pub fn add_member(&mut self, account_id: AccountId, role: Role) {
+        self.assert_open();
+        assert!(self.members.get(&account_id).is_none(), "ERR_ALREADY_MEMBER");
+        self.members.insert(&account_id, &role);
+        DivvyEvent::MemberJoined { stash_id: self.id, account_id, role }.emit();
+    }
+
+    /// Removes a member ranked below the caller. Their deposits and shares are untouched.
+    pub fn revoke_role(&mut self, account_id: &AccountId) {
+        self.assert_open();
+        let caller_role = self.assert_role(&env::predecessor_account_id(), Role::Admin);
+        let role = self.members.get(account_id).expect("ERR_NOT_A_MEMBER");
+        assert!(role < caller_role, "ERR_ROLE_NOT_ALLOWED");
+        self.members.remove(account_id);
+        DivvyEvent::RoleRevoked { stash_id: self.id, account_id: account_id.clone() }.emit();
+    }
+
+    /// Makes `new_owner_id` the owner, the previous owner stays on as an admin.
+    /// Returns whether the new owner is a new member.
+    pub fn transfer_ownership(&mut self, new_owner_id: AccountId) -> bool {
+        self.assert_open();
+        let owner_id = env::predecessor_account_id();
+        self.assert_role(&owner_id, Role::Owner);
+        assert_ne!(owner_id, new_owner_id, "ERR_ALREADY_OWNER");
+        self.members.insert(&owner_id, &Role::Admin);
+        let is_new_member = self.members.insert(&new_owner_id, &Role::Owner).is_none();
+        DivvyEvent::OwnershipTransferred { stash_id: self.id, old_owner_id: owner_id, new_owner_id }.emit();
+        is_new_member
+    }
+
+    /// Removes the caller from the members, the owner has to transfer ownership first.
+    pub fn leave(&mut self) {
+        self.assert_open();
+        let account_id = env::predecessor_account_id();
+        let role = self.members.get(&account_id).expect("ERR_NOT_A_MEMBER");
+        assert_ne!(role, Role::Owner, "ERR_OWNER_CANNOT_LEAVE");
+        self.members.remove(&account_id);
+        DivvyEvent::MemberLeft { stash_id: self.id, account_id }.emit();
+    }
+
+    /// Panics unless the caller is the owner, no swap is pending and no vault has shares
+    /// outstanding, see `clear`.
+    pub fn assert_removable(&self) {
+        self.assert_owner();
+        self.assert_no_pending_swap();
         assert!(
-            self.authorized_users.get(&caller).unwrap_or(false),
-            "Caller is not authorized"
+            self.vaults.values().map(TokenVault::from).all(|vault| vault.get_shares_total_supply() == 0),
+            "ERR_VAULT_NOT_EMPTY"
         );
     }
 
-    // TODO use a virtual account here?
-    // Add deposit associated to the predecessor's virtual account for the given token
-    pub fn deposit(&mut self, token_id: AccountId) -> Balance {
-        let sender = env::predecessor_account_id();
-        self.assert_authorized(sender.clone());
-        let amount: Balance = env::attached_deposit().as_yoctonear();
-        self.internal_deposit(&sender, &token_id, amount)
+    /// Removes the deposits, share balances and membership of `account_id` before the stash is
+    /// dropped. Panics if the member still holds deposits.
+    pub fn clear_member(&mut self, account_id: &AccountId) {
+        if let Some(mut deposits) = self.deposited_amounts.remove(account_id) {
+            assert!(deposits.values().all(|amount| amount == 0), "ERR_DEPOSITS_NOT_EMPTY");
+            deposits.clear();
+        }
+        for mut vault in self.vaults.values().map(TokenVault::from) {
+            vault.clear_shares(account_id);
+        }
+        self.members.remove(account_id);
+    }
+
+    /// Removes the remaining nested collections of the stash before it is dropped, once every
+    /// member was removed with `clear_member`.
+    pub fn clear(&mut self) {
+        for mut vault in self.vaults.values().map(TokenVault::from) {
+            vault.clear_grants();
+        }
+        self.vaults.clear();
+        self.proposals.clear();
+        self.members.clear();
+    }
+
+    /// Starts winding down the stash, see `dissolve`. Deposits, liquidity, proposals and
+    /// membership changes are refused from now on.
+    pub fn start_dissolution(&mut self) {
+        self.assert_open();
+        self.status = StashStatus::Dissolving { next_index: 0 };
+        DivvyEvent::DissolutionStarted { stash_id: self.id }.emit();
+    }
+
+    /// Redeems all shares of the next `limit` members at the current share price into their
+    /// deposits, less the vault's withdrawal fee, closing the stash once every member is done.
+    /// Returns whether it is closed. Shares of accounts which already left stay redeemable
+    /// through `exit`, as do the shares of vesting grants as they vest.
+    pub fn dissolve(&mut self, limit: u64) -> bool {
+        let next_index = match self.status {
+            StashStatus::Dissolving { next_index } => next_index,
+            _ => env::panic_str("ERR_STASH_NOT_DISSOLVING"),
+        };
+        let accounts: Vec<AccountId> =
+            self.members.keys_as_vector().iter().skip(next_index as usize).take(limit as usize).collect();
+        let mut vaults: Vec<TokenVault> = self.vaults.values().map(TokenVault::from).collect();
+        for account_id in &accounts {
+            for vault in vaults.iter_mut() {
+                let shares = vault.get_shares(account_id);
+                if shares > 0 {
+                    let assets = vault.remove_liquidity(account_id, shares);
+                    self.internal_deposit(account_id, &vault.get_token_type(), assets);
+                }
+            }
+        }
+        for vault in vaults {
+            self.internal_save_vault(vault);
+        }
+
+        let next_index = next_index + accounts.len() as u64;
+        if next_index < self.members.len() {
+            self.status = StashStatus::Dissolving { next_index };
+            return false;
+        }
+        self.status = StashStatus::Closed;
+        DivvyEvent::StashDissolved { stash_id: self.id }.emit();
+        true
+    }
+
+    pub fn get_status(&self) -> StashStatus {
+        self.status
+    }
+
+    pub fn get_paused(&self) -> PauseFlags {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, methods: &[MethodClass], paused: bool) {
+        self.paused.set(methods, paused);
+    }
+
+    fn assert_open(&self) {
+        assert_eq!(self.status, StashStatus::Open, "ERR_STASH_CLOSED");
+    }
+
+    pub fn assert_owner(&self) {
+        self.assert_role(&env::predecessor_account_id(), Role::Owner);
+    }
+
+    /// Panics unless the account holds at least `role`, returns its actual role.
+    fn assert_role(&self, account_id: &AccountId, role: Role) -> Role {
+        match self.members.get(account_id) {
+            Some(member_role) if member_role >= role => member_role,
+            _ => env::panic_str("Caller is not authorized"),
+        }
     }
 
+    fn assert_authorized(&self, caller: AccountId) {
+        self.assert_role(&caller, Role::Contributor);
+    }
+
+    pub fn get_role(&self, account_id: &AccountId) -> Option<Role> {
+        self.members.get(account_id)
+    }
+
+    /// Whether the account may move funds into the stash.
+    pub fn is_authorized(&self, account_id: &AccountId) -> bool {
+        self.get_role(account_id).is_some_and(|role| role >= Role::Contributor)
+    }
+
+    /// Whether this stash can take a deposit of `token_id` from `sender_id`.
+    pub fn accepts_deposit(&self, sender_id: &AccountId, token_id: &AccountId) -> bool {
+        self.status == StashStatus::Open
+            && !self.is_goal_locked()
+            && self.is_authorized(sender_id)
+            && self.is_allowlisted_token(token_id)
+    }
+
+    /// Credits fungible tokens received through `ft_on_transfer` to the sender's deposits
+    /// and, if requested, moves them straight into the token's vault.
+    /// Returns the sender's resulting deposit balance, or the minted shares when adding liquidity.
+    pub fn ft_deposit(
+        &mut self,
+        sender_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+        add_liquidity: bool,
+    ) -> Balance {
+        let balance = self.internal_deposit(sender_id, token_id, amount);
+        self.emit_deposit(sender_id, token_id, amount);
+        if add_liquidity {
+            self.internal_add_liquidity(sender_id, token_id, amount)
+        } else {
+            balance
+        }
+    }
+
+    /// Returns the deposited balance of `token_id` for given account.
+    pub fn get_deposit(&self, account_id: &AccountId, token_id: &AccountId) -> Balance {
+        self.internal_get_deposits(account_id).get(token_id).unwrap_or(0)
+    }
 
     /// Add liquidity from already deposited amounts to given Stash.
     pub fn add_liquidity(&mut self, token_id:AccountId, amount: u128) -> u128 {
+        self.assert_open();
+        assert!(!self.is_goal_locked(), "ERR_GOAL_REACHED");
         let sender_id = env::predecessor_account_id();
         self.assert_authorized(sender_id.clone());
-        let mut stash = self.vaults.get(&token_id).expect("ERR_NO_Stash");
-        let token = stash.get_token_type();
+        self.internal_add_liquidity(&sender_id, &token_id, amount)
+    }
 
-        let deposits = self.internal_get_deposits(&sender_id);
-        let deposit = deposits.get(&token.clone()).unwrap_or(0);
-        assert!(deposit >= amount, "ERR_NOT_ENOUGH");
+    /// Remove liquidity from the Stash into the user's deposits.
+    /// Refused while the stash is locked without an early withdrawal penalty, with one the
+    /// penalty is withheld once the tokens are withdrawn.
+    pub fn remove_liquidity(&mut self, token_id:AccountId, shares: u128,) -> u128 {
+        if let Some(lock) = self.active_lock() {
+            assert!(lock.early_withdrawal_penalty.is_some(), "ERR_STASH_LOCKED");
+        }
+        let sender_id = env::predecessor_account_id();
+        self.assert_authorized(sender_id.clone());
+        self.internal_remove_liquidity(&sender_id, &token_id, shares)
+    }
+
+    /// Sets the fees of the token's vault, paid to `fee_receiver_id`. Owner only.
+    pub fn set_vault_fees(&mut self, token_id: &AccountId, fees: VaultFees, fee_receiver_id: Option<AccountId>) {
+        self.assert_open();
+        self.assert_owner();
+        let mut vault = self.get_vault(token_id).expect("ERR_NO_VAULT");
+        vault.set_fees(fees, fee_receiver_id);
+        self.internal_save_vault(vault);
+    }
+
+    /// Lowers the fees of every vault charging more than `max_fees`, returns whether any was.
+    pub fn cap_vault_fees(&mut self, max_fees: &VaultFees) -> bool {
+        let mut capped = false;
+        for token_id in self.get_vault_tokens() {
+            let mut vault = self.get_vault(&token_id).unwrap();
+            if !vault.get_fees().is_within(max_fees) {
+                let fees = vault.get_fees().capped_to(max_fees);
+                let fee_receiver_id = if fees.is_zero() { None } else { vault.get_fee_receiver_id() };
+                vault.set_fees(fees, fee_receiver_id);
+                self.internal_save_vault(vault);
+                capped = true;
+            }
+        }
+        capped
+    }
+
+    /// Moves vault shares of `token_id` from `sender_id` to `receiver_id`. Shares can only go
+    /// to members allowed to deposit, and only while the stash is open.
+    pub fn transfer_shares(&mut self, sender_id: &AccountId, receiver_id: &AccountId, token_id: &AccountId, shares: Balance) {
+        self.assert_open();
+        assert!(self.is_authorized(receiver_id), "ERR_RECEIVER_NOT_AUTHORIZED");
+        let mut vault = self.get_vault(token_id).expect("ERR_NO_VAULT");
+        vault.transfer_shares(sender_id, receiver_id, shares);
+        self.internal_save_vault(vault);
+    }
 
-        let shares = stash.add_liquidity(&sender_id, amount);
-        self.vaults.insert(&token_id, &stash);
+    /// Grants `shares` of the caller vesting to `account_id` on `schedule`, see
+    /// `TokenVault::grant_shares`. Like transfers, only to members allowed to deposit.
+    pub fn grant_vested_shares(&mut self, token_id: &AccountId, account_id: &AccountId, shares: Balance, schedule: VestingSchedule) {
+        self.assert_open();
+        assert!(self.is_authorized(account_id), "ERR_RECEIVER_NOT_AUTHORIZED");
+        let mut vault = self.get_vault(token_id).expect("ERR_NO_VAULT");
+        vault.grant_shares(&env::predecessor_account_id(), account_id, shares, schedule);
+        self.internal_save_vault(vault);
+    }
 
-        // TODO - handle supported token types. The below assumes the Stash contains only near tokens
-        //Promise::new(env::current_account_id()).transfer(NearToken::from_near(amount));
+    /// Claims the vested shares of the caller's grant, also once it left or the stash closed.
+    pub fn claim_vested(&mut self, token_id: &AccountId) -> Balance {
+        let mut vault = self.get_vault(token_id).expect("ERR_NO_VAULT");
+        let shares = vault.claim_vested(&env::predecessor_account_id());
+        self.internal_save_vault(vault);
         shares
     }
 
-    /// Remove liquidity from the Stash into the user's deposits
-    pub fn remove_liquidity(&mut self, token_id:AccountId, shares: u128,) -> u128 {
+    /// Revokes a grant of the caller to `account_id`, returns the unvested shares given back.
+    pub fn revoke_grant(&mut self, token_id: &AccountId, account_id: &AccountId) -> Balance {
+        let mut vault = self.get_vault(token_id).expect("ERR_NO_VAULT");
+        let shares = vault.revoke_grant(&env::predecessor_account_id(), account_id);
+        self.internal_save_vault(vault);
+        shares
+    }
+
+    /// Returns up to `shares` a receiver did not use after `mt_transfer_call` to the previous
+    /// owner, whatever their role or the status of the stash. Returns the shares moved back.
+    pub fn return_shares(&mut self, receiver_id: &AccountId, owner_id: &AccountId, token_id: &AccountId, shares: Balance) -> Balance {
+        let Some(mut vault) = self.get_vault(token_id) else {
+            return 0;
+        };
+        let shares = shares.min(vault.get_shares(receiver_id));
+        if shares > 0 {
+            vault.transfer_shares(receiver_id, owner_id, shares);
+            self.internal_save_vault(vault);
+        }
+        shares
+    }
+
+    /// Withdraws given token from the deposits of given user.
+    /// Only updates the accounting, the caller is responsible for transferring the returned amount.
+    /// While the stash is locked the early withdrawal penalty is withheld from it, to be paid out
+    /// with `collect_penalty` once the transfer succeeded.
+    pub fn withdraw(&mut self, token_id: AccountId, amount: U128) -> Balance {
+        assert_one_yocto();
+        let amount: u128 = amount.into();
+        let sender_id: AccountId = env::predecessor_account_id();
+        let role = self.assert_role(&sender_id, Role::Contributor);
+        let remaining = self.internal_debit_deposit(&sender_id, &token_id, amount);
+        let penalty = self.internal_early_withdrawal_penalty(&sender_id, &token_id, amount);
+
+        //if sender's balance is zero, deauthorize the contributor, admins and the owner stay on
+        if remaining == 0 && self.internal_get_deposits(&sender_id).is_empty() && role == Role::Contributor {
+            self.members.remove(&sender_id);
+            self.deposited_amounts.remove(&sender_id);
+            DivvyEvent::MemberLeft { stash_id: self.id, account_id: sender_id }.emit();
+        }
+        amount - penalty
+    }
+
+    /// Redeems all shares of `token_id` of a former member and takes out their whole deposit
+    /// of it, members use `remove_liquidity` and `withdraw` instead. Locks apply like to them.
+    /// Only updates the accounting like `withdraw`, returns the amount to transfer and the early
+    /// withdrawal penalty withheld from it.
+    pub fn exit(&mut self, token_id: &AccountId) -> (Balance, Balance) {
+        assert_one_yocto();
         let sender_id = env::predecessor_account_id();
+        assert!(self.members.get(&sender_id).is_none(), "ERR_STILL_A_MEMBER");
+        if let Some(lock) = self.active_lock() {
+            assert!(lock.early_withdrawal_penalty.is_some(), "ERR_STASH_LOCKED");
+        }
+        let shares = self.get_vault(token_id).map_or(0, |mut vault| {
+            // vested shares of a grant leave with the rest
+            vault.claim_vested(&sender_id);
+            let shares = vault.get_shares(&sender_id);
+            self.internal_save_vault(vault);
+            shares
+        });
+        if shares > 0 {
+            self.internal_remove_liquidity(&sender_id, token_id, shares);
+        }
+        let amount = self.get_deposit(&sender_id, token_id);
+        assert!(amount > 0, "ERR_NOTHING_TO_WITHDRAW");
+        self.internal_debit_deposit(&sender_id, token_id, amount);
+        let penalty = self.internal_early_withdrawal_penalty(&sender_id, token_id, amount);
+        (amount - penalty, penalty)
+    }
+
+    /// Restores a withdrawn amount to the user's deposits after the outgoing transfer failed,
+    /// `rejoin` if the withdrawal deauthorized them.
+    pub fn restore_withdrawal(&mut self, account_id: &AccountId, token_id: &AccountId, amount: Balance, rejoin: bool) {
+        if rejoin {
+            self.members.insert(account_id, &Role::Contributor);
+            DivvyEvent::ContributorAuthorized { stash_id: self.id, account_id: account_id.clone(), role: Role::Contributor }.emit();
+        }
+        self.internal_deposit(account_id, token_id, amount);
+    }
+
+    /// Pays the penalty withheld from a withdrawal of `account_id` to the receiver of the lock,
+    /// or to the members if the lock was removed in the meantime. If no shares are left by then
+    /// there are no members to pay and the penalty goes back to the deposits of `account_id`.
+    pub fn collect_penalty(&mut self, account_id: &AccountId, token_id: &AccountId, amount: Balance) {
+        let receiver = self
+            .lock
+            .as_ref()
+            .and_then(|lock| lock.early_withdrawal_penalty.as_ref())
+            .map_or(PenaltyReceiver::Members, |penalty| penalty.receiver.clone());
+        match &receiver {
+            PenaltyReceiver::Members => {
+                let mut vault = self.get_vault(token_id).expect("ERR_NO_VAULT");
+                if vault.get_shares_total_supply() == 0 {
+                    self.internal_deposit(account_id, token_id, amount);
+                    return;
+                }
+                vault.add_assets(amount);
+                self.internal_save_vault(vault);
+                self.internal_check_goal();
+            }
+            PenaltyReceiver::Treasury { account_id: treasury_id } => {
+                self.internal_deposit(treasury_id, token_id, amount);
+            }
+        }
+        DivvyEvent::EarlyWithdrawalPenalty {
+            stash_id: self.id,
+            account_id: account_id.clone(),
+            token_id: token_id.clone(),
+            amount: U128(amount),
+            receiver,
+        }
+        .emit();
+    }
+
+    /// Takes `amount` of `token_in` out of the sender's deposits to be swapped into `token_out`.
+    pub fn debit_for_swap(&mut self, sender_id: &AccountId, token_in: &AccountId, token_out: &AccountId, amount: Balance) {
+        self.assert_open();
         self.assert_authorized(sender_id.clone());
-        let mut stash = self.vaults.get(&token_id).expect("ERR_NO_Stash");
+        assert!(self.is_allowlisted_token(token_out), "ERR_NO_VAULT_FOR_TOKEN_OUT");
+        self.internal_debit_deposit(sender_id, token_in, amount);
+        self.pending_swaps += 1;
+    }
+
+    /// Credits the output of a swap, or the refunded input, to the user's deposits.
+    pub fn credit_swap(&mut self, account_id: &AccountId, token_id: &AccountId, amount: Balance) -> Balance {
+        self.internal_deposit(account_id, token_id, amount)
+    }
+
+    /// Marks a swap started with `debit_for_swap` as done, nothing more will be credited for it.
+    pub fn finish_swap(&mut self) {
+        self.pending_swaps = self.pending_swaps.saturating_sub(1);
+    }
+
+    pub fn assert_no_pending_swap(&self) {
+        assert_eq!(self.pending_swaps, 0, "ERR_SWAP_PENDING");
+    }
+
+    /// Keeps a swap output the exchange failed to send back, the swap stays pending until
+    /// it is withdrawn with `take_stranded_swap`.
+    pub fn strand_swap(&mut self, swap: StrandedSwap) {
+        match self.stranded_swaps.iter_mut().find(|stranded| {
+            stranded.account_id == swap.account_id && stranded.dex_id == swap.dex_id && stranded.token_id == swap.token_id
+        }) {
+            Some(stranded) => {
+                stranded.amount.0 += swap.amount.0;
+                // merged into a swap already counted as pending
+                self.finish_swap();
+            }
+            None => self.stranded_swaps.push(swap),
+        }
+    }
+
+    /// Removes and returns the stranded output of `token_id` owed to `account_id`.
+    pub fn take_stranded_swap(&mut self, account_id: &AccountId, token_id: &AccountId) -> StrandedSwap {
+        let index = self
+            .stranded_swaps
+            .iter()
+            .position(|stranded| &stranded.account_id == account_id && &stranded.token_id == token_id)
+            .expect("ERR_NO_STRANDED_SWAP");
+        self.stranded_swaps.swap_remove(index)
+    }
+
+    pub fn get_stranded_swaps(&self, account_id: &AccountId) -> Vec<StrandedSwap> {
+        self.stranded_swaps.iter().filter(|stranded| &stranded.account_id == account_id).cloned().collect()
+    }
+
+    /// Submits a proposal on behalf of the caller, who approves it right away.
+    /// Contributors and above can propose. Returns the stored proposal.
+    pub fn add_proposal(&mut self, kind: ProposalKind) -> Proposal {
+        self.assert_open();
+        let proposer = env::predecessor_account_id();
+        self.assert_authorized(proposer.clone());
+        match &kind {
+            ProposalKind::Payout { token_id, amount, .. } => {
+                assert!(amount.0 > 0, "ERR_ZERO_AMOUNT");
+                assert!(self.is_allowlisted_token(token_id), "ERR_NO_VAULT");
+            }
+            ProposalKind::AddToken { token_id } => {
+                assert!(!self.is_allowlisted_token(token_id), "ERR_VAULT_EXISTS");
+            }
+            ProposalKind::ChangeThreshold { threshold } => {
+                assert!(*threshold > 0, "ERR_INVALID_THRESHOLD");
+                assert!(*threshold as u64 <= self.voting_members(), "ERR_THRESHOLD_TOO_HIGH");
+            }
+            ProposalKind::RemoveMember { account_id } => {
+                let role = self.members.get(account_id).expect("ERR_NOT_A_MEMBER");
+                assert_ne!(role, Role::Owner, "ERR_CANNOT_REMOVE_OWNER");
+            }
+            ProposalKind::Dissolve => {}
+        }
+
+        self.last_proposal_id += 1;
+        let mut proposal = Proposal {
+            id: self.last_proposal_id,
+            proposer: proposer.clone(),
+            kind,
+            status: ProposalStatus::InProgress,
+            approvals: vec![proposer],
+            rejections: vec![],
+            submission_time: env::block_timestamp().into(),
+        };
+        self.internal_update_status(&mut proposal);
+        self.proposals.insert(&proposal.id, &proposal);
+        DivvyEvent::ProposalAdded {
+            stash_id: self.id,
+            proposal_id: proposal.id,
+            proposer_id: proposal.proposer.clone(),
+            kind: proposal.kind.clone(),
+            status: proposal.status,
+        }
+        .emit();
+        proposal
+    }
+
+    /// Records the caller's vote. Once the threshold is reached the proposal is approved, or
+    /// rejected as soon as enough members voted against it to make that impossible. Proposals left open past
+    /// `PROPOSAL_PERIOD` expire instead. Returns the updated proposal.
+    pub fn act_proposal(&mut self, proposal_id: u64, vote: Vote) -> Proposal {
+        self.assert_open();
+        let voter = env::predecessor_account_id();
+        self.assert_authorized(voter.clone());
+        let mut proposal = self.proposals.get(&proposal_id).expect("ERR_NO_PROPOSAL");
+        assert_eq!(proposal.status, ProposalStatus::InProgress, "ERR_PROPOSAL_NOT_IN_PROGRESS");
+
+        if env::block_timestamp() >= proposal.submission_time.0 + PROPOSAL_PERIOD {
+            proposal.status = ProposalStatus::Expired;
+        } else {
+            assert!(
+                !proposal.approvals.contains(&voter) && !proposal.rejections.contains(&voter),
+                "ERR_ALREADY_VOTED"
+            );
+            match vote {
+                Vote::Approve => proposal.approvals.push(voter.clone()),
+                Vote::Reject => proposal.rejections.push(voter.clone()),
+            }
+            self.internal_update_status(&mut proposal);
+        }
+        self.proposals.insert(&proposal_id, &proposal);
+        DivvyEvent::ProposalVoted {
+            stash_id: self.id,
+            proposal_id,
+            account_id: voter,
+            vote,
+            status: proposal.status,
+        }
+        .emit();
+        proposal
+    }
+
+    /// Applies the stash side of an approved proposal. A payout is taken out of the vault's
+    /// assets, sending the tokens is up to the caller. Payouts are refused while the stash is
+    /// locked, there is no member to charge the early withdrawal penalty.
+    pub fn execute_proposal(&mut self, proposal: &Proposal) {
+        assert_eq!(proposal.status, ProposalStatus::Approved, "ERR_PROPOSAL_NOT_APPROVED");
+        match &proposal.kind {
+            ProposalKind::Payout { token_id, amount, .. } => {
+                assert!(self.active_lock().is_none(), "ERR_STASH_LOCKED");
+                let mut vault = self.get_vault(token_id).expect("ERR_NO_VAULT");
+                vault.payout(amount.0);
+                self.internal_save_vault(vault);
+            }
+            ProposalKind::AddToken { token_id } => {
+                self.internal_add_vault(TokenVault::new(self.id, token_id.clone()));
+            }
+            ProposalKind::ChangeThreshold { threshold } => {
+                self.threshold = Some(*threshold);
+            }
+            ProposalKind::RemoveMember { account_id } => {
+                // the member may have left or taken over ownership since the proposal was made
+                let role = self.members.get(account_id).expect("ERR_NOT_A_MEMBER");
+                assert_ne!(role, Role::Owner, "ERR_CANNOT_REMOVE_OWNER");
+                self.members.remove(account_id);
+            }
+            ProposalKind::Dissolve => {
+                self.start_dissolution();
+            }
+        }
+    }
+
+    /// Puts the assets of a payout back into the vault after the transfer failed.
+    pub fn restore_payout(&mut self, token_id: &AccountId, amount: Balance) {
+        let mut vault = self.get_vault(token_id).expect("ERR_NO_VAULT");
+        vault.restore_payout(amount);
+        self.internal_save_vault(vault);
+    }
+
+    /// Pooled assets in the token's vault, 0 without a vault.
+    pub fn get_vault_assets(&self, token_id: &AccountId) -> Balance {
+        self.get_vault(token_id).map_or(0, |vault| vault.get_total_assets())
+    }
+
+    pub fn get_id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn get_member_count(&self) -> u64 {
+        self.members.len()
+    }
+
+    /// Members with their roles.
+    pub fn get_members(&self) -> Vec<(AccountId, Role)> {
+        self.members.to_vec()
+    }
+
+    pub fn get_vault(&self, token_id: &AccountId) -> Option<TokenVault> {
+        self.vaults.get(token_id).map(TokenVault::from)
+    }
+
+    /// Tokens the stash has a vault for.
+    pub fn get_vault_tokens(&self) -> Vec<AccountId> {
+        self.vaults.keys().collect()
+    }
+
+    /// Deposited balances of given account, by token.
+    pub fn get_deposits(&self, account_id: &AccountId) -> Vec<(AccountId, Balance)> {
+        self.internal_get_deposits(account_id).to_vec()
+    }
+
+    pub fn get_proposal(&self, proposal_id: u64) -> Option<Proposal> {
+        self.proposals.get(&proposal_id)
+    }
+
+    pub fn get_proposals(&self, from_index: u64, limit: u64) -> Vec<Proposal> {
+        self.proposals
+            .values()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .collect()
+    }
+
+    /// Sets or removes the savings goal, admins only. Every target needs a vault.
+    pub fn set_goal(&mut self, goal: Option<Goal>) {
+        self.assert_open();
+        self.assert_role(&env::predecessor_account_id(), Role::Admin);
+        if let Some(goal) = &goal {
+            goal.assert_valid();
+            for target in &goal.targets {
+                assert!(self.vaults.get(&target.token_id).is_some(), "ERR_NO_VAULT");
+            }
+        }
+        self.goal = goal.clone();
+        self.goal_reached_at = None;
+        DivvyEvent::GoalUpdated { stash_id: self.id, goal }.emit();
+        self.internal_check_goal();
+    }
+
+    pub fn get_goal_progress(&self) -> Option<GoalProgress> {
+        let goal = self.goal.clone()?;
+        let targets: Vec<TargetProgress> = goal
+            .targets
+            .iter()
+            .map(|target| {
+                let total_assets = self.get_vault_assets(&target.token_id);
+                TargetProgress {
+                    token_id: target.token_id.clone(),
+                    target: target.amount,
+                    total_assets: U128(total_assets),
+                    percent: percent_of(total_assets, target.amount.0),
+                }
+            })
+            .collect();
+        let percent = targets.iter().map(|target| target.percent as usize).sum::<usize>() / targets.len();
+        let missed_deadline = self.goal_reached_at.is_none()
+            && goal.deadline.is_some_and(|deadline| env::block_timestamp() >= deadline.0);
+        Some(GoalProgress {
+            goal,
+            targets,
+            percent: percent as u8,
+            reached_at: self.goal_reached_at.map(U64),
+            missed_deadline,
+        })
+    }
+
+    /// Whether the goal was reached and locks further deposits.
+    fn is_goal_locked(&self) -> bool {
+        self.goal_reached_at.is_some() && self.goal.as_ref().is_some_and(|goal| goal.lock_on_reached)
+    }
+
+    /// Locks the funds until `lock.unlock_at` or removes an expired lock, owner only.
+    /// An active lock can only be extended, its penalty stays as it is.
+    pub fn set_lock(&mut self, lock: Option<StashLock>) {
+        self.assert_open();
+        self.assert_owner();
+        if let Some(lock) = &lock {
+            lock.assert_valid();
+        }
+        if let Some(current) = self.active_lock() {
+            assert!(
+                lock.as_ref().is_some_and(|lock| lock.unlock_at.0 >= current.unlock_at.0
+                    && lock.early_withdrawal_penalty == current.early_withdrawal_penalty),
+                "ERR_LOCK_ACTIVE"
+            );
+        }
+        self.lock = lock.clone();
+        DivvyEvent::StashLockUpdated { stash_id: self.id, lock }.emit();
+    }
+
+    pub fn get_lock_status(&self) -> Option<LockStatus> {
+        self.lock.as_ref().map(StashLock::get_status)
+    }
+
+    /// The lock, while it still holds.
+    fn active_lock(&self) -> Option<&StashLock> {
+        self.lock.as_ref().filter(|lock| lock.is_active())
+    }
+
+    /// Approvals currently needed to pass a proposal. A set threshold is capped at the number
+    /// of voting members, who may have left since it was set.
+    pub fn get_threshold(&self) -> u32 {
+        let voting_members = self.voting_members();
+        match self.threshold {
+            Some(threshold) => threshold.min(voting_members as u32),
+            None => (voting_members / 2 + 1) as u32,
+        }
+    }
+
+    /// Members allowed to vote, contributors and above.
+    fn voting_members(&self) -> u64 {
+        self.members.values().filter(|role| *role >= Role::Contributor).count() as u64
+    }
+
+    /// Votes of accounts which are no longer voting members don't count.
+    fn internal_update_status(&self, proposal: &mut Proposal) {
+        let threshold = self.get_threshold() as u64;
+        if self.count_votes(&proposal.approvals) >= threshold {
+            proposal.status = ProposalStatus::Approved;
+        } else if self.count_votes(&proposal.rejections) > self.voting_members().saturating_sub(threshold) {
+            proposal.status = ProposalStatus::Rejected;
+        }
+    }
+
+    fn count_votes(&self, voters: &[AccountId]) -> u64 {
+        voters
+            .iter()
+            .filter(|voter| self.members.get(voter).is_some_and(|role| role >= Role::Contributor))
+            .count() as u64
+    }
+}
+
+/// Internal methods implementation.
+impl Stash {
+    /// Adds given TokenVault to the stash.
+    fn internal_add_vault(&mut self, vault: TokenVault) {
+        let token_id = vault.get_token_type();
+        self.internal_save_vault(vault);
+        DivvyEvent::VaultAdded { stash_id: self.id, token_id }.emit();
+    }
+
+    fn internal_save_vault(&mut self, vault: TokenVault) {
+        self.vaults.insert(&vault.get_token_type(), &vault.into());
+    }
+
+    /// Marks the goal reached once every vault holds its target.
+    fn internal_check_goal(&mut self) {
+        let Some(goal) = &self.goal else {
+            return;
+        };
+        if self.goal_reached_at.is_none()
+            && goal.targets.iter().all(|target| self.get_vault_assets(&target.token_id) >= target.amount.0)
+        {
+            self.goal_reached_at = Some(env::block_timestamp());
+            DivvyEvent::GoalReached { stash_id: self.id }.emit();
+        }
+    }
+
+    fn emit_deposit(&self, account_id: &AccountId, token_id: &AccountId, amount: Balance) {
+        DivvyEvent::Deposit {
+            stash_id: self.id,
+            account_id: account_id.clone(),
+            token_id: token_id.clone(),
+            amount: U128(amount),
+        }
+        .emit();
+    }
+
+    // TODO Must we use virtual accounts?
+    fn internal_deposit(
+        &mut self,
+        sender_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+    ) -> Balance {
+        assert!(
+            self.is_allowlisted_token(token_id),
+            "{}",
+            "Token is not on the allowed list"
+        );
+        let mut deposits = self.internal_get_deposits(sender_id);
+        deposits.insert(&token_id.clone(), &(amount + deposits.get(token_id).unwrap_or(0)));
+         self.deposited_amounts.insert(sender_id, &deposits);
+        deposits.get(token_id).unwrap()
+    }
+
+    /// Moves `amount` from the sender's deposits into the vault and returns the minted shares.
+    fn internal_add_liquidity(&mut self, sender_id: &AccountId, token_id: &AccountId, amount: Balance) -> Balance {
+        let mut stash = self.get_vault(token_id).expect("ERR_NO_Stash");
+        let token = stash.get_token_type();
+
+        let mut deposits = self.internal_get_deposits(sender_id);
+        let deposit = deposits.get(&token).unwrap_or(0);
+        assert!(deposit >= amount, "ERR_NOT_ENOUGH");
+        deposits.insert(&token, &(deposit - amount));
+        self.deposited_amounts.insert(sender_id, &deposits);
+
+        let shares = stash.add_liquidity(sender_id, amount);
+        self.internal_save_vault(stash);
+        self.internal_check_goal();
+        shares
+    }
+
+    /// Redeems `shares` of the sender into their deposits and returns the redeemed amount.
+    fn internal_remove_liquidity(&mut self, sender_id: &AccountId, token_id: &AccountId, shares: Balance) -> Balance {
+        let mut stash = self.get_vault(token_id).expect("ERR_NO_Stash");
+        // only vested shares of a grant can be redeemed
+        stash.claim_vested(sender_id);
         let new_balance = stash.remove_liquidity(
-            &sender_id,
+            sender_id,
             shares,
         );
-        self.vaults.insert(&token_id, &stash);
         let tokens = stash.get_token_type();
-        let mut deposits = self.internal_get_deposits(&sender_id);
+        self.internal_save_vault(stash);
+        let mut deposits = self.internal_get_deposits(sender_id);
         let current_balance = deposits.get(&tokens).unwrap_or(0);
         deposits.insert(&tokens, &(current_balance + new_balance));
-        self.deposited_amounts.insert(&sender_id, &deposits);
+        self.deposited_amounts.insert(sender_id, &deposits);
 
         new_balance
     }
 
-    /// Withdraws given token from the deposits of given user.
-    pub fn withdraw(&mut self, token_id: AccountId, amount: U128) {
-        assert_one_yocto();
-        let amount: u128 = amount.into();
-        let sender_id: AccountId = env::predecessor_account_id();
-        self.assert_authorized(sender_id.clone());
-        let mut deposits: UnorderedMap<AccountId, u128> = self.deposited_amounts.get(&sender_id).unwrap();
+    /// Subtracts `amount` from the user's deposits of the token and returns what remains.
+    fn internal_debit_deposit(&mut self, sender_id: &AccountId, token_id: &AccountId, amount: Balance) -> Balance {
+        let mut deposits = self.internal_get_deposits(sender_id);
         let available_amount: u128 = deposits
-            .get(&token_id)
+            .get(token_id)
             .expect("ERR_NO_TOKEN");
-        println!("available_amount vs amount: {}, {}", available_amount, amount);
         assert!(available_amount >= amount, "ERR_NOT_ENOUGH");
-        if available_amount == amount {
-            deposits.remove(&token_id);
-
-            //if sender's balance is zero, deauthrozize the user
-            if deposits.is_empty() {
-                self.authorized_users.remove(&sender_id);
-            }
+        let remaining = available_amount - amount;
+        if remaining == 0 {
+            deposits.remove(token_id);
         } else {
-            deposits.insert(&token_id.clone(), &(available_amount - amount));
+            deposits.insert(token_id, &remaining);
+        }
+        self.deposited_amounts.insert(sender_id, &deposits);
+        remaining
+    }
+
+    /// Penalty withheld from a withdrawal of `amount` while the stash is locked, the treasury
+    /// collecting the penalties pays none. Panics if the lock has no penalty, or the penalty goes
+    /// to the members and no shares are left to take it.
+    fn internal_early_withdrawal_penalty(&self, account_id: &AccountId, token_id: &AccountId, amount: Balance) -> Balance {
+        let Some(lock) = self.active_lock() else {
+            return 0;
+        };
+        let penalty = lock.early_withdrawal_penalty.as_ref().expect("ERR_STASH_LOCKED");
+        if penalty.is_receiver(account_id) {
+            return 0;
+        }
+        if penalty.receiver == PenaltyReceiver::Members {
+            let shares = self.get_vault(token_id).map_or(0, |vault| vault.get_shares_total_supply());
+            assert!(shares > 0, "ERR_STASH_LOCKED");
         }
-        self.deposited_amounts.insert(&sender_id, &deposits);
+        penalty.penalty_of(amount)
+    }
+
+    fn is_allowlisted_token(&self, token_id: &AccountId) -> bool {
+        self.vaults.get(token_id).is_some()
+    }
 
-         // TODO - handle supported token types. The below assumes the Stash contains only near tokens
-        Promise::new(sender_id).transfer(NearToken::from_near(amount));
+    /// Returns current balances across all tokens for given user.
+    fn internal_get_deposits(&self, sender_id: &AccountId) -> UnorderedMap<AccountId, Balance> {
+        self.deposited_amounts
+            .get(sender_id)
+            .unwrap_or_else(|| {
+                UnorderedMap::new(StorageKey::AccountDeposits { stash_id: self.id, account_id: sender_id.clone() })
+            })
     }
 }
 
@@ -132,7 +993,8 @@ impl Stash {
 mod tests {
     use super::*;
     use near_sdk::test_utils::{accounts, VMContextBuilder};
-    use near_sdk::testing_env;
+    use near_sdk::{testing_env, NearToken};
+    use crate::test_utils::get_events;
 
     fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
         let mut builder = VMContextBuilder::new();
@@ -150,20 +1012,57 @@ mod tests {
 
         //add a new vault to the Stash
         contract.add_vault(token_id.clone());
+        contract.add_member(accounts(1), Role::Contributor);
         let amount = 100;
 
         //simulate deposit
-        contract.internal_deposit(&accounts(0), &token_id, amount);
+        contract.internal_deposit(&accounts(1), &token_id, amount);
 
         // Withdraw
-        testing_env!(context.attached_deposit(NearToken::from_yoctonear(1)).build());
-        contract.withdraw(token_id.clone(), U128(amount.into()));
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_yoctonear(1)).build());
+        contract.withdraw(token_id.clone(), U128(amount));
 
         // Check balances
-        let updated_deposits = contract.deposited_amounts.get(&accounts(0)).unwrap();
-        assert_eq!(updated_deposits.get(&accounts(0)), None);
-        assert_eq!(contract.authorized_users.get(&accounts(0)), None);
+        assert!(contract.deposited_amounts.get(&accounts(1)).is_none());
+        assert_eq!(contract.get_role(&accounts(1)), None);
+    }
+
+    #[test]
+    fn test_withdraw_all_keeps_owner() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = Stash::new(1, "501c3 donations for 2025".to_string());
+        let token_id: AccountId = "usdt-token.near".parse().unwrap();
+        contract.add_vault(token_id.clone());
+        contract.internal_deposit(&accounts(0), &token_id, 100);
+
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(1)).build());
+        contract.withdraw(token_id.clone(), U128(100));
+        assert_eq!(contract.get_role(&accounts(0)), Some(Role::Owner));
+    }
+
+    #[test]
+    fn test_restore_withdrawal() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+
+        let mut contract = Stash::new(1, "501c3 donations for 2025".to_string());
+        let token_id: AccountId = "usdt-token.near".parse().unwrap();
+        contract.add_vault(token_id.clone());
+        contract.add_member(accounts(1), Role::Contributor);
+        contract.internal_deposit(&accounts(1), &token_id, 100);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_yoctonear(1)).build());
+        assert_eq!(contract.withdraw(token_id.clone(), U128(100)), 100);
+        assert_eq!(contract.get_deposit(&accounts(1), &token_id), 0);
+        assert!(!contract.is_authorized(&accounts(1)));
+
+        // the transfer failed, the deposit is put back
+        contract.restore_withdrawal(&accounts(1), &token_id, 100, true);
+        assert_eq!(contract.get_deposit(&accounts(1), &token_id), 100);
+        assert!(contract.is_authorized(&accounts(1)));
     }
+
     #[test]
     #[should_panic(expected = "ERR_NOT_ENOUGH")]
     fn test_withdraw_insufficient_balance() {
@@ -189,7 +1088,7 @@ mod tests {
         testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
 
         let mut contract = Stash::new(1, "Weekend getaway to Miami".to_string());
-        let vault = TokenVault::new("usdt-token.near".parse().unwrap());
+        let vault = TokenVault::new(1, "usdt-token.near".parse().unwrap());
         let token_type = vault.get_token_type();
 
         let prev_storage = env::storage_usage();
@@ -200,18 +1099,6 @@ mod tests {
         assert!(new_storage > prev_storage);
     }
 
-    #[test]
-    #[should_panic(expected = "ERR_STORAGE_DEPOSIT")]
-    fn test_internal_add_vault_insufficient_deposit() {
-        let mut context = get_context(accounts(0));
-        testing_env!(context.attached_deposit(NearToken::from_near(0)).build());
-
-        let mut contract = Stash::new(1, "A week in Barcelona".to_string());
-        let vault = TokenVault::new("usdt-token.near".parse().unwrap());
-
-        contract.internal_add_vault(vault);
-    }
-
     #[test]
     fn test_authorization() {
         let sender: AccountId = "alice.near".parse().unwrap();
@@ -219,59 +1106,232 @@ mod tests {
         testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
 
         let mut stash = Stash::new(1, "A week in Barcelona".to_string());
-        let vault = TokenVault::new("usdt-token.near".parse().unwrap());
+        let vault = TokenVault::new(1, "usdt-token.near".parse().unwrap());
 
-        assert_eq!(stash.authorized_users.get(&sender).unwrap(), true);
+        assert_eq!(stash.members.get(&sender).unwrap(), Role::Owner);
         stash.internal_add_vault(vault);
 
-        testing_env!(context.attached_deposit(NearToken::from_near(100)).build());
         // Authorized user can deposit
-        let shares= stash.deposit("usdt-token.near".parse().unwrap());
-        assert_eq!(shares, 100000000000000000000000000);
+        assert!(stash.accepts_deposit(&sender, &"usdt-token.near".parse().unwrap()));
+        let balance = stash.ft_deposit(&sender, &"usdt-token.near".parse().unwrap(), 100, false);
+        assert_eq!(balance, 100);
     }
-}
 
-/// Internal methods implementation.
-impl Stash {
-    /// Adds given Stash to the list and returns it's id.
-    /// If there is not enough attached balance to cover storage, fails.
-    fn internal_add_vault(&mut self, vault: TokenVault) {
-        let prev_storage = env::storage_usage();
+    fn new_stash_with_members() -> Stash {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut stash = Stash::new(1, "Roommates".to_string());
+        stash.add_member(accounts(1), Role::Admin);
+        stash.add_member(accounts(2), Role::Contributor);
+        stash.add_member(accounts(3), Role::Viewer);
+        stash
+    }
 
-        self.vaults.insert(&vault.get_token_type(), &vault);
-        assert!(
-            (env::storage_usage() - prev_storage) as u128 * env::storage_byte_cost().as_yoctonear()
-                <= env::attached_deposit().as_yoctonear(),
-            "ERR_STORAGE_DEPOSIT"
+    #[test]
+    fn test_grant_roles() {
+        let mut stash = new_stash_with_members();
+        assert_eq!(stash.get_role(&accounts(0)), Some(Role::Owner));
+        assert_eq!(stash.get_role(&accounts(1)), Some(Role::Admin));
+        assert!(stash.is_authorized(&accounts(2)));
+        assert!(!stash.is_authorized(&accounts(3)));
+
+        // admins manage contributors and viewers
+        testing_env!(get_context(accounts(1)).build());
+        stash.grant_role(accounts(3), Role::Contributor);
+        assert_eq!(stash.get_role(&accounts(3)), Some(Role::Contributor));
+        assert_eq!(
+            get_events().last(),
+            Some(&DivvyEvent::ContributorAuthorized { stash_id: 1, account_id: accounts(3), role: Role::Contributor })
         );
     }
 
-    // TODO Must we use virtual accounts?
-    fn internal_deposit(
-        &mut self,
-        sender_id: &AccountId,
-        token_id: &AccountId,
-        amount: Balance,
-    ) -> Balance {
-        assert!(
-            self.is_allowlisted_token(token_id),
-            "{}",
-            "Token is not on the allowed list"
-        );
-        let mut deposits = self.internal_get_deposits(sender_id);
-        deposits.insert(&token_id.clone(), &(amount + deposits.get(token_id).unwrap_or(0)));
-         self.deposited_amounts.insert(sender_id, &deposits);
-        deposits.get(token_id).unwrap()
+    #[test]
+    #[should_panic(expected = "ERR_NOT_A_MEMBER")]
+    fn test_grant_role_needs_member() {
+        let mut stash = new_stash_with_members();
+        stash.grant_role(accounts(4), Role::Contributor);
     }
 
-    fn is_allowlisted_token(&self, token_id: &AccountId) -> bool {
-        self.vaults.contains_key(token_id)
+    #[test]
+    #[should_panic(expected = "ERR_ALREADY_MEMBER")]
+    fn test_add_existing_member() {
+        let mut stash = new_stash_with_members();
+        stash.add_member(accounts(3), Role::Contributor);
     }
 
-    /// Returns current balances across all tokens for given user.
-    fn internal_get_deposits(&self, sender_id: &AccountId) -> UnorderedMap<AccountId, Balance> {
-        self.deposited_amounts
-            .get(sender_id)
-            .unwrap_or_else(|| UnorderedMap::new(b"d".to_vec()))
+    #[test]
+    #[should_panic(expected = "ERR_ROLE_NOT_ALLOWED")]
+    fn test_admin_cannot_grant_admin() {
+        let mut stash = new_stash_with_members();
+        testing_env!(get_context(accounts(1)).build());
+        stash.grant_role(accounts(4), Role::Admin);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_USE_TRANSFER_OWNERSHIP")]
+    fn test_cannot_grant_owner() {
+        let mut stash = new_stash_with_members();
+        stash.grant_role(accounts(4), Role::Owner);
+    }
+
+    #[test]
+    #[should_panic(expected = "Caller is not authorized")]
+    fn test_contributor_cannot_grant() {
+        let mut stash = new_stash_with_members();
+        testing_env!(get_context(accounts(2)).build());
+        stash.grant_role(accounts(4), Role::Viewer);
+    }
+
+    #[test]
+    #[should_panic(expected = "Caller is not authorized")]
+    fn test_contributor_cannot_add_vault() {
+        let mut stash = new_stash_with_members();
+        testing_env!(get_context(accounts(2)).build());
+        stash.add_vault("usdt-token.near".parse().unwrap());
+    }
+
+    #[test]
+    fn test_revoke_role() {
+        let mut stash = new_stash_with_members();
+        testing_env!(get_context(accounts(1)).build());
+        stash.revoke_role(&accounts(2));
+        assert_eq!(stash.get_role(&accounts(2)), None);
+
+        testing_env!(get_context(accounts(0)).build());
+        stash.revoke_role(&accounts(1));
+        assert_eq!(stash.get_role(&accounts(1)), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ROLE_NOT_ALLOWED")]
+    fn test_admin_cannot_revoke_owner() {
+        let mut stash = new_stash_with_members();
+        testing_env!(get_context(accounts(1)).build());
+        stash.revoke_role(&accounts(0));
+    }
+
+    #[test]
+    fn test_transfer_ownership() {
+        let mut stash = new_stash_with_members();
+        assert!(!stash.transfer_ownership(accounts(2)));
+        assert_eq!(stash.get_role(&accounts(2)), Some(Role::Owner));
+        assert_eq!(stash.get_role(&accounts(0)), Some(Role::Admin));
+    }
+
+    #[test]
+    #[should_panic(expected = "Caller is not authorized")]
+    fn test_admin_cannot_transfer_ownership() {
+        let mut stash = new_stash_with_members();
+        testing_env!(get_context(accounts(1)).build());
+        stash.transfer_ownership(accounts(1));
+    }
+
+    #[test]
+    fn test_leave() {
+        let mut stash = new_stash_with_members();
+        testing_env!(get_context(accounts(2)).build());
+        stash.leave();
+        assert_eq!(stash.get_role(&accounts(2)), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_OWNER_CANNOT_LEAVE")]
+    fn test_owner_cannot_leave() {
+        let mut stash = new_stash_with_members();
+        stash.leave();
+    }
+
+    #[test]
+    #[should_panic(expected = "Caller is not authorized")]
+    fn test_removed_member_cannot_withdraw() {
+        let mut stash = new_stash_with_members();
+        stash.add_vault("usdt-token.near".parse().unwrap());
+        stash.internal_deposit(&accounts(2), &"usdt-token.near".parse().unwrap(), 100);
+        stash.revoke_role(&accounts(2));
+
+        testing_env!(get_context(accounts(2)).attached_deposit(NearToken::from_yoctonear(1)).build());
+        stash.withdraw("usdt-token.near".parse().unwrap(), U128(100));
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut stash = new_stash_with_members();
+        let token_id: AccountId = "usdt-token.near".parse().unwrap();
+        stash.add_vault(token_id.clone());
+        stash.internal_deposit(&accounts(2), &token_id, 2_000);
+        stash.internal_add_liquidity(&accounts(2), &token_id, 1_500);
+        stash.revoke_role(&accounts(2));
+
+        testing_env!(get_context(accounts(2)).attached_deposit(NearToken::from_yoctonear(1)).build());
+        assert_eq!(stash.exit(&token_id), (2_000, 0));
+        assert_eq!(stash.get_deposit(&accounts(2), &token_id), 0);
+        assert_eq!(stash.get_vault(&token_id).unwrap().get_shares(&accounts(2)), 0);
+    }
+
+    #[test]
+    fn test_exit_after_dissolution() {
+        let mut stash = new_stash_with_members();
+        let token_id: AccountId = "usdt-token.near".parse().unwrap();
+        stash.add_vault(token_id.clone());
+        stash.internal_deposit(&accounts(2), &token_id, 2_000);
+        stash.internal_add_liquidity(&accounts(2), &token_id, 1_500);
+        stash.revoke_role(&accounts(2));
+        stash.start_dissolution();
+        assert!(stash.dissolve(10));
+
+        // the dissolution only redeemed the members' shares
+        testing_env!(get_context(accounts(2)).attached_deposit(NearToken::from_yoctonear(1)).build());
+        assert_eq!(stash.exit(&token_id), (2_000, 0));
+        assert_eq!(stash.get_vault(&token_id).unwrap().get_shares_total_supply(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_STILL_A_MEMBER")]
+    fn test_member_cannot_exit() {
+        let mut stash = new_stash_with_members();
+        let token_id: AccountId = "usdt-token.near".parse().unwrap();
+        stash.add_vault(token_id.clone());
+        stash.internal_deposit(&accounts(2), &token_id, 100);
+
+        testing_env!(get_context(accounts(2)).attached_deposit(NearToken::from_yoctonear(1)).build());
+        stash.exit(&token_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_STASH_CLOSED")]
+    fn test_closed_stash_refuses_changes() {
+        let mut stash = new_stash_with_members();
+        stash.start_dissolution();
+        assert!(!stash.accepts_deposit(&accounts(0), &"usdt-token.near".parse().unwrap()));
+        stash.grant_role(accounts(4), Role::Viewer);
+    }
+
+    #[test]
+    fn test_stashes_do_not_share_state() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let token_id: AccountId = "usdt-token.near".parse().unwrap();
+        let mut first = Stash::new(1, "Roommates".to_string());
+        let mut second = Stash::new(2, "Family".to_string());
+        for stash in [&mut first, &mut second] {
+            stash.add_vault(token_id.clone());
+        }
+        first.add_member(accounts(1), Role::Contributor);
+
+        first.ft_deposit(&accounts(0), &token_id, 3_000, false);
+        first.ft_deposit(&accounts(0), &token_id, 2_000, true);
+        second.ft_deposit(&accounts(0), &token_id, 7_000, false);
+        first.ft_deposit(&accounts(1), &token_id, 1_000, false);
+
+        assert_eq!(first.get_deposit(&accounts(0), &token_id), 3_000);
+        assert_eq!(first.get_deposit(&accounts(1), &token_id), 1_000);
+        assert_eq!(second.get_deposit(&accounts(0), &token_id), 7_000);
+        assert_eq!(second.get_vault(&token_id).unwrap().get_shares(&accounts(0)), 0);
+        assert_eq!(first.get_vault(&token_id).unwrap().get_shares(&accounts(0)), 2_000_000);
+        assert_eq!(second.get_role(&accounts(1)), None);
+
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(1)).build());
+        second.withdraw(token_id.clone(), U128(7_000));
+        assert_eq!(first.get_deposit(&accounts(0), &token_id), 3_000);
     }
 }
@@ -0,0 +1,301 @@
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
+use near_sdk::{assert_one_yocto, env, log, near, AccountId, NearToken, Promise, StorageUsage};
+
+use crate::{Contract, ContractExt};
+
+/// Prepaid storage of a registered account.
+#[near(serializers = [borsh])]
+#[derive(Default)]
+pub struct StorageAccount {
+    /// Total NEAR deposited for storage, in yoctoNEAR.
+    pub deposit: u128,
+    /// Bytes of contract storage currently paid for by this account.
+    pub used: StorageUsage,
+}
+
+#[near]
+impl StorageManagement for Contract {
+    /// Registers the account, or tops up its storage balance when already registered.
+    /// With `registration_only` only the minimum balance is kept and the rest is refunded.
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let amount = env::attached_deposit();
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let registration_only = registration_only.unwrap_or(false);
+        let min_balance = self.storage_balance_bounds().min;
+
+        let refund = match self.storage_accounts.get(&account_id) {
+            Some(_) if registration_only => {
+                log!("The account is already registered, refunding the deposit");
+                amount
+            }
+            Some(mut storage_account) => {
+                storage_account.deposit += amount.as_yoctonear();
+                self.storage_accounts.insert(&account_id, &storage_account);
+                NearToken::from_yoctonear(0)
+            }
+            None => {
+                assert!(amount >= min_balance, "ERR_STORAGE_DEPOSIT_LESS_THAN_MIN_BALANCE");
+                let deposit = if registration_only { min_balance } else { amount };
+                self.storage_accounts.insert(
+                    &account_id,
+                    &StorageAccount { deposit: deposit.as_yoctonear(), used: 0 },
+                );
+                amount.saturating_sub(deposit)
+            }
+        };
+        if !refund.is_zero() {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+        self.internal_storage_balance_of(&account_id).unwrap()
+    }
+
+    /// Withdraws unused storage balance, all of it when `amount` is not given.
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<NearToken>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let balance = self.internal_storage_balance_of(&account_id).expect("ERR_STORAGE_NOT_REGISTERED");
+        let amount = amount.unwrap_or(balance.available);
+        assert!(amount <= balance.available, "ERR_STORAGE_WITHDRAW_TOO_MUCH");
+
+        let mut storage_account = self.storage_accounts.get(&account_id).unwrap();
+        storage_account.deposit -= amount.as_yoctonear();
+        self.storage_accounts.insert(&account_id, &storage_account);
+        if !amount.is_zero() {
+            Promise::new(account_id.clone()).transfer(amount);
+        }
+        self.internal_storage_balance_of(&account_id).unwrap()
+    }
+
+    /// Unregisters the caller and refunds the whole storage deposit.
+    /// Fails while the account still pays for stash storage, `force` is not supported.
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let storage_account = match self.storage_accounts.get(&account_id) {
+            Some(storage_account) => storage_account,
+            None => {
+                log!("The account {} is not registered", &account_id);
+                return false;
+            }
+        };
+        assert!(!force.unwrap_or(false), "ERR_FORCE_UNREGISTER_NOT_SUPPORTED");
+        assert_eq!(storage_account.used, 0, "ERR_STORAGE_IN_USE");
+
+        self.storage_accounts.remove(&account_id);
+        if storage_account.deposit > 0 {
+            Promise::new(account_id).transfer(NearToken::from_yoctonear(storage_account.deposit));
+        }
+        true
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: env::storage_byte_cost().saturating_mul(self.account_storage_usage.into()),
+            max: None,
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.internal_storage_balance_of(&account_id)
+    }
+}
+
+// internal methods
+impl Contract {
+    /// Measures the bytes needed to register the longest possible account.
+    pub(crate) fn internal_measure_account_storage_usage(&mut self) {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id: AccountId = "a".repeat(64).parse().unwrap();
+        self.storage_accounts.insert(&tmp_account_id, &StorageAccount::default());
+        self.account_storage_usage = env::storage_usage() - initial_storage_usage;
+        self.storage_accounts.remove(&tmp_account_id);
+    }
+
+    fn internal_storage_balance_of(&self, account_id: &AccountId) -> Option<StorageBalance> {
+        self.storage_accounts.get(account_id).map(|storage_account| {
+            let locked = self.internal_storage_locked(&storage_account);
+            StorageBalance {
+                total: NearToken::from_yoctonear(storage_account.deposit),
+                available: NearToken::from_yoctonear(storage_account.deposit.saturating_sub(locked)),
+            }
+        })
+    }
+
+    /// yoctoNEAR reserved for the registration and the storage used by the account.
+    fn internal_storage_locked(&self, storage_account: &StorageAccount) -> u128 {
+        (self.account_storage_usage + storage_account.used) as u128
+            * env::storage_byte_cost().as_yoctonear()
+    }
+
+    /// Charges the storage added since `prev_storage` to the account's storage balance, or
+    /// releases the freed storage back to it. Panics if the balance does not cover the usage.
+    pub(crate) fn internal_check_storage(&mut self, account_id: &AccountId, prev_storage: StorageUsage) {
+        let mut storage_account = self.storage_accounts.get(account_id).expect("ERR_STORAGE_NOT_REGISTERED");
+        Self::internal_apply_storage_delta(&mut storage_account, prev_storage);
+        let locked = self.internal_storage_locked(&storage_account);
+        assert!(
+            storage_account.deposit >= locked,
+            "ERR_STORAGE_DEPOSIT need {}, available {}",
+            locked,
+            storage_account.deposit
+        );
+        self.storage_accounts.insert(account_id, &storage_account);
+    }
+
+    /// Records the storage delta without enforcing the balance, for callbacks which must not fail.
+    pub(crate) fn internal_record_storage(&mut self, account_id: &AccountId, prev_storage: StorageUsage) {
+        if let Some(mut storage_account) = self.storage_accounts.get(account_id) {
+            Self::internal_apply_storage_delta(&mut storage_account, prev_storage);
+            self.storage_accounts.insert(account_id, &storage_account);
+        }
+    }
+
+    fn internal_apply_storage_delta(storage_account: &mut StorageAccount, prev_storage: StorageUsage) {
+        let storage_usage = env::storage_usage();
+        if storage_usage >= prev_storage {
+            storage_account.used += storage_usage - prev_storage;
+        } else {
+            storage_account.used = storage_account.used.saturating_sub(prev_storage - storage_usage);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::new_contract;
+
+    fn get_context(predecessor: AccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder
+    }
+
+    #[test]
+    fn test_storage_deposit_registers() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract(None);
+        assert!(contract.storage_balance_of(accounts(0)).is_none());
+
+        let min = contract.storage_balance_bounds().min;
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let balance = contract.storage_deposit(None, None);
+        assert_eq!(balance.total, NearToken::from_near(1));
+        assert_eq!(balance.available, NearToken::from_near(1).saturating_sub(min));
+
+        // top up
+        let balance = contract.storage_deposit(None, None);
+        assert_eq!(balance.total, NearToken::from_near(2));
+    }
+
+    #[test]
+    fn test_storage_deposit_registration_only() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract(None);
+        let min = contract.storage_balance_bounds().min;
+
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let balance = contract.storage_deposit(Some(accounts(1)), Some(true));
+        assert_eq!(balance.total, min);
+        assert!(balance.available.is_zero());
+        assert!(contract.storage_balance_of(accounts(1)).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_STORAGE_DEPOSIT_LESS_THAN_MIN_BALANCE")]
+    fn test_storage_deposit_below_min() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract(None);
+
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(1)).build());
+        contract.storage_deposit(None, None);
+    }
+
+    #[test]
+    fn test_create_stash_draws_from_storage_balance() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract(None);
+
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let before = contract.storage_deposit(None, None).available;
+
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract.create_stash("Roommates".to_string());
+        let after = contract.storage_balance_of(accounts(0)).unwrap().available;
+        assert!(after < before);
+        assert!(contract.storage_accounts.get(&accounts(0)).unwrap().used > 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_STORAGE_NOT_REGISTERED")]
+    fn test_create_stash_requires_registration() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract(None);
+        contract.create_stash("Roommates".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_STORAGE_DEPOSIT")]
+    fn test_create_stash_insufficient_storage_balance() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract(None);
+
+        let min = contract.storage_balance_bounds().min;
+        testing_env!(context.attached_deposit(min).build());
+        contract.storage_deposit(None, None);
+        contract.create_stash("Roommates".to_string());
+    }
+
+    #[test]
+    fn test_storage_withdraw_and_unregister() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract(None);
+        let min = contract.storage_balance_bounds().min;
+
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        contract.storage_deposit(None, None);
+
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(1)).build());
+        let balance = contract.storage_withdraw(None);
+        assert_eq!(balance.total, min);
+        assert!(balance.available.is_zero());
+
+        assert!(contract.storage_unregister(None));
+        assert!(contract.storage_balance_of(accounts(0)).is_none());
+        assert!(!contract.storage_unregister(None));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_STORAGE_IN_USE")]
+    fn test_storage_unregister_in_use() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract(None);
+
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        contract.storage_deposit(None, None);
+        contract.create_stash("Roommates".to_string());
+
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(1)).build());
+        contract.storage_unregister(None);
+    }
+}
@@ -0,0 +1,84 @@
+use near_sdk::serde::de::DeserializeOwned;
+use near_sdk::serde::Deserialize;
+use near_sdk::serde_json::Value;
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::test_utils::{get_logs, VMContextBuilder};
+use near_sdk::{env, testing_env, AccountId, NearToken};
+
+use crate::events::{DivvyEvent, MtEvent};
+use crate::stash::Role;
+use crate::Contract;
+
+/// Tokens every test contract is seeded with.
+pub const TOKENS: [&str; 6] = [
+    "btc-token.near",
+    "eth-token.near",
+    "usdt-token.near",
+    "usdc-token.near",
+    "wrap.near",
+    "sol-token.near",
+];
+
+pub fn get_context(predecessor: AccountId) -> VMContextBuilder {
+    let mut builder = VMContextBuilder::new();
+    builder.predecessor_account_id(predecessor);
+    builder
+}
+
+/// New contract owned by the current predecessor, allowlisting `TOKENS` with `wrap.near` as native NEAR.
+pub fn new_contract(dex_id: Option<AccountId>) -> Contract {
+    Contract::new(
+        env::predecessor_account_id(),
+        TOKENS.iter().map(|token| token.parse().unwrap()).collect(),
+        Some("wrap.near".parse().unwrap()),
+        dex_id,
+    )
+}
+
+/// Invites `account_id` into the stash as the context's predecessor and accepts as the account,
+/// registering its storage if needed. Leaves the context as it was.
+pub fn add_member(context: &mut VMContextBuilder, contract: &mut Contract, stash_id: u64, account_id: AccountId, role: Role) {
+    let inviter_id = context.context.predecessor_account_id.clone();
+    let attached_deposit = context.context.attached_deposit;
+    testing_env!(context.attached_deposit(NearToken::from_yoctonear(0)).build());
+    contract.invite_member(stash_id, account_id.clone(), role, None);
+    testing_env!(context.predecessor_account_id(account_id.clone()).attached_deposit(NearToken::from_near(1)).build());
+    if contract.storage_balance_of(account_id).is_none() {
+        contract.storage_deposit(None, None);
+    }
+    contract.accept_invite(stash_id);
+    testing_env!(context.predecessor_account_id(inviter_id).attached_deposit(attached_deposit).build());
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<T> {
+    version: String,
+    #[serde(flatten)]
+    event: T,
+}
+
+/// Events of `standard` logged so far in the current test context, checking the NEP-297 envelope.
+fn get_standard_events<T: DeserializeOwned>(standard: &str) -> Vec<T> {
+    get_logs()
+        .iter()
+        .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+        .map(|json| near_sdk::serde_json::from_str::<Value>(json).unwrap())
+        .filter(|log| log["standard"] == standard)
+        .map(|log| {
+            let log: EventLog<T> = near_sdk::serde_json::from_value(log).unwrap();
+            assert_eq!(log.version, "1.0.0");
+            log.event
+        })
+        .collect()
+}
+
+/// Events of the contract logged so far in the current test context.
+pub fn get_events() -> Vec<DivvyEvent> {
+    get_standard_events("divvy")
+}
+
+/// NEP-245 events of the share tokens logged so far in the current test context.
+pub fn get_mt_events() -> Vec<MtEvent> {
+    get_standard_events("nep245")
+}
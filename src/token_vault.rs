@@ -1,54 +1,79 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::LookupMap;
-use near_sdk::AccountId;
-use lazy_static::lazy_static;
-
-// TODO should I never use std collections, or is this fine becuase its only use is in the lazy_static macro?
-use std::collections::HashMap;
+use near_sdk::collections::{LookupMap, UnorderedMap};
+use near_sdk::json_types::U128;
+use near_sdk::{env, near, AccountId};
+
+use crate::events::{DivvyEvent, MtBurn, MtEvent, MtMint, MtTransfer};
+use crate::multi_token::share_token_id;
+use crate::math::{mul_div, Rounding};
+use crate::vesting::{VestingGrant, VestingSchedule};
+use crate::StorageKey;
+
+/// Virtual shares and assets added to the totals in every conversion, an offset of 3 decimals
+/// between shares and assets: an empty vault mints 1_000 shares per asset. Inflating the share
+/// price ahead of another depositor has to be paid 1_000 times over and the virtual shares keep
+/// part of the donation, while the victim loses no more than the value of one share to rounding.
+pub const VIRTUAL_SHARES: u128 = 1_000;
+const VIRTUAL_ASSETS: u128 = 1;
+/// Smallest amount accepted as the first deposit of a vault.
+pub const MIN_FIRST_DEPOSIT: u128 = 1_000;
+/// Basis points in a whole, fees are given in basis points.
+pub const FEE_DENOMINATOR: u16 = 10_000;
+const NANOS_PER_YEAR: u128 = 365 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Fees of a vault in basis points, paid in shares to the fee receiver of the contract.
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct VaultFees {
+    /// Yearly fee on all shares, accrued over time by minting new shares.
+    pub management_fee_bps: u16,
+    /// Part of the shares minted by adding liquidity.
+    pub deposit_fee_bps: u16,
+    /// Part of the shares given up when removing liquidity.
+    pub withdrawal_fee_bps: u16,
+}
 
-#[derive(BorshDeserialize, BorshSerialize)]
-pub enum Token {
-        // top two marketcap
-        BTC,
-        ETH,
+impl VaultFees {
+    pub fn is_zero(&self) -> bool {
+        *self == VaultFees::default()
+    }
 
-        // Near native stable coins
-        USDT,
-        USDC,
+    /// Whether no fee is above the same fee of `caps`.
+    pub fn is_within(&self, caps: &VaultFees) -> bool {
+        self.management_fee_bps <= caps.management_fee_bps
+            && self.deposit_fee_bps <= caps.deposit_fee_bps
+            && self.withdrawal_fee_bps <= caps.withdrawal_fee_bps
+    }
 
-        // AI coins
-        NEAR,
+    /// Each fee lowered to the same fee of `caps` where above it.
+    pub fn capped_to(&self, caps: &VaultFees) -> VaultFees {
+        VaultFees {
+            management_fee_bps: self.management_fee_bps.min(caps.management_fee_bps),
+            deposit_fee_bps: self.deposit_fee_bps.min(caps.deposit_fee_bps),
+            withdrawal_fee_bps: self.withdrawal_fee_bps.min(caps.withdrawal_fee_bps),
+        }
+    }
 
-        // High Marketcap L1s
-        SOL,
+    /// Panics unless every fee is below 100%.
+    pub fn assert_valid(&self) {
+        let fees = [self.management_fee_bps, self.deposit_fee_bps, self.withdrawal_fee_bps];
+        assert!(fees.iter().all(|fee_bps| *fee_bps < FEE_DENOMINATOR), "ERR_INVALID_FEE");
+    }
 }
 
-
-// Define constants for token contract account IDs
-//move these to be param in the init method of the parent contract
-const BTC_CONTRACT: &str = "btc-token.near";
-const ETH_CONTRACT: &str = "eth-token.near";
-const USDT_CONTRACT: &str = "usdt-token.near";
-const USDC_CONTRACT: &str = "usdc-token.near";
-const NEAR_CONTRACT: &str = "wrap.near";
-const SOL_CONTRACT: &str = "sol-token.near";
-
-lazy_static! {
-    // Map of token contract account ID to token enum
-    static ref TOKEN_MAP: HashMap<&'static str, Token> = {
-        let mut m = HashMap::new();
-        m.insert(BTC_CONTRACT, Token::BTC);
-        m.insert(ETH_CONTRACT, Token::ETH);
-        m.insert(USDT_CONTRACT, Token::USDT);
-        m.insert(USDC_CONTRACT, Token::USDC);
-        m.insert(NEAR_CONTRACT, Token::NEAR);
-        m.insert(SOL_CONTRACT, Token::SOL);
-        m
-    };
+/// Fee reported by `DivvyEvent::FeeCharged`.
+#[near(serializers = [json])]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FeeKind {
+    Management,
+    Deposit,
+    Withdrawal,
 }
 
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct TokenVault {
+    // Stash holding the vault
+    stash_id: u64,
     // Type of token in the vault
     token_type: AccountId,
     // Total count of tokens
@@ -57,42 +82,239 @@ pub struct TokenVault {
     shares_total_supply: u128,
     // Shares of the vault by owner accountId.
     shares: LookupMap<AccountId, u128>,
+    /// Fees charged by the vault, see `set_fees`.
+    fees: VaultFees,
+    /// Account the fee shares are paid to, set whenever a fee is.
+    fee_receiver_id: Option<AccountId>,
+    /// Block timestamp up to which the management fee was minted.
+    fees_accrued_at: u64,
+    /// Vesting grants by member, see `grant_shares`.
+    grants: UnorderedMap<AccountId, VestingGrant>,
+}
+
+/// Borsh layouts of `TokenVault`. Vaults are stored tagged with their layout so a changed
+/// layout only needs a variant for the previous one, upgraded to the current as it is read.
+#[near(serializers = [borsh])]
+pub enum VersionedTokenVault {
+    Current(TokenVault),
+}
+
+impl From<VersionedTokenVault> for TokenVault {
+    fn from(vault: VersionedTokenVault) -> Self {
+        match vault {
+            VersionedTokenVault::Current(vault) => vault,
+        }
+    }
+}
+
+impl From<TokenVault> for VersionedTokenVault {
+    fn from(vault: TokenVault) -> Self {
+        VersionedTokenVault::Current(vault)
+    }
 }
 
 impl TokenVault {
 
-    pub fn new(token_type: AccountId) ->  TokenVault {
-        assert!(TOKEN_MAP.contains_key(token_type.as_str()), "Token is not on the allowed list");
+    pub fn new(stash_id: u64, token_type: AccountId) ->  TokenVault {
         Self {
-            token_type,
+            stash_id,
+            shares: LookupMap::new(StorageKey::Shares { stash_id, token_id: token_type.clone() }),
             total_assets: 0,
             shares_total_supply: 0,
-            shares: LookupMap::new(b"s".to_vec()),
+            fees: VaultFees::default(),
+            fee_receiver_id: None,
+            fees_accrued_at: env::block_timestamp(),
+            grants: UnorderedMap::new(StorageKey::Grants { stash_id, token_id: token_type.clone() }),
+            token_type,
         }
     }
 
+    /// NEP-245 token id of the vault's shares.
+    pub fn get_share_token_id(&self) -> String {
+        share_token_id(self.stash_id, &self.token_type)
+    }
+
     pub fn get_token_type(&self) -> AccountId {
         self.token_type.clone()
     }
 
-    fn calculate_share(&self, assets: u128) -> u128 {
-        if self.total_assets == 0 || self.shares_total_supply == 0 {
-            assets
+    pub fn get_total_assets(&self) -> u128 {
+        self.total_assets
+    }
+
+    pub fn get_shares_total_supply(&self) -> u128 {
+        self.shares_total_supply
+    }
+
+    pub fn get_shares(&self, account_id: &AccountId) -> u128 {
+        self.shares.get(account_id).unwrap_or(0)
+    }
+
+    pub fn get_fees(&self) -> VaultFees {
+        self.fees
+    }
+
+    pub fn get_fee_receiver_id(&self) -> Option<AccountId> {
+        self.fee_receiver_id.clone()
+    }
+
+    /// Mints the management fee owed at the old rate, then charges `fees` to `fee_receiver_id`
+    /// from now on. The receiver is required unless all fees are zero.
+    pub fn set_fees(&mut self, fees: VaultFees, fee_receiver_id: Option<AccountId>) {
+        assert!(fees.is_zero() || fee_receiver_id.is_some(), "ERR_NO_FEE_RECEIVER");
+        self.accrue_management_fee();
+        self.fees = fees;
+        self.fee_receiver_id = fee_receiver_id;
+        DivvyEvent::VaultFeesUpdated { stash_id: self.stash_id, token_id: self.token_type.clone(), fees }.emit();
+    }
+
+    /// Management fee shares owed since the last accrual. Conversions count them as issued.
+    pub fn pending_management_fee(&self) -> u128 {
+        let elapsed = env::block_timestamp().saturating_sub(self.fees_accrued_at) as u128;
+        mul_div(
+            self.shares_total_supply,
+            self.fees.management_fee_bps as u128 * elapsed,
+            FEE_DENOMINATOR as u128 * NANOS_PER_YEAR,
+            Rounding::Down,
+        )
+    }
+
+    /// Mints the management fee owed since the last accrual to the fee receiver.
+    pub fn accrue_management_fee(&mut self) {
+        let shares = self.pending_management_fee();
+        self.fees_accrued_at = env::block_timestamp();
+        if shares > 0 {
+            self.internal_mint_fee(FeeKind::Management, shares);
+        }
+    }
+
+    /// Shares worth `assets`, rounded down.
+    pub fn convert_to_shares(&self, assets: u128) -> u128 {
+        self.internal_convert_to_shares(assets, Rounding::Down)
+    }
+
+    /// Assets currently redeemable for `shares`, rounded down.
+    pub fn convert_to_assets(&self, shares: u128) -> u128 {
+        self.internal_convert_to_assets(shares, Rounding::Down)
+    }
+
+    /// Shares minted for depositing `assets`, after the deposit fee.
+    pub fn preview_deposit(&self, assets: u128) -> u128 {
+        let shares = self.internal_convert_to_shares(assets, Rounding::Down);
+        shares - fee_of(shares, self.fees.deposit_fee_bps)
+    }
+
+    /// Whether depositing `assets` mints shares instead of failing `add_liquidity`.
+    pub fn can_add_liquidity(&self, assets: u128) -> bool {
+        (self.shares_total_supply > 0 || assets >= MIN_FIRST_DEPOSIT) && self.preview_deposit(assets) > 0
+    }
+
+    /// Assets needed to mint `shares` after the deposit fee.
+    pub fn preview_mint(&self, shares: u128) -> u128 {
+        self.internal_convert_to_assets(before_fee(shares, self.fees.deposit_fee_bps), Rounding::Up)
+    }
+
+    /// Shares given up to withdraw `assets`, including the withdrawal fee.
+    pub fn preview_withdraw(&self, assets: u128) -> u128 {
+        before_fee(self.internal_convert_to_shares(assets, Rounding::Up), self.fees.withdrawal_fee_bps)
+    }
+
+    /// Assets received for redeeming `shares`, after the withdrawal fee.
+    pub fn preview_redeem(&self, shares: u128) -> u128 {
+        let shares = shares - fee_of(shares, self.fees.withdrawal_fee_bps);
+        self.internal_convert_to_assets(shares, Rounding::Down)
+    }
+
+    /// Most assets the vault can still take before its totals overflow.
+    pub fn max_deposit(&self) -> u128 {
+        u128::MAX - self.total_assets
+    }
+
+    /// Assets `owner` can withdraw by redeeming all their shares, see `max_redeem`.
+    pub fn max_withdraw(&self, owner: &AccountId) -> u128 {
+        self.preview_redeem(self.max_redeem(owner))
+    }
+
+    /// Shares `owner` can redeem, the vested shares of their grant included.
+    pub fn max_redeem(&self, owner: &AccountId) -> u128 {
+        self.get_shares(owner) + self.grants.get(owner).map_or(0, |grant| grant.claimable())
+    }
+
+    fn internal_convert_to_shares(&self, assets: u128, rounding: Rounding) -> u128 {
+        mul_div(
+            assets,
+            self.shares_total_supply + self.pending_management_fee() + VIRTUAL_SHARES,
+            self.total_assets + VIRTUAL_ASSETS,
+            rounding,
+        )
+    }
+
+    fn internal_convert_to_assets(&self, shares: u128, rounding: Rounding) -> u128 {
+        mul_div(
+            shares,
+            self.total_assets + VIRTUAL_ASSETS,
+            self.shares_total_supply + self.pending_management_fee() + VIRTUAL_SHARES,
+            rounding,
+        )
+    }
+
+    /// Fee rate `account_id` pays, the fee receiver pays none.
+    fn internal_fee_bps(&self, account_id: &AccountId, fee_bps: u16) -> u16 {
+        if self.fee_receiver_id.as_ref() == Some(account_id) {
+            0
         } else {
-            assets * self.shares_total_supply / self.total_assets
+            fee_bps
+        }
+    }
+
+    fn internal_mint_fee(&mut self, kind: FeeKind, shares: u128) {
+        let receiver_id = self.fee_receiver_id.clone().expect("ERR_NO_FEE_RECEIVER");
+        self.shares_total_supply += shares;
+        self.shares.insert(&receiver_id, &(self.get_shares(&receiver_id) + shares));
+        MtEvent::MtMint(vec![MtMint {
+            owner_id: receiver_id.clone(),
+            token_ids: vec![self.get_share_token_id()],
+            amounts: vec![U128(shares)],
+            memo: None,
+        }])
+        .emit();
+        self.emit_fee(kind, receiver_id, shares);
+    }
+
+    fn emit_fee(&self, kind: FeeKind, receiver_id: AccountId, shares: u128) {
+        DivvyEvent::FeeCharged {
+            stash_id: self.stash_id,
+            token_id: self.token_type.clone(),
+            kind,
+            receiver_id,
+            shares: U128(shares),
         }
+        .emit();
     }
 
-    #[allow(dead_code)]
-    pub fn preview_deposit(&self, sender: AccountId, assets: u128) -> u128 {
-        //self.assert_authorized(sender.clone());
-        let sender_balance = self.shares.get(&sender).unwrap_or(0);
-        self.calculate_share(assets) + sender_balance
+    /// `transfer_shares` reported as a NEP-245 transfer.
+    fn internal_move_shares(&mut self, sender: &AccountId, receiver: &AccountId, shares: u128) {
+        self.transfer_shares(sender, receiver, shares);
+        MtEvent::MtTransfer(vec![MtTransfer {
+            old_owner_id: sender.clone(),
+            new_owner_id: receiver.clone(),
+            token_ids: vec![self.get_share_token_id()],
+            amounts: vec![U128(shares)],
+            memo: None,
+        }])
+        .emit();
     }
 
     pub fn add_liquidity(&mut self, sender: &AccountId, amount: u128) -> u128 {
-        // Calculate shares to mint based on net assets
-        let shares = self.calculate_share(amount);
+        self.accrue_management_fee();
+        if self.shares_total_supply == 0 {
+            assert!(amount >= MIN_FIRST_DEPOSIT, "ERR_MIN_FIRST_DEPOSIT");
+        }
+        // Calculate shares to mint based on net assets, the deposit fee is minted to the receiver
+        let minted = self.internal_convert_to_shares(amount, Rounding::Down);
+        let fee = fee_of(minted, self.internal_fee_bps(sender, self.fees.deposit_fee_bps));
+        let shares = minted - fee;
+        assert!(shares > 0, "ERR_ZERO_SHARES");
 
         // Update total assets and shares
         self.total_assets += amount;
@@ -102,13 +324,124 @@ impl TokenVault {
         let sender_balance = self.shares.get(sender).unwrap_or(0);
         self.shares.insert(sender, &(sender_balance + shares));
 
-        near_sdk::env::log_str(format!("Sender: {}, Deposited {} assets, Minted {} shares", sender, amount, shares)
-.as_str());
+        DivvyEvent::LiquidityAdded {
+            stash_id: self.stash_id,
+            account_id: sender.clone(),
+            token_id: self.token_type.clone(),
+            amount: U128(amount),
+            shares: U128(shares),
+        }
+        .emit();
+        MtEvent::MtMint(vec![MtMint {
+            owner_id: sender.clone(),
+            token_ids: vec![self.get_share_token_id()],
+            amounts: vec![U128(shares)],
+            memo: None,
+        }])
+        .emit();
+        if fee > 0 {
+            self.internal_mint_fee(FeeKind::Deposit, fee);
+        }
+        shares
+    }
+
+    /// Moves `shares` from `sender` to `receiver`, the totals are unchanged.
+    pub fn transfer_shares(&mut self, sender: &AccountId, receiver: &AccountId, shares: u128) {
+        assert!(shares > 0, "ERR_ZERO_AMOUNT");
+        let sender_balance = self.get_shares(sender);
+        assert!(sender_balance >= shares, "ERR_NOT_ENOUGH_SHARES");
+        self.shares.insert(sender, &(sender_balance - shares));
+        self.shares.insert(receiver, &(self.get_shares(receiver) + shares));
+    }
+
+
+    /// Removes the share balance of `account_id`, once the vault has no shares left.
+    pub fn clear_shares(&mut self, account_id: &AccountId) {
+        self.shares.remove(account_id);
+    }
+
+    /// Removes the grants and the shares the contract holds for them, once the vault has no
+    /// shares left.
+    pub fn clear_grants(&mut self) {
+        self.shares.remove(&env::current_account_id());
+        self.grants.clear();
+    }
+
+    /// Moves `shares` of `grantor_id` into a grant vesting to `account_id`. Until claimed they are
+    /// held by the contract's own account, keeping their value but out of reach of both.
+    pub fn grant_shares(&mut self, grantor_id: &AccountId, account_id: &AccountId, shares: u128, schedule: VestingSchedule) {
+        schedule.assert_valid();
+        assert_ne!(grantor_id, account_id, "ERR_SELF_GRANT");
+        assert!(self.grants.get(account_id).is_none(), "ERR_GRANT_EXISTS");
+        self.internal_move_shares(grantor_id, &env::current_account_id(), shares);
+        let grant = VestingGrant { grantor_id: grantor_id.clone(), shares: U128(shares), claimed: U128(0), schedule };
+        self.grants.insert(account_id, &grant);
+        DivvyEvent::VestingGranted {
+            stash_id: self.stash_id,
+            token_id: self.token_type.clone(),
+            account_id: account_id.clone(),
+            grant,
+        }
+        .emit();
+    }
+
+    /// Moves the vested shares of the account's grant not claimed yet to its balance, dropping
+    /// the grant once all are claimed. Returns the claimed shares, 0 without a grant.
+    pub fn claim_vested(&mut self, account_id: &AccountId) -> u128 {
+        let Some(mut grant) = self.grants.get(account_id) else {
+            return 0;
+        };
+        let shares = grant.claimable();
+        if shares == 0 {
+            return 0;
+        }
+        self.internal_move_shares(&env::current_account_id(), account_id, shares);
+        grant.claimed = U128(grant.claimed.0 + shares);
+        if grant.claimed == grant.shares {
+            self.grants.remove(account_id);
+        } else {
+            self.grants.insert(account_id, &grant);
+        }
+        DivvyEvent::VestedSharesClaimed {
+            stash_id: self.stash_id,
+            token_id: self.token_type.clone(),
+            account_id: account_id.clone(),
+            shares: U128(shares),
+        }
+        .emit();
         shares
     }
 
+    /// Ends a revocable grant of `grantor_id`, the vested shares go to the account and the
+    /// unvested ones back to the grantor. Returns the shares given back.
+    pub fn revoke_grant(&mut self, grantor_id: &AccountId, account_id: &AccountId) -> u128 {
+        let grant = self.grants.get(account_id).expect("ERR_NO_GRANT");
+        assert_eq!(&grant.grantor_id, grantor_id, "ERR_NOT_GRANTOR");
+        assert!(grant.schedule.revocable, "ERR_GRANT_NOT_REVOCABLE");
+        self.claim_vested(account_id);
+        self.grants.remove(account_id);
+        let returned = grant.shares.0 - grant.vested();
+        if returned > 0 {
+            self.internal_move_shares(&env::current_account_id(), grantor_id, returned);
+        }
+        DivvyEvent::VestingRevoked {
+            stash_id: self.stash_id,
+            token_id: self.token_type.clone(),
+            account_id: account_id.clone(),
+            returned: U128(returned),
+        }
+        .emit();
+        returned
+    }
+
+    pub fn get_grant(&self, account_id: &AccountId) -> Option<VestingGrant> {
+        self.grants.get(account_id)
+    }
 
+    /// Redeems `shares` of the sender, the withdrawal fee part of them goes to the fee receiver
+    /// and the rest is burned. Returns the assets of the burned shares.
     pub fn remove_liquidity(&mut self, sender: &AccountId, shares: u128) -> u128 {
+        self.accrue_management_fee();
         let sender_balance: u128 = self.shares.get(sender).unwrap_or(0);
         assert!(
             sender_balance >= shares,
@@ -116,22 +449,76 @@ impl TokenVault {
             sender_balance
         );
 
-        let assets = self.total_assets * shares / self.shares_total_supply;
+        let fee = fee_of(shares, self.internal_fee_bps(sender, self.fees.withdrawal_fee_bps));
+        let burned = shares - fee;
+        let assets = self.internal_convert_to_assets(burned, Rounding::Down);
 
         // Update total assets and shares
         self.total_assets -= assets;
-        self.shares_total_supply -= shares;
+        self.shares_total_supply -= burned;
 
         // Update sender's balance
         let new_balance = sender_balance - shares;
         self.shares.insert(sender, &new_balance);
 
-        // Log the transaction
-        near_sdk::env::log_str(&format!("Sender: {}, Withdrew {} shares, Burned {} assets", sender, shares, assets));
+        DivvyEvent::LiquidityRemoved {
+            stash_id: self.stash_id,
+            account_id: sender.clone(),
+            token_id: self.token_type.clone(),
+            amount: U128(assets),
+            shares: U128(shares),
+        }
+        .emit();
+        MtEvent::MtBurn(vec![MtBurn {
+            owner_id: sender.clone(),
+            token_ids: vec![self.get_share_token_id()],
+            amounts: vec![U128(burned)],
+            memo: None,
+        }])
+        .emit();
+        if fee > 0 {
+            let receiver_id = self.fee_receiver_id.clone().expect("ERR_NO_FEE_RECEIVER");
+            self.shares.insert(&receiver_id, &(self.get_shares(&receiver_id) + fee));
+            MtEvent::MtTransfer(vec![MtTransfer {
+                old_owner_id: sender.clone(),
+                new_owner_id: receiver_id.clone(),
+                token_ids: vec![self.get_share_token_id()],
+                amounts: vec![U128(fee)],
+                memo: None,
+            }])
+            .emit();
+            self.emit_fee(FeeKind::Withdrawal, receiver_id, fee);
+        }
 
         assets
     }
 
+    /// Pays `amount` out of the pooled assets, the loss is shared by all shareholders.
+    pub fn payout(&mut self, amount: u128) {
+        assert!(self.total_assets >= amount, "ERR_NOT_ENOUGH_ASSETS");
+        self.total_assets -= amount;
+    }
+
+    /// Returns the assets of a payout which could not be sent.
+    pub fn restore_payout(&mut self, amount: u128) {
+        self.total_assets += amount;
+    }
+
+    /// Pools `amount` without minting shares, raising the value of every share.
+    pub fn add_assets(&mut self, amount: u128) {
+        self.total_assets += amount;
+    }
+
+}
+
+/// Part of `amount` taken by a fee of `fee_bps`, rounded up.
+fn fee_of(amount: u128, fee_bps: u16) -> u128 {
+    mul_div(amount, fee_bps as u128, FEE_DENOMINATOR as u128, Rounding::Up)
+}
+
+/// Amount which leaves at least `amount` once a fee of `fee_bps` is taken from it.
+fn before_fee(amount: u128, fee_bps: u16) -> u128 {
+    mul_div(amount, FEE_DENOMINATOR as u128, (FEE_DENOMINATOR - fee_bps) as u128, Rounding::Up)
 }
 
 #[cfg(test)]
@@ -140,13 +527,15 @@ mod tests {
     use super::*;
     use near_sdk::test_utils::VMContextBuilder;
     use near_sdk::testing_env;
+    use crate::test_utils::get_events;
+    use proptest::prelude::*;
 
     #[test]
     fn test_initialization() {
         let context = VMContextBuilder::new();
         testing_env!(context.build());
 
-        let vault = TokenVault::new(BTC_CONTRACT.parse().unwrap());
+        let vault = TokenVault::new(0, "btc-token.near".parse().unwrap());
         assert_eq!(vault.get_token_type(), "btc-token.near");
         assert_eq!(vault.total_assets, 0);
         assert_eq!(vault.shares_total_supply, 0);
@@ -158,15 +547,25 @@ mod tests {
         testing_env!(context.build());
 
         let sender: AccountId = "roger.near".parse().unwrap();
-        let mut vault = TokenVault::new(ETH_CONTRACT.parse().unwrap());
+        let mut vault = TokenVault::new(0, "eth-token.near".parse().unwrap());
 
         assert_eq!(vault.get_token_type(), "eth-token.near");
 
         let shares = vault.add_liquidity(&sender, 10_000);
-        assert_eq!(shares, 10_000);
+        assert_eq!(shares, 10_000_000);
         assert_eq!(vault.total_assets, 10_000);
-        assert_eq!(vault.shares_total_supply, 10_000);
-        assert_eq!(vault.shares.get(&sender).unwrap(), 10_000);
+        assert_eq!(vault.shares_total_supply, 10_000_000);
+        assert_eq!(vault.shares.get(&sender).unwrap(), 10_000_000);
+        assert_eq!(
+            get_events(),
+            vec![DivvyEvent::LiquidityAdded {
+                stash_id: 0,
+                account_id: sender,
+                token_id: "eth-token.near".parse().unwrap(),
+                amount: U128(10_000),
+                shares: U128(10_000_000),
+            }]
+        );
     }
 
     #[test]
@@ -175,10 +574,10 @@ mod tests {
         testing_env!(context.build());
 
         let sender: AccountId = "phillipe.near".parse().unwrap();
-        let mut vault = TokenVault::new(USDC_CONTRACT.parse().unwrap());
+        let mut vault = TokenVault::new(0, "usdc-token.near".parse().unwrap());
 
-        vault.add_liquidity(&sender, 10_000);
-        let assets = vault.remove_liquidity(&sender, 10_000);
+        let shares = vault.add_liquidity(&sender, 10_000);
+        let assets = vault.remove_liquidity(&sender, shares);
         assert_eq!(assets, 10_000);
         assert_eq!(vault.total_assets, 0);
         assert_eq!(vault.shares_total_supply, 0);
@@ -191,10 +590,10 @@ mod tests {
         testing_env!(context.build());
 
         let sender: AccountId = "toy.near".parse().unwrap();
-        let mut vault = TokenVault::new(USDT_CONTRACT.parse().unwrap());
+        let mut vault = TokenVault::new(0, "usdt-token.near".parse().unwrap());
 
-        vault.add_liquidity(&sender, 10_000);
-        vault.remove_liquidity(&sender, 10_000);
+        let shares = vault.add_liquidity(&sender, 10_000);
+        vault.remove_liquidity(&sender, shares);
 
         assert_eq!(vault.total_assets, 0);
         assert_eq!(vault.shares_total_supply, 0);
@@ -207,14 +606,14 @@ mod tests {
         testing_env!(context.build());
 
         let sender: AccountId = "phillipe.near".parse().unwrap();
-        let mut vault = TokenVault::new(SOL_CONTRACT.parse().unwrap());
+        let mut vault = TokenVault::new(0, "sol-token.near".parse().unwrap());
 
         vault.add_liquidity(&sender, 5_000);
         vault.add_liquidity(&sender, 5_000);
 
         assert_eq!(vault.total_assets, 10_000);
-        assert_eq!(vault.shares_total_supply, 10_000);
-        assert_eq!(vault.shares.get(&sender).unwrap(), 10_000);
+        assert_eq!(vault.shares_total_supply, 10_000_000);
+        assert_eq!(vault.shares.get(&sender).unwrap(), 10_000_000);
     }
 
     #[test]
@@ -223,15 +622,310 @@ mod tests {
         testing_env!(context.build());
 
         let sender: AccountId = "root.near".parse().unwrap();
-        let mut vault = TokenVault::new(NEAR_CONTRACT.parse().unwrap());
+        let mut vault = TokenVault::new(0, "wrap.near".parse().unwrap());
 
 
         vault.add_liquidity(&sender, 10_000);
-        vault.remove_liquidity(&sender, 5_000);
-        vault.remove_liquidity(&sender, 5_000);
+        vault.remove_liquidity(&sender, 5_000_000);
+        vault.remove_liquidity(&sender, 5_000_000);
 
         assert_eq!(vault.total_assets, 0);
         assert_eq!(vault.shares_total_supply, 0);
         assert_eq!(vault.shares.get(&sender).unwrap(), 0);
     }
+
+    fn vault_with(total_assets: u128, shares_total_supply: u128) -> TokenVault {
+        let mut vault = TokenVault::new(0, "usdt-token.near".parse().unwrap());
+        vault.total_assets = total_assets;
+        vault.shares_total_supply = shares_total_supply;
+        vault
+    }
+
+    #[test]
+    fn test_empty_vault_converts_at_offset() {
+        let context = VMContextBuilder::new();
+        testing_env!(context.build());
+
+        let vault = vault_with(0, 0);
+        assert_eq!(vault.convert_to_shares(1_000), 1_000_000);
+        assert_eq!(vault.convert_to_assets(1_000_000), 1_000);
+        assert_eq!(vault.preview_mint(1_000_000), 1_000);
+        assert_eq!(vault.preview_withdraw(1_000), 1_000_000);
+    }
+
+    #[test]
+    fn test_conversion_rounding() {
+        let context = VMContextBuilder::new();
+        testing_env!(context.build());
+
+        // 3 assets for every 2 shares, virtual ones included
+        let vault = vault_with(2_999, 1_000);
+        assert_eq!(vault.convert_to_shares(10), 6);
+        assert_eq!(vault.convert_to_assets(7), 10);
+
+        // down for the user when receiving
+        assert_eq!(vault.preview_deposit(10), 6);
+        assert_eq!(vault.preview_redeem(7), 10);
+        // up when the user provides
+        assert_eq!(vault.preview_mint(7), 11);
+        assert_eq!(vault.preview_withdraw(10), 7);
+
+        // exact amounts are not rounded
+        assert_eq!(vault.preview_mint(2), 3);
+        assert_eq!(vault.preview_withdraw(3), 2);
+    }
+
+    #[test]
+    fn test_round_trips_never_profit() {
+        let context = VMContextBuilder::new();
+        testing_env!(context.build());
+
+        let vault = vault_with(1_000_003, 999_999);
+        for amount in [1, 7, 99, 1_234, 500_001] {
+            assert!(vault.preview_redeem(vault.preview_deposit(amount)) <= amount);
+            assert!(vault.preview_mint(vault.preview_withdraw(amount)) >= amount);
+            assert!(vault.preview_withdraw(vault.preview_redeem(amount)) <= amount);
+        }
+    }
+
+    #[test]
+    fn test_max_limits() {
+        let context = VMContextBuilder::new();
+        testing_env!(context.build());
+
+        let owner: AccountId = "roger.near".parse().unwrap();
+        let mut vault = TokenVault::new(0, "usdt-token.near".parse().unwrap());
+        vault.add_liquidity(&owner, 1_000);
+        vault.payout(1);
+
+        assert_eq!(vault.max_deposit(), u128::MAX - 999);
+        assert_eq!(vault.max_redeem(&owner), 1_000_000);
+        assert_eq!(vault.max_withdraw(&owner), 999);
+        assert_eq!(vault.max_withdraw(&"other.near".parse().unwrap()), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MIN_FIRST_DEPOSIT")]
+    fn test_min_first_deposit() {
+        let context = VMContextBuilder::new();
+        testing_env!(context.build());
+
+        let mut vault = TokenVault::new(0, "usdt-token.near".parse().unwrap());
+        vault.add_liquidity(&"attacker.near".parse().unwrap(), MIN_FIRST_DEPOSIT - 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ZERO_SHARES")]
+    fn test_deposit_minting_zero_shares() {
+        let context = VMContextBuilder::new();
+        testing_env!(context.build());
+
+        let mut vault = TokenVault::new(0, "usdt-token.near".parse().unwrap());
+        vault.add_liquidity(&"attacker.near".parse().unwrap(), MIN_FIRST_DEPOSIT);
+        // donation straight into the vault
+        vault.total_assets += 10_000_000_000_000;
+        vault.add_liquidity(&"victim.near".parse().unwrap(), 5_000);
+    }
+
+    /// First depositor inflates the share price with a donation so that the victim's deposit
+    /// rounds down to few shares, then redeems hoping to take part of the victim's assets.
+    /// Returns what both got back and the value of one share when the victim deposited.
+    fn inflation_attack(donation: u128, victim_deposit: u128) -> (u128, u128, u128) {
+        let attacker: AccountId = "attacker.near".parse().unwrap();
+        let victim: AccountId = "victim.near".parse().unwrap();
+        let mut vault = TokenVault::new(0, "usdt-token.near".parse().unwrap());
+
+        let attacker_shares = vault.add_liquidity(&attacker, MIN_FIRST_DEPOSIT);
+        vault.total_assets += donation;
+        let share_value = vault.preview_mint(1);
+        let victim_shares = vault.add_liquidity(&victim, victim_deposit);
+
+        let attacker_out = vault.remove_liquidity(&attacker, attacker_shares);
+        let victim_out = vault.remove_liquidity(&victim, victim_shares);
+        (attacker_out, victim_out, share_value)
+    }
+
+    #[test]
+    fn test_inflation_attack_is_not_profitable() {
+        let context = VMContextBuilder::new();
+        testing_env!(context.build());
+
+        for (donation, victim_deposit) in [
+            (1_000_000, 600_000),
+            (1_000_000, 1_500_000),
+            (10_000_000_000, 9_000_000_000),
+            (1_000_000_000_000, 20_000_000_000_000),
+            (1_000_000_000_000, 1_500_000),
+        ] {
+            let (attacker_out, victim_out, share_value) = inflation_attack(donation, victim_deposit);
+            let attacker_in = MIN_FIRST_DEPOSIT + donation;
+            assert!(attacker_out < attacker_in, "attacker made {} from {}", attacker_out, attacker_in);
+            assert!(attacker_out + victim_out <= attacker_in + victim_deposit);
+            // the victim only loses the rounding of their deposit and redemption
+            assert!(
+                victim_deposit - victim_out <= share_value + 1,
+                "victim lost {} of {}",
+                victim_deposit - victim_out,
+                victim_deposit
+            );
+        }
+    }
+
+    #[test]
+    fn test_small_donation_bounds_victim_loss() {
+        let context = VMContextBuilder::new();
+        testing_env!(context.build());
+
+        let (_, victim_out, share_value) = inflation_attack(1_000_000, 600_000);
+        assert_eq!(share_value, 2);
+        assert!(victim_out >= 600_000 - 3);
+    }
+
+    fn treasury() -> AccountId {
+        "treasury.near".parse().unwrap()
+    }
+
+    fn vault_with_fees(fees: VaultFees) -> TokenVault {
+        let mut vault = TokenVault::new(0, "usdt-token.near".parse().unwrap());
+        vault.set_fees(fees, Some(treasury()));
+        vault
+    }
+
+    #[test]
+    fn test_deposit_and_withdrawal_fees() {
+        let context = VMContextBuilder::new();
+        testing_env!(context.build());
+
+        let sender: AccountId = "roger.near".parse().unwrap();
+        let mut vault = vault_with_fees(VaultFees { management_fee_bps: 0, deposit_fee_bps: 100, withdrawal_fee_bps: 50 });
+        assert_eq!(vault.preview_deposit(10_000), 9_900_000);
+        assert_eq!(vault.add_liquidity(&sender, 10_000), 9_900_000);
+        assert_eq!(vault.get_shares(&treasury()), 100_000);
+        assert_eq!(vault.shares_total_supply, 10_000_000);
+
+        // 49_500 of the shares go to the treasury, the other 9_850_500 are burned
+        assert_eq!(vault.preview_redeem(9_900_000), 9_850);
+        assert_eq!(vault.remove_liquidity(&sender, 9_900_000), 9_850);
+        assert_eq!(vault.get_shares(&treasury()), 149_500);
+        assert_eq!(vault.shares_total_supply, 149_500);
+        assert_eq!(vault.total_assets, 150);
+        assert_eq!(
+            get_events().last(),
+            Some(&DivvyEvent::FeeCharged {
+                stash_id: 0,
+                token_id: "usdt-token.near".parse().unwrap(),
+                kind: FeeKind::Withdrawal,
+                receiver_id: treasury(),
+                shares: U128(49_500),
+            })
+        );
+
+        // the treasury pays no fee on its own shares
+        assert_eq!(vault.remove_liquidity(&treasury(), 149_500), 149);
+        assert_eq!(vault.shares_total_supply, 0);
+    }
+
+    #[test]
+    fn test_management_fee_accrues_over_time() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.block_timestamp(0).build());
+
+        let sender: AccountId = "roger.near".parse().unwrap();
+        let mut vault = vault_with_fees(VaultFees { management_fee_bps: 200, deposit_fee_bps: 0, withdrawal_fee_bps: 0 });
+        vault.add_liquidity(&sender, 10_000);
+
+        testing_env!(context.block_timestamp(NANOS_PER_YEAR as u64 / 2).build());
+        assert_eq!(vault.pending_management_fee(), 100_000);
+        // quotes already count the owed shares
+        assert_eq!(vault.convert_to_assets(10_000_000), 9_900);
+        assert_eq!(vault.max_withdraw(&sender), 9_900);
+
+        vault.accrue_management_fee();
+        assert_eq!(vault.get_shares(&treasury()), 100_000);
+        assert_eq!(vault.shares_total_supply, 10_100_000);
+        assert_eq!(vault.pending_management_fee(), 0);
+        assert_eq!(vault.convert_to_assets(10_000_000), 9_900);
+    }
+
+    #[test]
+    fn test_changing_fees_accrues_at_old_rate() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.block_timestamp(0).build());
+
+        let mut vault = vault_with_fees(VaultFees { management_fee_bps: 100, deposit_fee_bps: 0, withdrawal_fee_bps: 0 });
+        vault.add_liquidity(&"roger.near".parse().unwrap(), 10_000);
+        testing_env!(context.block_timestamp(NANOS_PER_YEAR as u64).build());
+        vault.set_fees(VaultFees::default(), None);
+        assert_eq!(vault.get_shares(&treasury()), 100_000);
+
+        testing_env!(context.block_timestamp(2 * NANOS_PER_YEAR as u64).build());
+        assert_eq!(vault.pending_management_fee(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_FEE_RECEIVER")]
+    fn test_fees_need_receiver() {
+        let context = VMContextBuilder::new();
+        testing_env!(context.build());
+
+        let mut vault = TokenVault::new(0, "usdt-token.near".parse().unwrap());
+        vault.set_fees(VaultFees { management_fee_bps: 0, deposit_fee_bps: 10, withdrawal_fee_bps: 0 }, None);
+    }
+
+    #[test]
+    fn test_fee_previews_round_against_user() {
+        let context = VMContextBuilder::new();
+        testing_env!(context.build());
+
+        let mut vault = vault_with_fees(VaultFees { management_fee_bps: 0, deposit_fee_bps: 30, withdrawal_fee_bps: 70 });
+        vault.total_assets = 1_000_003;
+        vault.shares_total_supply = 999_999;
+        for amount in [1, 7, 99, 1_234, 500_001] {
+            assert!(vault.preview_redeem(vault.preview_deposit(amount)) <= amount);
+            assert!(vault.preview_mint(vault.preview_withdraw(amount)) >= amount);
+            // the quoted amounts are enough once the fees are taken
+            assert!(vault.preview_deposit(vault.preview_mint(amount)) >= amount);
+            assert!(vault.preview_redeem(vault.preview_withdraw(amount)) >= amount);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn prop_conversions_near_u128_max(
+            total_assets in (u128::MAX / 2)..u128::MAX,
+            shares_total_supply in (u128::MAX / 2)..u128::MAX,
+            amount in 0..(u128::MAX / 4),
+        ) {
+            let context = VMContextBuilder::new();
+            testing_env!(context.build());
+            let vault = vault_with(total_assets, shares_total_supply);
+
+            let shares = vault.preview_deposit(amount);
+            prop_assert!(vault.preview_redeem(shares) <= amount);
+            let assets = vault.preview_redeem(amount);
+            prop_assert!(vault.preview_withdraw(assets) <= amount);
+            prop_assert!(vault.preview_mint(amount) >= vault.convert_to_assets(amount));
+        }
+
+        #[test]
+        // shares carry VIRTUAL_SHARES times the assets, so their supply bounds the deposits
+        fn prop_large_deposits_and_withdrawals(
+            first in MIN_FIRST_DEPOSIT..u128::MAX / 4 / VIRTUAL_SHARES,
+            second in 1..u128::MAX / 4 / VIRTUAL_SHARES,
+        ) {
+            let context = VMContextBuilder::new();
+            testing_env!(context.build());
+            let alice: AccountId = "alice.near".parse().unwrap();
+            let bob: AccountId = "bob.near".parse().unwrap();
+            let mut vault = TokenVault::new(0, "wrap.near".parse().unwrap());
+
+            let alice_shares = vault.add_liquidity(&alice, first);
+            let bob_shares = vault.preview_deposit(second);
+            prop_assume!(bob_shares > 0);
+            vault.add_liquidity(&bob, second);
+
+            prop_assert!(vault.remove_liquidity(&bob, bob_shares) <= second);
+            prop_assert!(vault.remove_liquidity(&alice, alice_shares) <= first);
+        }
+    }
 }
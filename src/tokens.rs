@@ -0,0 +1,195 @@
+use near_contract_standards::fungible_token::metadata::{ext_ft_metadata, FungibleTokenMetadata};
+use near_sdk::{env, log, near, AccountId, Gas, Promise, PromiseError};
+
+use crate::{Contract, ContractExt};
+
+const GAS_FOR_FT_METADATA: Gas = Gas::from_tgas(10);
+const GAS_FOR_ON_FT_METADATA: Gas = Gas::from_tgas(10);
+
+/// Metadata recorded for a supported token, fetched from its `ft_metadata`.
+#[near(serializers = [borsh, json])]
+#[derive(Clone)]
+pub struct TokenMetadata {
+    pub symbol: String,
+    pub decimals: u8,
+    pub icon: Option<String>,
+}
+
+impl From<FungibleTokenMetadata> for TokenMetadata {
+    fn from(metadata: FungibleTokenMetadata) -> Self {
+        Self { symbol: metadata.symbol, decimals: metadata.decimals, icon: metadata.icon }
+    }
+}
+
+#[near(serializers = [json])]
+pub struct SupportedToken {
+    pub token_id: AccountId,
+    /// `None` until the token's `ft_metadata` has been fetched.
+    pub metadata: Option<TokenMetadata>,
+}
+
+#[near]
+impl Contract {
+    /// Adds a token to the allowlist once its `ft_metadata` could be fetched.
+    /// Calling it again for a supported token refreshes the metadata. Owner only.
+    pub fn add_supported_token(&mut self, token_id: AccountId) -> Promise {
+        self.assert_owner();
+        self.internal_fetch_token_metadata(token_id)
+    }
+
+    /// Removes a token from the allowlist. Existing vaults keep working so members can still
+    /// withdraw, but no new vaults or deposits of the token are accepted. Owner only.
+    pub fn remove_supported_token(&mut self, token_id: AccountId) {
+        self.assert_owner();
+        assert!(self.supported_tokens.remove(&token_id).is_some(), "ERR_TOKEN_NOT_SUPPORTED");
+    }
+
+    /// Records the fetched metadata, returns whether the token is supported.
+    #[private]
+    pub fn on_ft_metadata(
+        &mut self,
+        token_id: AccountId,
+        #[callback_result] metadata: Result<FungibleTokenMetadata, PromiseError>,
+    ) -> bool {
+        match metadata {
+            Ok(metadata) => {
+                self.supported_tokens.insert(&token_id, &Some(metadata.into()));
+                true
+            }
+            Err(_) => {
+                log!("ERR_FT_METADATA unable to fetch metadata of {}", token_id);
+                self.supported_tokens.get(&token_id).is_some()
+            }
+        }
+    }
+
+    pub fn get_supported_tokens(&self) -> Vec<SupportedToken> {
+        self.supported_tokens
+            .iter()
+            .map(|(token_id, metadata)| SupportedToken { token_id, metadata })
+            .collect()
+    }
+}
+
+// internal methods
+impl Contract {
+    pub(crate) fn assert_owner(&self) {
+        assert_eq!(env::predecessor_account_id(), self.owner_id, "ERR_NOT_OWNER");
+    }
+
+    pub(crate) fn is_supported_token(&self, token_id: &AccountId) -> bool {
+        self.supported_tokens.get(token_id).is_some()
+    }
+
+    pub(crate) fn internal_fetch_token_metadata(&self, token_id: AccountId) -> Promise {
+        ext_ft_metadata::ext(token_id.clone())
+            .with_static_gas(GAS_FOR_FT_METADATA)
+            .ft_metadata()
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_ON_FT_METADATA)
+                    .on_ft_metadata(token_id),
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_contract_standards::fungible_token::metadata::FT_METADATA_SPEC;
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{get_context, new_contract};
+
+    fn metadata(symbol: &str, decimals: u8) -> FungibleTokenMetadata {
+        FungibleTokenMetadata {
+            spec: FT_METADATA_SPEC.to_string(),
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            icon: Some("data:image/svg+xml,<svg/>".to_string()),
+            reference: None,
+            reference_hash: None,
+            decimals,
+        }
+    }
+
+    #[test]
+    fn test_seeded_tokens() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let contract = new_contract(None);
+
+        assert!(contract.is_supported_token(&"usdt-token.near".parse().unwrap()));
+        assert!(!contract.is_supported_token(&"usdt.tether-token.near".parse().unwrap()));
+        assert!(contract.get_supported_tokens().iter().all(|token| token.metadata.is_none()));
+    }
+
+    #[test]
+    fn test_add_supported_token() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract(None);
+        let token_id: AccountId = "usdt.tether-token.near".parse().unwrap();
+
+        contract.add_supported_token(token_id.clone());
+        // not supported until the metadata is known
+        assert!(!contract.is_supported_token(&token_id));
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        assert!(contract.on_ft_metadata(token_id.clone(), Ok(metadata("USDt", 6))));
+        assert!(contract.is_supported_token(&token_id));
+
+        let token = contract.get_supported_tokens().into_iter().find(|token| token.token_id == token_id).unwrap();
+        let token_metadata = token.metadata.unwrap();
+        assert_eq!(token_metadata.symbol, "USDt");
+        assert_eq!(token_metadata.decimals, 6);
+        assert!(token_metadata.icon.is_some());
+    }
+
+    #[test]
+    fn test_add_supported_token_without_metadata() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract(None);
+        let token_id: AccountId = "not-a-token.near".parse().unwrap();
+
+        contract.add_supported_token(token_id.clone());
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        assert!(!contract.on_ft_metadata(token_id.clone(), Err(PromiseError::Failed)));
+        assert!(!contract.is_supported_token(&token_id));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_OWNER")]
+    fn test_add_supported_token_owner_only() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract(None);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.add_supported_token("usdt.tether-token.near".parse().unwrap());
+    }
+
+    #[test]
+    fn test_remove_supported_token() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract(None);
+        let token_id: AccountId = "sol-token.near".parse().unwrap();
+
+        contract.remove_supported_token(token_id.clone());
+        assert!(!contract.is_supported_token(&token_id));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_OWNER")]
+    fn test_remove_supported_token_owner_only() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract(None);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.remove_supported_token("sol-token.near".parse().unwrap());
+    }
+}
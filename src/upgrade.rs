@@ -0,0 +1,318 @@
+use near_sdk::collections::{LookupMap, UnorderedMap, UnorderedSet};
+use near_sdk::{env, near, AccountId, Gas, GasWeight, NearToken, Promise};
+
+use crate::config::{Config, PauseFlags};
+use crate::stash::{Role, Stash};
+use crate::token_vault::VaultFees;
+use crate::{Contract, ContractExt, StorageKey};
+
+/// Gas reserved for `migrate`, it also gets whatever the deployment leaves unused.
+const GAS_FOR_MIGRATE: Gas = Gas::from_tgas(20);
+
+/// Storage key of the layout version of the contract state. The first release did not write it.
+const STATE_VERSION_KEY: &[u8] = b"VERSION";
+/// Layout version written by this code, the first release being version 1.
+const STATE_VERSION: u32 = 2;
+
+/// Token allowlist hard-coded in the first release.
+const V1_TOKENS: [&str; 6] = [
+    "btc-token.near",
+    "eth-token.near",
+    "usdt-token.near",
+    "usdc-token.near",
+    "wrap.near",
+    "sol-token.near",
+];
+/// Native NEAR token of the first release.
+const V1_WRAP_NEAR: &str = "wrap.near";
+
+/// Layout of `Contract` in the first release.
+#[near(serializers = [borsh])]
+pub(crate) struct ContractV1 {
+    pub stashes: UnorderedMap<u64, StashV1>,
+    /// Stash ids by creator. The sets were created with the prefix of `stashes`, so inserting
+    /// into them always found the id taken and every set is empty.
+    pub accounts: UnorderedMap<AccountId, UnorderedSet<u64>>,
+}
+
+/// Layout of `Stash` in the first release. Its collections used fixed prefixes, so every stash
+/// shares the same vaults, deposits and authorized users, see `Contract::from`.
+#[near(serializers = [borsh])]
+#[allow(dead_code)] // the collections are read through `V1_PREFIX_*`
+pub(crate) struct StashV1 {
+    pub id: u64,
+    pub name: String,
+    pub vaults: LookupMap<AccountId, TokenVaultV1>,
+    pub deposited_amounts: LookupMap<AccountId, UnorderedMap<AccountId, u128>>,
+    pub authorized_users: LookupMap<AccountId, bool>,
+}
+
+/// Layout of `TokenVault` in the first release, stored under `V1_PREFIX_VAULTS` by token.
+#[near(serializers = [borsh])]
+#[allow(dead_code)] // only the assets are checked, see `Contract::from`
+pub(crate) struct TokenVaultV1 {
+    pub token_type: AccountId,
+    pub total_assets: u128,
+    pub shares_total_supply: u128,
+    pub shares: LookupMap<AccountId, u128>,
+}
+
+const V1_PREFIX_VAULTS: &[u8] = b"v";
+const V1_PREFIX_SHARES: &[u8] = b"s";
+const V1_PREFIX_AUTHORIZED_USERS: &[u8] = b"a";
+
+impl From<ContractV1> for Contract {
+    /// Rebuilds the stashes of the first release, owned by the contract account. Deposits could
+    /// not be made then, so vaults are empty and only their tokens are carried over. Authorization
+    /// was shared by all stashes, so every creator still authorized becomes an admin of every
+    /// stash. Accounts authorized with `authorize_contributor` alone cannot be listed and are
+    /// left out. Token metadata is not fetched, `add_supported_token` refreshes it.
+    fn from(mut old: ContractV1) -> Self {
+        let mut contract = Self {
+            owner_id: env::current_account_id(),
+            stashes: UnorderedMap::new(StorageKey::Stashes),
+            accounts: UnorderedMap::new(StorageKey::Accounts),
+            storage_accounts: LookupMap::new(StorageKey::StorageAccounts),
+            account_storage_usage: 0,
+            supported_tokens: UnorderedMap::new(StorageKey::SupportedTokens),
+            config: Config {
+                wrap_near_id: Some(V1_WRAP_NEAR.parse().unwrap()),
+                dex_id: None,
+                fee_receiver_id: None,
+                max_members_per_stash: None,
+                max_fees: VaultFees::default(),
+            },
+            paused: PauseFlags::default(),
+            next_stash_id: 0,
+            invites: LookupMap::new(StorageKey::Invites),
+            invite_codes: LookupMap::new(StorageKey::InviteCodes),
+        };
+        contract.internal_measure_account_storage_usage();
+
+        let mut vaults: LookupMap<AccountId, TokenVaultV1> = LookupMap::new(V1_PREFIX_VAULTS);
+        let mut shares: LookupMap<AccountId, u128> = LookupMap::new(V1_PREFIX_SHARES);
+        let mut authorized_users: LookupMap<AccountId, bool> = LookupMap::new(V1_PREFIX_AUTHORIZED_USERS);
+        let creators: Vec<AccountId> = old.accounts.keys().collect();
+        let admins: Vec<AccountId> = creators
+            .iter()
+            .filter(|account_id| authorized_users.get(account_id).unwrap_or(false))
+            .filter(|account_id| **account_id != contract.owner_id)
+            .cloned()
+            .collect();
+        let mut tokens = Vec::new();
+        for token_id in V1_TOKENS.iter().map(|token| token.parse::<AccountId>().unwrap()) {
+            contract.supported_tokens.insert(&token_id, &None);
+            if let Some(vault) = vaults.remove(&token_id) {
+                assert_eq!(vault.total_assets, 0, "ERR_LEGACY_VAULT_NOT_EMPTY");
+                tokens.push(token_id);
+            }
+        }
+
+        for (stash_id, old_stash) in old.stashes.iter() {
+            let mut stash = Stash::new(stash_id, old_stash.name);
+            for token_id in &tokens {
+                stash.add_vault(token_id.clone());
+            }
+            for account_id in &admins {
+                stash.add_member(account_id.clone(), Role::Admin);
+            }
+            for (account_id, _) in stash.get_members() {
+                contract.internal_add_account_stash(&account_id, stash_id);
+            }
+            contract.internal_save_stash(stash_id, stash);
+            contract.next_stash_id = contract.next_stash_id.max(stash_id + 1);
+        }
+
+        for account_id in &creators {
+            shares.remove(account_id);
+            authorized_users.remove(account_id);
+        }
+        old.stashes.clear();
+        old.accounts.clear();
+        contract
+    }
+}
+
+#[near]
+impl Contract {
+    /// Deploys the wasm passed as the raw input of the call and runs `migrate` on it.
+    /// Owner only. The first release has no `upgrade`, the contract account deploys it and
+    /// calls `migrate` in one transaction instead.
+    pub fn upgrade(&self) -> Promise {
+        self.assert_owner();
+        let code = env::input().expect("ERR_NO_CODE");
+        Promise::new(env::current_account_id()).deploy_contract(code).function_call_weight(
+            "migrate".to_string(),
+            vec![],
+            NearToken::from_yoctonear(0),
+            GAS_FOR_MIGRATE,
+            GasWeight(1),
+        )
+    }
+
+    /// Upgrades the state to the current layout, picked by the stored state version. State
+    /// without a version is the first release's. Stashes and vaults are stored tagged with their
+    /// layout and upgraded as they are read, so current state is left as it is.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let contract = match Self::internal_read_state_version() {
+            None => env::state_read::<ContractV1>().expect("ERR_NOT_INITIALIZED").into(),
+            Some(STATE_VERSION) => env::state_read::<Self>().expect("ERR_NOT_INITIALIZED"),
+            Some(_) => env::panic_str("ERR_UNKNOWN_STATE_VERSION"),
+        };
+        Self::internal_write_state_version();
+        contract
+    }
+}
+
+// internal methods
+impl Contract {
+    pub(crate) fn internal_write_state_version() {
+        env::storage_write(STATE_VERSION_KEY, &STATE_VERSION.to_le_bytes());
+    }
+
+    fn internal_read_state_version() -> Option<u32> {
+        env::storage_read(STATE_VERSION_KEY)
+            .map(|version| u32::from_le_bytes(version.try_into().expect("ERR_UNKNOWN_STATE_VERSION")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::json_types::U128;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::stash::StashStatus;
+    use crate::test_utils::{get_context, new_contract};
+
+    fn usdt() -> AccountId {
+        "usdt-token.near".parse().unwrap()
+    }
+
+    /// Contract with stash 0 holding 2_000 usdt of accounts(0), 1_000 of it pooled.
+    fn setup(context: &mut VMContextBuilder) -> Contract {
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = new_contract(None);
+        contract.storage_deposit(None, None);
+        contract.create_stash("Roommates".to_string());
+        contract.add_token_to_stash(0, usdt());
+
+        testing_env!(context.predecessor_account_id(usdt()).attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract.ft_on_transfer(accounts(0), U128(2_000), r#"{"stash_id": 0}"#.to_string());
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.add_liquidity_to_stash(0, usdt(), 1_000);
+        contract
+    }
+
+    /// Writes the state of the first release after accounts(0) created stashes 0 and 2 and
+    /// accounts(1) created stash 1 and removed it, with a usdt vault added to stash 0.
+    fn write_v1_state() {
+        let stash = |id: u64, name: &str| StashV1 {
+            id,
+            name: name.to_string(),
+            vaults: LookupMap::new(V1_PREFIX_VAULTS),
+            deposited_amounts: LookupMap::new(b"d".to_vec()),
+            authorized_users: LookupMap::new(V1_PREFIX_AUTHORIZED_USERS),
+        };
+        let mut stashes = UnorderedMap::new(b"s".to_vec());
+        stashes.insert(&0, &stash(0, "Roommates"));
+        stashes.insert(&2, &stash(2, "Road trip"));
+        let mut accounts_map = UnorderedMap::new(b"a".to_vec());
+        let mut authorized_users = LookupMap::new(V1_PREFIX_AUTHORIZED_USERS);
+        for account_id in [accounts(0), accounts(1)] {
+            accounts_map.insert(&account_id, &UnorderedSet::new(b"s".to_vec()));
+            authorized_users.insert(&account_id, &true);
+        }
+        let mut shares = LookupMap::new(V1_PREFIX_SHARES);
+        shares.insert(&accounts(0), &0);
+        let mut vaults = LookupMap::new(V1_PREFIX_VAULTS);
+        vaults.insert(&usdt(), &TokenVaultV1 { token_type: usdt(), total_assets: 0, shares_total_supply: 0, shares });
+        env::state_write(&ContractV1 { stashes, accounts: accounts_map });
+    }
+
+    #[test]
+    fn test_migrate_v1() {
+        testing_env!(get_context(env::current_account_id()).build());
+        write_v1_state();
+
+        let contract = Contract::migrate();
+        assert_eq!(contract.owner_id, env::current_account_id());
+        assert_eq!(contract.next_stash_id, 3);
+        assert_eq!(contract.get_supported_tokens().len(), 6);
+        assert!(contract.get_stash(1).is_none());
+        for stash_id in [0, 2] {
+            let stash = contract.internal_get_stash(stash_id);
+            assert_eq!(stash.get_status(), StashStatus::Open);
+            assert_eq!(stash.get_vault_tokens(), vec![usdt()]);
+            assert_eq!(stash.get_role(&env::current_account_id()), Some(Role::Owner));
+            assert_eq!(stash.get_role(&accounts(0)), Some(Role::Admin));
+            assert_eq!(stash.get_role(&accounts(1)), Some(Role::Admin));
+        }
+        assert_eq!(contract.get_stash(2).unwrap().name, "Road trip");
+        assert_eq!(contract.get_stashes_for_account(accounts(1)), vec![0, 2]);
+
+        // the keys of the first release are gone
+        assert!(!env::storage_has_key(&[V1_PREFIX_VAULTS, &near_sdk::borsh::to_vec(&usdt()).unwrap()].concat()));
+        for account_id in [accounts(0), accounts(1)] {
+            let key = near_sdk::borsh::to_vec(&account_id).unwrap();
+            assert!(!env::storage_has_key(&[V1_PREFIX_AUTHORIZED_USERS, &key].concat()));
+            assert!(!env::storage_has_key(&[V1_PREFIX_SHARES, &key].concat()));
+        }
+        let mut old_index = b"si".to_vec();
+        old_index.extend(near_sdk::borsh::to_vec(&0u64).unwrap());
+        assert!(!env::storage_has_key(&old_index));
+        assert_eq!(Contract::internal_read_state_version(), Some(STATE_VERSION));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_LEGACY_VAULT_NOT_EMPTY")]
+    fn test_migrate_v1_funded_vault() {
+        testing_env!(get_context(env::current_account_id()).build());
+        write_v1_state();
+        let mut vaults: LookupMap<AccountId, TokenVaultV1> = LookupMap::new(V1_PREFIX_VAULTS);
+        let mut vault = vaults.get(&usdt()).unwrap();
+        vault.total_assets = 1;
+        vaults.insert(&usdt(), &vault);
+
+        Contract::migrate();
+    }
+
+    #[test]
+    fn test_migrate_current_state() {
+        let mut context = get_context(accounts(0));
+        let contract = setup(&mut context);
+        env::state_write(&contract);
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        let contract = Contract::migrate();
+        assert_eq!(contract.next_stash_id, 1);
+        assert_eq!(contract.get_deposit(0, accounts(0), usdt()).0, 1_000);
+        assert_eq!(contract.get_shares(0, usdt(), accounts(0)).0, 1_000_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_UNKNOWN_STATE_VERSION")]
+    fn test_migrate_unknown_version() {
+        let mut context = get_context(accounts(0));
+        let contract = setup(&mut context);
+        env::state_write(&contract);
+        env::storage_write(STATE_VERSION_KEY, &(STATE_VERSION + 1).to_le_bytes());
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        Contract::migrate();
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_OWNER")]
+    fn test_upgrade_owner_only() {
+        let mut context = get_context(accounts(0));
+        let contract = setup(&mut context);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.upgrade();
+    }
+}
@@ -0,0 +1,341 @@
+use near_contract_standards::fungible_token::Balance;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{assert_one_yocto, env, near, AccountId};
+
+use crate::config::MethodClass;
+use crate::math::{mul_div, Rounding};
+use crate::{Contract, ContractExt};
+
+/// When the shares of a grant vest.
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct VestingSchedule {
+    /// Block timestamp vesting starts at, in nanoseconds. May be in the past.
+    pub start: U64,
+    /// Nanoseconds after `start` before anything vests, what accrued until then vests at once.
+    pub cliff: U64,
+    /// Nanoseconds after `start` until all shares are vested, linearly.
+    pub duration: U64,
+    /// Whether the grantor can take back the unvested shares, see `revoke_grant`.
+    pub revocable: bool,
+}
+
+/// Vault shares vesting to a member. Shares not claimed yet are held by the contract's own
+/// account, see `TokenVault::grant_shares`.
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct VestingGrant {
+    pub grantor_id: AccountId,
+    /// Shares granted.
+    pub shares: U128,
+    /// Shares already moved to the member's balance.
+    pub claimed: U128,
+    pub schedule: VestingSchedule,
+}
+
+#[near(serializers = [json])]
+pub struct VestingGrantView {
+    pub grant: VestingGrant,
+    pub vested: U128,
+    /// Vested shares not claimed yet.
+    pub claimable: U128,
+}
+
+impl VestingSchedule {
+    /// Panics unless the duration is positive and the cliff is within it.
+    pub fn assert_valid(&self) {
+        assert!(self.duration.0 > 0, "ERR_INVALID_DURATION");
+        assert!(self.cliff.0 <= self.duration.0, "ERR_INVALID_CLIFF");
+    }
+}
+
+impl VestingGrant {
+    /// Shares vested by now, rounded down.
+    pub fn vested(&self) -> Balance {
+        let schedule = &self.schedule;
+        let elapsed = env::block_timestamp().saturating_sub(schedule.start.0);
+        if elapsed < schedule.cliff.0 {
+            0
+        } else if elapsed >= schedule.duration.0 {
+            self.shares.0
+        } else {
+            mul_div(self.shares.0, elapsed as u128, schedule.duration.0 as u128, Rounding::Down)
+        }
+    }
+
+    pub fn claimable(&self) -> Balance {
+        self.vested() - self.claimed.0
+    }
+}
+
+#[near]
+impl Contract {
+    /// Moves `shares` of the caller into a grant vesting to `account_id`, which has to be allowed
+    /// to deposit into the stash. A member has at most one grant per vault. Requires 1 yoctoNEAR.
+    #[payable]
+    pub fn grant_vested_shares(
+        &mut self,
+        stash_id: u64,
+        token_id: AccountId,
+        account_id: AccountId,
+        shares: U128,
+        schedule: VestingSchedule,
+    ) {
+        assert_one_yocto();
+        let prev_storage = env::storage_usage();
+        let mut stash = self.internal_get_stash(stash_id);
+        self.assert_not_paused(&stash, MethodClass::Withdrawals);
+        stash.grant_vested_shares(&token_id, &account_id, shares.0, schedule);
+        self.internal_save_stash(stash_id, stash);
+        self.internal_check_storage(&env::predecessor_account_id(), prev_storage);
+    }
+
+    /// Moves the vested shares of the caller's grant to its balance. Returns the claimed shares.
+    pub fn claim_vested(&mut self, stash_id: u64, token_id: AccountId) -> U128 {
+        let prev_storage = env::storage_usage();
+        let mut stash = self.internal_get_stash(stash_id);
+        self.assert_not_paused(&stash, MethodClass::Withdrawals);
+        let shares = stash.claim_vested(&token_id);
+        self.internal_save_stash(stash_id, stash);
+        self.internal_check_storage(&env::predecessor_account_id(), prev_storage);
+        U128(shares)
+    }
+
+    /// Ends a revocable grant of the caller, the vested shares go to the member and the unvested
+    /// ones back to the caller. Requires 1 yoctoNEAR. Returns the shares given back.
+    #[payable]
+    pub fn revoke_grant(&mut self, stash_id: u64, token_id: AccountId, account_id: AccountId) -> U128 {
+        assert_one_yocto();
+        let prev_storage = env::storage_usage();
+        let mut stash = self.internal_get_stash(stash_id);
+        self.assert_not_paused(&stash, MethodClass::Withdrawals);
+        let shares = stash.revoke_grant(&token_id, &account_id);
+        self.internal_save_stash(stash_id, stash);
+        self.internal_check_storage(&env::predecessor_account_id(), prev_storage);
+        U128(shares)
+    }
+
+    /// Grant of the account in the vault with what vested of it so far.
+    pub fn get_vesting_grant(&self, stash_id: u64, token_id: AccountId, account_id: AccountId) -> Option<VestingGrantView> {
+        let grant = self.internal_get_vault(stash_id, &token_id).get_grant(&account_id)?;
+        Some(VestingGrantView { vested: U128(grant.vested()), claimable: U128(grant.claimable()), grant })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, NearToken};
+
+    use super::*;
+    use crate::events::{DivvyEvent, MtEvent, MtTransfer};
+    use crate::multi_token::share_token_id;
+    use crate::stash::Role;
+    use crate::test_utils::{add_member, get_context, get_events, get_mt_events, new_contract};
+
+    const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+    fn usdt() -> AccountId {
+        "usdt-token.near".parse().unwrap()
+    }
+
+    fn schedule(revocable: bool) -> VestingSchedule {
+        VestingSchedule { start: U64(0), cliff: U64(90 * DAY), duration: U64(360 * DAY), revocable }
+    }
+
+    /// "Trust fund" with a usdt vault where accounts(0) pooled 10_000 and granted 3_600_000 shares
+    /// to accounts(1) on `schedule`. Attaches 1 yoctoNEAR.
+    fn setup(context: &mut VMContextBuilder, revocable: bool) -> Contract {
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = new_contract(None);
+        contract.storage_deposit(None, None);
+        contract.create_stash("Trust fund".to_string());
+        contract.add_token_to_stash(0, usdt());
+        add_member(context, &mut contract, 0, accounts(1), Role::Contributor);
+        testing_env!(context.predecessor_account_id(usdt()).build());
+        contract.ft_on_transfer(accounts(0), U128(10_000), r#"{"stash_id": 0, "add_liquidity": true}"#.to_string());
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(1)).build());
+        contract.grant_vested_shares(0, usdt(), accounts(1), U128(3_600_000), schedule(revocable));
+        contract
+    }
+
+    fn shares(contract: &Contract, account_id: AccountId) -> u128 {
+        contract.get_shares(0, usdt(), account_id).0
+    }
+
+    #[test]
+    fn test_vesting_math() {
+        let context = VMContextBuilder::new();
+        let grant = VestingGrant {
+            grantor_id: accounts(0),
+            shares: U128(3_600),
+            claimed: U128(0),
+            schedule: VestingSchedule { start: U64(10 * DAY), ..schedule(false) },
+        };
+        let vested = |timestamp: u64| {
+            testing_env!(context.clone().block_timestamp(timestamp).build());
+            grant.vested()
+        };
+        assert_eq!(vested(0), 0);
+        assert_eq!(vested(99 * DAY), 0);
+        // everything accrued until the cliff vests at once
+        assert_eq!(vested(100 * DAY), 900);
+        assert_eq!(vested(100 * DAY + 1), 900);
+        assert_eq!(vested(190 * DAY), 1_800);
+        assert_eq!(vested(370 * DAY), 3_600);
+        assert_eq!(vested(1_000 * DAY), 3_600);
+    }
+
+    #[test]
+    fn test_grant_holds_shares() {
+        let mut context = get_context(accounts(0));
+        let contract = setup(&mut context, false);
+        assert_eq!(shares(&contract, accounts(0)), 6_400_000);
+        assert_eq!(shares(&contract, accounts(1)), 0);
+        assert_eq!(shares(&contract, env::current_account_id()), 3_600_000);
+        assert_eq!(
+            get_events(),
+            vec![DivvyEvent::VestingGranted {
+                stash_id: 0,
+                token_id: usdt(),
+                account_id: accounts(1),
+                grant: VestingGrant { grantor_id: accounts(0), shares: U128(3_600_000), claimed: U128(0), schedule: schedule(false) },
+            }]
+        );
+        assert_eq!(
+            get_mt_events(),
+            vec![MtEvent::MtTransfer(vec![MtTransfer {
+                old_owner_id: accounts(0),
+                new_owner_id: env::current_account_id(),
+                token_ids: vec![share_token_id(0, &usdt())],
+                amounts: vec![U128(3_600_000)],
+                memo: None,
+            }])]
+        );
+    }
+
+    #[test]
+    fn test_claim_vested() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context, false);
+        testing_env!(context.predecessor_account_id(accounts(1)).block_timestamp(45 * DAY).build());
+        assert_eq!(contract.claim_vested(0, usdt()), U128(0));
+
+        testing_env!(context.block_timestamp(180 * DAY).build());
+        assert_eq!(contract.claim_vested(0, usdt()), U128(1_800_000));
+        assert_eq!(shares(&contract, accounts(1)), 1_800_000);
+        let view = contract.get_vesting_grant(0, usdt(), accounts(1)).unwrap();
+        assert_eq!((view.grant.claimed, view.vested, view.claimable), (U128(1_800_000), U128(1_800_000), U128(0)));
+
+        testing_env!(context.block_timestamp(400 * DAY).build());
+        assert_eq!(contract.claim_vested(0, usdt()), U128(1_800_000));
+        assert_eq!(shares(&contract, accounts(1)), 3_600_000);
+        assert_eq!(shares(&contract, env::current_account_id()), 0);
+        assert!(contract.get_vesting_grant(0, usdt(), accounts(1)).is_none());
+    }
+
+    #[test]
+    fn test_remove_liquidity_redeems_vested_shares() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context, false);
+        testing_env!(context.predecessor_account_id(accounts(1)).block_timestamp(120 * DAY).build());
+        contract.remove_liquidity_from_stash(0, usdt(), 1_200_000);
+        assert_eq!(contract.get_deposit(0, accounts(1), usdt()), U128(1_200));
+        assert_eq!(shares(&contract, accounts(1)), 0);
+        assert_eq!(contract.get_vesting_grant(0, usdt(), accounts(1)).unwrap().grant.claimed, U128(1_200_000));
+    }
+
+    #[test]
+    fn test_max_redeem_includes_vested_shares() {
+        let mut context = get_context(accounts(0));
+        let contract = setup(&mut context, false);
+        testing_env!(context.block_timestamp(120 * DAY).build());
+        assert_eq!(contract.max_redeem(0, usdt(), accounts(1)), U128(1_200_000));
+        assert_eq!(contract.max_withdraw(0, usdt(), accounts(1)), U128(1_200));
+        assert_eq!(contract.max_redeem(0, usdt(), accounts(0)), U128(6_400_000));
+    }
+
+    #[test]
+    fn test_exit_redeems_vested_shares() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context, false);
+        contract.revoke_role(0, accounts(1));
+        testing_env!(context.predecessor_account_id(accounts(1)).block_timestamp(120 * DAY).build());
+        contract.exit_stash(0, usdt());
+        assert_eq!(contract.get_vesting_grant(0, usdt(), accounts(1)).unwrap().grant.claimed, U128(1_200_000));
+        assert_eq!(shares(&contract, accounts(1)), 0);
+        assert_eq!(contract.get_deposit(0, accounts(1), usdt()), U128(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough shares to withdraw")]
+    fn test_unvested_shares_cannot_be_redeemed() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context, false);
+        testing_env!(context.predecessor_account_id(accounts(1)).block_timestamp(120 * DAY).build());
+        contract.remove_liquidity_from_stash(0, usdt(), 1_200_001);
+    }
+
+    #[test]
+    fn test_revoke_grant() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context, true);
+        testing_env!(context.block_timestamp(90 * DAY).build());
+        assert_eq!(contract.revoke_grant(0, usdt(), accounts(1)), U128(2_700_000));
+        assert_eq!(shares(&contract, accounts(0)), 9_100_000);
+        assert_eq!(shares(&contract, accounts(1)), 900_000);
+        assert_eq!(shares(&contract, env::current_account_id()), 0);
+        assert!(contract.get_vesting_grant(0, usdt(), accounts(1)).is_none());
+        assert!(get_events().contains(&DivvyEvent::VestingRevoked {
+            stash_id: 0,
+            token_id: usdt(),
+            account_id: accounts(1),
+            returned: U128(2_700_000),
+        }));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_GRANT_NOT_REVOCABLE")]
+    fn test_irrevocable_grant() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context, false);
+        contract.revoke_grant(0, usdt(), accounts(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_GRANTOR")]
+    fn test_revoke_grant_grantor_only() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context, true);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.revoke_grant(0, usdt(), accounts(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_GRANT_EXISTS")]
+    fn test_one_grant_per_vault() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context, false);
+        contract.grant_vested_shares(0, usdt(), accounts(1), U128(100), schedule(false));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_RECEIVER_NOT_AUTHORIZED")]
+    fn test_grant_to_non_member() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context, false);
+        contract.grant_vested_shares(0, usdt(), accounts(2), U128(100), schedule(false));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_CLIFF")]
+    fn test_cliff_after_duration() {
+        let mut context = get_context(accounts(0));
+        let mut contract = setup(&mut context, false);
+        add_member(&mut context, &mut contract, 0, accounts(2), Role::Contributor);
+        let schedule = VestingSchedule { cliff: U64(400 * DAY), ..schedule(false) };
+        contract.grant_vested_shares(0, usdt(), accounts(2), U128(100), schedule);
+    }
+}
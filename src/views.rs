@@ -0,0 +1,305 @@
+use near_sdk::json_types::U128;
+use near_sdk::{near, AccountId};
+
+use crate::config::PauseFlags;
+use crate::lock::LockStatus;
+use crate::stash::{Role, Stash, StashStatus};
+use crate::token_vault::{TokenVault, VaultFees};
+use crate::{Contract, ContractExt};
+
+#[near(serializers = [json])]
+pub struct MemberView {
+    pub account_id: AccountId,
+    pub role: Role,
+}
+
+#[near(serializers = [json])]
+pub struct StashView {
+    pub id: u64,
+    pub name: String,
+    pub status: StashStatus,
+    /// Method classes paused for this stash only, see `get_config` for the global ones.
+    pub paused: PauseFlags,
+    pub members: Vec<MemberView>,
+    /// Tokens the stash has a vault for.
+    pub vaults: Vec<AccountId>,
+    /// Lock of the funds, see `set_stash_lock`.
+    pub lock: Option<LockStatus>,
+}
+
+#[near(serializers = [json])]
+pub struct VaultView {
+    pub token_id: AccountId,
+    pub total_assets: U128,
+    pub shares_total_supply: U128,
+    pub fees: VaultFees,
+    /// Account the fee shares are paid to, `None` while the vault charges no fees.
+    pub fee_receiver_id: Option<AccountId>,
+    /// Management fee shares owed but not yet minted, counted in every quote.
+    pub pending_management_fee: U128,
+}
+
+#[near(serializers = [json])]
+pub struct TokenBalance {
+    pub token_id: AccountId,
+    pub amount: U128,
+}
+
+/// Shares of a member in one vault and the assets they are currently worth.
+#[near(serializers = [json])]
+pub struct VaultPosition {
+    pub token_id: AccountId,
+    pub shares: U128,
+    pub assets: U128,
+}
+
+#[near(serializers = [json])]
+pub struct MemberPosition {
+    pub account_id: AccountId,
+    /// `None` for accounts which left or were removed but still hold deposits or shares.
+    pub role: Option<Role>,
+    pub deposits: Vec<TokenBalance>,
+    /// Vaults the account holds shares in.
+    pub vaults: Vec<VaultPosition>,
+}
+
+#[near]
+impl Contract {
+    pub fn get_stash(&self, stash_id: u64) -> Option<StashView> {
+        self.stashes.get(&stash_id).map(Stash::from).map(|stash| StashView {
+            id: stash.get_id(),
+            name: stash.get_name(),
+            status: stash.get_status(),
+            paused: stash.get_paused(),
+            members: stash
+                .get_members()
+                .into_iter()
+                .map(|(account_id, role)| MemberView { account_id, role })
+                .collect(),
+            vaults: stash.get_vault_tokens(),
+            lock: stash.get_lock_status(),
+        })
+    }
+
+    pub fn get_stashes_for_account(&self, account_id: AccountId) -> Vec<u64> {
+        self.accounts.get(&account_id).map(|set| set.to_vec()).unwrap_or_default()
+    }
+
+    pub fn get_vault(&self, stash_id: u64, token_id: AccountId) -> Option<VaultView> {
+        self.internal_get_stash(stash_id).get_vault(&token_id).map(|vault| VaultView {
+            token_id,
+            total_assets: U128(vault.get_total_assets()),
+            shares_total_supply: U128(vault.get_shares_total_supply()),
+            fees: vault.get_fees(),
+            fee_receiver_id: vault.get_fee_receiver_id(),
+            pending_management_fee: U128(vault.pending_management_fee()),
+        })
+    }
+
+    pub fn get_shares(&self, stash_id: u64, token_id: AccountId, account_id: AccountId) -> U128 {
+        let stash = self.internal_get_stash(stash_id);
+        U128(stash.get_vault(&token_id).map_or(0, |vault| vault.get_shares(&account_id)))
+    }
+
+    pub fn get_deposit(&self, stash_id: u64, account_id: AccountId, token_id: AccountId) -> U128 {
+        U128(self.internal_get_stash(stash_id).get_deposit(&account_id, &token_id))
+    }
+
+    pub fn get_deposits(&self, stash_id: u64, account_id: AccountId) -> Vec<TokenBalance> {
+        self.internal_get_stash(stash_id)
+            .get_deposits(&account_id)
+            .into_iter()
+            .map(|(token_id, amount)| TokenBalance { token_id, amount: U128(amount) })
+            .collect()
+    }
+
+    /// Role, deposits and vault shares of an account, with the shares converted into assets.
+    pub fn get_member_position(&self, stash_id: u64, account_id: AccountId) -> MemberPosition {
+        let stash = self.internal_get_stash(stash_id);
+        let vaults = stash
+            .get_vault_tokens()
+            .into_iter()
+            .filter_map(|token_id| {
+                let vault = stash.get_vault(&token_id)?;
+                let shares = vault.get_shares(&account_id);
+                (shares > 0).then(|| VaultPosition {
+                    token_id,
+                    shares: U128(shares),
+                    assets: U128(vault.convert_to_assets(shares)),
+                })
+            })
+            .collect();
+        MemberPosition {
+            role: stash.get_role(&account_id),
+            deposits: self.get_deposits(stash_id, account_id.clone()),
+            vaults,
+            account_id,
+        }
+    }
+
+    // ERC-4626 style quotes of a vault, conversions and previews round in favour of the vault
+
+    pub fn convert_to_shares(&self, stash_id: u64, token_id: AccountId, assets: U128) -> U128 {
+        U128(self.internal_get_vault(stash_id, &token_id).convert_to_shares(assets.0))
+    }
+
+    pub fn convert_to_assets(&self, stash_id: u64, token_id: AccountId, shares: U128) -> U128 {
+        U128(self.internal_get_vault(stash_id, &token_id).convert_to_assets(shares.0))
+    }
+
+    pub fn preview_deposit(&self, stash_id: u64, token_id: AccountId, assets: U128) -> U128 {
+        U128(self.internal_get_vault(stash_id, &token_id).preview_deposit(assets.0))
+    }
+
+    pub fn preview_mint(&self, stash_id: u64, token_id: AccountId, shares: U128) -> U128 {
+        U128(self.internal_get_vault(stash_id, &token_id).preview_mint(shares.0))
+    }
+
+    pub fn preview_withdraw(&self, stash_id: u64, token_id: AccountId, assets: U128) -> U128 {
+        U128(self.internal_get_vault(stash_id, &token_id).preview_withdraw(assets.0))
+    }
+
+    pub fn preview_redeem(&self, stash_id: u64, token_id: AccountId, shares: U128) -> U128 {
+        U128(self.internal_get_vault(stash_id, &token_id).preview_redeem(shares.0))
+    }
+
+    /// Assets the account can move into the vault, limited to its deposits in the stash.
+    /// 0 for accounts which may not add liquidity.
+    pub fn max_deposit(&self, stash_id: u64, token_id: AccountId, account_id: AccountId) -> U128 {
+        let stash = self.internal_get_stash(stash_id);
+        let vault = stash.get_vault(&token_id).expect("ERR_NO_VAULT");
+        if !stash.is_authorized(&account_id) {
+            return U128(0);
+        }
+        U128(stash.get_deposit(&account_id, &token_id).min(vault.max_deposit()))
+    }
+
+    pub fn max_withdraw(&self, stash_id: u64, token_id: AccountId, account_id: AccountId) -> U128 {
+        U128(self.internal_get_vault(stash_id, &token_id).max_withdraw(&account_id))
+    }
+
+    pub fn max_redeem(&self, stash_id: u64, token_id: AccountId, account_id: AccountId) -> U128 {
+        U128(self.internal_get_vault(stash_id, &token_id).max_redeem(&account_id))
+    }
+}
+
+// internal methods
+impl Contract {
+    pub(crate) fn internal_get_vault(&self, stash_id: u64, token_id: &AccountId) -> TokenVault {
+        self.internal_get_stash(stash_id).get_vault(token_id).expect("ERR_NO_VAULT")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::accounts;
+    use near_sdk::{testing_env, NearToken};
+
+    use super::*;
+    use crate::proposal::ProposalKind;
+    use crate::test_utils::{add_member, get_context, new_contract};
+
+    fn usdt() -> AccountId {
+        "usdt-token.near".parse().unwrap()
+    }
+
+    fn usdc() -> AccountId {
+        "usdc-token.near".parse().unwrap()
+    }
+
+    /// Stash 0 with usdt and usdc vaults, accounts(0) deposited 10_000 usdt and pooled 6_000 of it.
+    fn setup() -> Contract {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = new_contract(None);
+        contract.storage_deposit(None, None);
+        contract.create_stash("Roommates".to_string());
+        contract.add_token_to_stash(0, usdt());
+        contract.add_token_to_stash(0, usdc());
+        add_member(&mut context, &mut contract, 0, accounts(1), Role::Viewer);
+
+        testing_env!(context.predecessor_account_id(usdt()).attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract.ft_on_transfer(accounts(0), U128(10_000), r#"{"stash_id": 0}"#.to_string());
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.add_liquidity_to_stash(0, usdt(), 6_000);
+        contract
+    }
+
+    #[test]
+    fn test_get_stash() {
+        let contract = setup();
+        let stash = contract.get_stash(0).unwrap();
+        assert_eq!(stash.name, "Roommates");
+        assert_eq!(stash.vaults, vec![usdt(), usdc()]);
+        let members: Vec<_> = stash.members.into_iter().map(|member| (member.account_id, member.role)).collect();
+        assert_eq!(members, vec![(accounts(0), Role::Owner), (accounts(1), Role::Viewer)]);
+        assert!(contract.get_stash(1).is_none());
+    }
+
+    #[test]
+    fn test_get_vault_and_shares() {
+        let contract = setup();
+        let vault = contract.get_vault(0, usdt()).unwrap();
+        assert_eq!(vault.total_assets.0, 6_000);
+        assert_eq!(vault.shares_total_supply.0, 6_000_000);
+        assert_eq!(contract.get_shares(0, usdt(), accounts(0)).0, 6_000_000);
+        assert_eq!(contract.get_shares(0, usdt(), accounts(1)).0, 0);
+        assert!(contract.get_vault(0, "eth-token.near".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_get_member_position() {
+        let contract = setup();
+        let position = contract.get_member_position(0, accounts(0));
+        assert_eq!(position.role, Some(Role::Owner));
+        assert_eq!(position.deposits.len(), 1);
+        assert_eq!(position.deposits[0].token_id, usdt());
+        assert_eq!(position.deposits[0].amount.0, 4_000);
+        assert_eq!(position.vaults.len(), 1);
+        assert_eq!(position.vaults[0].token_id, usdt());
+        assert_eq!(position.vaults[0].shares.0, 6_000_000);
+        assert_eq!(position.vaults[0].assets.0, 6_000);
+
+        let position = contract.get_member_position(0, accounts(2));
+        assert_eq!(position.role, None);
+        assert!(position.deposits.is_empty() && position.vaults.is_empty());
+    }
+
+    #[test]
+    fn test_vault_quotes() {
+        let mut contract = setup();
+        // a payout leaves 3 assets for every 4_000 shares
+        contract.add_proposal(
+            0,
+            ProposalKind::Payout { token_id: usdt(), receiver_id: accounts(3), amount: U128(1_500) },
+        );
+
+        assert_eq!(contract.convert_to_shares(0, usdt(), U128(10)).0, 13_332);
+        assert_eq!(contract.convert_to_assets(0, usdt(), U128(10_000)).0, 7);
+        assert_eq!(contract.preview_deposit(0, usdt(), U128(10)).0, 13_332);
+        assert_eq!(contract.preview_mint(0, usdt(), U128(10_000)).0, 8);
+        assert_eq!(contract.preview_withdraw(0, usdt(), U128(10)).0, 13_333);
+        assert_eq!(contract.preview_redeem(0, usdt(), U128(10_000)).0, 7);
+
+        assert_eq!(contract.max_deposit(0, usdt(), accounts(0)).0, 4_000);
+        assert_eq!(contract.max_deposit(0, usdt(), accounts(1)).0, 0);
+        assert_eq!(contract.max_withdraw(0, usdt(), accounts(0)).0, 4_500);
+        assert_eq!(contract.max_redeem(0, usdt(), accounts(0)).0, 6_000_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_VAULT")]
+    fn test_quote_without_vault() {
+        let contract = setup();
+        contract.preview_deposit(0, "eth-token.near".parse().unwrap(), U128(10));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_STASH_NOT_FOUND")]
+    fn test_get_deposits_unknown_stash() {
+        let contract = setup();
+        contract.get_deposits(1, accounts(0));
+    }
+}
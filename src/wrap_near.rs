@@ -0,0 +1,359 @@
+use near_sdk::json_types::U128;
+use near_sdk::{env, ext_contract, log, near, AccountId, Gas, NearToken, Promise, PromiseError, PromiseOrValue};
+
+use crate::config::MethodClass;
+use crate::stash::Stash;
+use crate::token_vault::MIN_FIRST_DEPOSIT;
+use crate::{Contract, ContractExt};
+
+const GAS_FOR_NEAR_DEPOSIT: Gas = Gas::from_tgas(10);
+const GAS_FOR_NEAR_WITHDRAW: Gas = Gas::from_tgas(10);
+const GAS_FOR_ON_NEAR_REWRAPPED: Gas = Gas::from_tgas(5);
+// covers re-wrapping the NEAR if the transfer bounces
+const GAS_FOR_ON_NEAR_SENT: Gas = Gas::from_tgas(25);
+const GAS_FOR_ON_NEAR_UNWRAPPED: Gas = Gas::from_tgas(40);
+// covers unwrapping and refunding the NEAR if the stash no longer takes the deposit
+const GAS_FOR_ON_NEAR_DEPOSIT: Gas = Gas::from_tgas(70);
+
+/// Subset of the wNEAR (`wrap.near`) interface used for native NEAR.
+#[allow(dead_code)]
+#[ext_contract(ext_wrap_near)]
+pub trait WrapNear {
+    fn near_deposit(&mut self);
+    fn near_withdraw(&mut self, amount: U128) -> Promise;
+}
+
+#[near]
+impl Contract {
+    /// Deposits the attached NEAR into the stash. The NEAR is wrapped with `near_deposit` on
+    /// the configured wrap contract and credited to the caller's deposits of that token, or
+    /// straight into its vault with `add_liquidity`. If wrapping fails the NEAR is refunded.
+    #[payable]
+    pub fn deposit_near(&mut self, stash_id: u64, add_liquidity: Option<bool>) -> Promise {
+        let amount = env::attached_deposit();
+        assert!(!amount.is_zero(), "ERR_ZERO_AMOUNT");
+        let wrap_id = self.config.wrap_near_id.clone().expect("ERR_WRAP_NOT_CONFIGURED");
+        let sender_id = env::predecessor_account_id();
+        let add_liquidity = add_liquidity.unwrap_or(false);
+
+        let stash = self.internal_get_stash(stash_id);
+        self.assert_not_paused(&stash, MethodClass::Deposits);
+        assert!(
+            self.is_supported_token(&wrap_id) && stash.accepts_deposit(&sender_id, &wrap_id),
+            "ERR_DEPOSIT_NOT_ACCEPTED"
+        );
+        assert!(self.storage_accounts.get(&sender_id).is_some(), "ERR_STORAGE_NOT_REGISTERED");
+        // checked upfront to fail fast, the callback refunds if the vault changed meanwhile
+        if add_liquidity {
+            let vault = stash.get_vault(&wrap_id).expect("ERR_NO_VAULT");
+            let amount = amount.as_yoctonear();
+            assert!(vault.get_shares_total_supply() > 0 || amount >= MIN_FIRST_DEPOSIT, "ERR_MIN_FIRST_DEPOSIT");
+            assert!(vault.preview_deposit(amount) > 0, "ERR_ZERO_SHARES");
+        }
+
+        ext_wrap_near::ext(wrap_id.clone())
+            .with_attached_deposit(amount)
+            .with_static_gas(GAS_FOR_NEAR_DEPOSIT)
+            .near_deposit()
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_ON_NEAR_DEPOSIT)
+                    .on_near_deposit(stash_id, sender_id, wrap_id, U128(amount.as_yoctonear()), add_liquidity),
+            )
+    }
+
+    /// Credits the NEAR wrapped by `wrap_id` to the user's deposits. Refunds the NEAR if
+    /// wrapping failed, or unwraps and refunds it if the stash no longer accepts the deposit or
+    /// its vault can no longer take the liquidity. Returns the credited amount.
+    #[private]
+    pub fn on_near_deposit(
+        &mut self,
+        stash_id: u64,
+        account_id: AccountId,
+        wrap_id: AccountId,
+        amount: U128,
+        add_liquidity: bool,
+        #[callback_result] result: Result<(), PromiseError>,
+    ) -> PromiseOrValue<U128> {
+        if result.is_err() {
+            log!("Wrapping {} yoctoNEAR for {} failed, refunding", amount.0, account_id);
+            return Promise::new(account_id).transfer(NearToken::from_yoctonear(amount.0)).into();
+        }
+        let stash = self
+            .stashes
+            .get(&stash_id)
+            .map(Stash::from)
+            .filter(|stash| !self.is_paused(stash, MethodClass::Deposits) && stash.accepts_deposit(&account_id, &wrap_id))
+            .filter(|stash| !add_liquidity || stash.get_vault(&wrap_id).is_some_and(|vault| vault.can_add_liquidity(amount.0)));
+        match stash {
+            Some(mut stash) => {
+                let prev_storage = env::storage_usage();
+                stash.ft_deposit(&account_id, &wrap_id, amount.0, add_liquidity);
+                self.internal_save_stash(stash_id, stash);
+                self.internal_record_storage(&account_id, prev_storage);
+                PromiseOrValue::Value(amount)
+            }
+            None => {
+                log!("ERR_DEPOSIT_NOT_ACCEPTED stash {} no longer takes NEAR from {}, refunding", stash_id, account_id);
+                self.internal_unwrap_and_transfer(&wrap_id, &account_id, amount).into()
+            }
+        }
+    }
+
+    /// Sends the unwrapped NEAR to the receiver. Fails if unwrapping failed so the caller's
+    /// callback restores the tokens.
+    #[private]
+    pub fn on_near_unwrapped(
+        &mut self,
+        wrap_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+        #[callback_result] result: Result<(), PromiseError>,
+    ) -> Promise {
+        if result.is_err() {
+            env::panic_str("ERR_NEAR_WITHDRAW_FAILED");
+        }
+        Promise::new(receiver_id).transfer(NearToken::from_yoctonear(amount.0)).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_ON_NEAR_SENT)
+                .on_near_sent(wrap_id, amount),
+        )
+    }
+
+    /// Wraps the NEAR again with `wrap_id` if the transfer bounced, the contract keeps holding
+    /// wNEAR only.
+    #[private]
+    pub fn on_near_sent(
+        &mut self,
+        wrap_id: AccountId,
+        amount: U128,
+        #[callback_result] result: Result<(), PromiseError>,
+    ) -> PromiseOrValue<()> {
+        if result.is_ok() {
+            return PromiseOrValue::Value(());
+        }
+        log!("Transfer of {} yoctoNEAR failed, wrapping it again", amount.0);
+        ext_wrap_near::ext(wrap_id)
+            .with_attached_deposit(NearToken::from_yoctonear(amount.0))
+            .with_static_gas(GAS_FOR_NEAR_DEPOSIT)
+            .near_deposit()
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_ON_NEAR_REWRAPPED)
+                    .on_near_rewrapped(amount),
+            )
+            .into()
+    }
+
+    /// Fails the transfer once the NEAR is wrapped again so the caller's callback restores
+    /// the tokens.
+    #[private]
+    pub fn on_near_rewrapped(&mut self, amount: U128, #[callback_result] result: Result<(), PromiseError>) {
+        if result.is_err() {
+            log!("ERR_NEAR_REWRAP_FAILED {} yoctoNEAR remain unwrapped", amount.0);
+        }
+        env::panic_str("ERR_NEAR_TRANSFER_FAILED");
+    }
+}
+
+// internal methods
+impl Contract {
+    /// Unwraps `amount` of wNEAR with `near_withdraw` and sends the NEAR to `receiver_id`.
+    /// The returned promise fails if either step fails, with the wNEAR still held.
+    pub(crate) fn internal_unwrap_and_transfer(&self, wrap_id: &AccountId, receiver_id: &AccountId, amount: U128) -> Promise {
+        ext_wrap_near::ext(wrap_id.clone())
+            .with_attached_deposit(NearToken::from_yoctonear(1))
+            .with_static_gas(GAS_FOR_NEAR_WITHDRAW)
+            .near_withdraw(amount)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_ON_NEAR_UNWRAPPED)
+                    .on_near_unwrapped(wrap_id.clone(), receiver_id.clone(), amount),
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::stash::Role;
+    use crate::test_utils::{add_member, get_events, new_contract, TOKENS};
+    use crate::events::DivvyEvent;
+
+    fn wrap() -> AccountId {
+        "wrap.near".parse().unwrap()
+    }
+
+    /// Stash 0 with a `wrap.near` vault, accounts(1) is a viewer.
+    fn setup(context: &mut VMContextBuilder) -> Contract {
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = new_contract(None);
+        contract.storage_deposit(None, None);
+        contract.storage_deposit(Some(accounts(1)), None);
+        let stash_id = contract.create_stash("Roommates".to_string());
+        contract.add_token_to_stash(stash_id, wrap());
+        add_member(context, &mut contract, stash_id, accounts(1), Role::Viewer);
+        testing_env!(context.attached_deposit(NearToken::from_near(5)).build());
+        contract
+    }
+
+    fn deposit_of(contract: &Contract, account_id: &AccountId) -> u128 {
+        contract.internal_get_stash(0).get_deposit(account_id, &wrap())
+    }
+
+    fn near(amount: u128) -> U128 {
+        U128(NearToken::from_near(amount).as_yoctonear())
+    }
+
+    #[test]
+    fn test_deposit_near_credits_once_wrapped() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.deposit_near(0, None);
+        // nothing is credited before the wrap contract minted the tokens
+        assert_eq!(deposit_of(&contract, &accounts(0)), 0);
+
+        testing_env!(context
+            .predecessor_account_id(env::current_account_id())
+            .attached_deposit(NearToken::from_yoctonear(0))
+            .build());
+        contract.on_near_deposit(0, accounts(0), wrap(), near(5), false, Ok(()));
+        assert_eq!(deposit_of(&contract, &accounts(0)), near(5).0);
+        assert_eq!(
+            get_events(),
+            vec![DivvyEvent::Deposit { stash_id: 0, account_id: accounts(0), token_id: wrap(), amount: near(5) }]
+        );
+    }
+
+    #[test]
+    fn test_deposit_near_add_liquidity() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.deposit_near(0, Some(true));
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        contract.on_near_deposit(0, accounts(0), wrap(), near(5), true, Ok(()));
+        assert_eq!(deposit_of(&contract, &accounts(0)), 0);
+        assert_eq!(contract.get_shares(0, wrap(), accounts(0)).0, near(5).0 * 1_000);
+    }
+
+    #[test]
+    fn test_failed_wrap_credits_nothing() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.deposit_near(0, None);
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        let result = contract.on_near_deposit(0, accounts(0), wrap(), near(5), false, Err(PromiseError::Failed));
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        assert_eq!(deposit_of(&contract, &accounts(0)), 0);
+    }
+
+    #[test]
+    fn test_wrapped_near_refunded_when_stash_removed() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        contract.deposit_near(0, None);
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract.remove_stash(0);
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        let result = contract.on_near_deposit(0, accounts(0), wrap(), near(5), false, Ok(()));
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        assert!(contract.get_stash(0).is_none());
+    }
+
+    #[test]
+    fn test_wrapped_near_refunded_when_vault_emptied() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        contract.on_near_deposit(0, accounts(0), wrap(), near(5), true, Ok(()));
+        // passes while the vault has shares, too small for a first deposit once they are gone
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(500)).build());
+        contract.deposit_near(0, Some(true));
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(1)).build());
+        let shares = contract.get_shares(0, wrap(), accounts(0)).0;
+        contract.remove_liquidity_from_stash(0, wrap(), shares);
+
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        let result = contract.on_near_deposit(0, accounts(0), wrap(), U128(500), true, Ok(()));
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        assert_eq!(deposit_of(&contract, &accounts(0)), near(5).0);
+        assert_eq!(contract.get_vault(0, wrap()).unwrap().shares_total_supply.0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ZERO_AMOUNT")]
+    fn test_deposit_near_requires_deposit() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract.deposit_near(0, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_WRAP_NOT_CONFIGURED")]
+    fn test_deposit_near_requires_wrap_contract() {
+        let context = VMContextBuilder::new();
+        testing_env!(context.build());
+        let mut contract = Contract::new(
+            accounts(0),
+            TOKENS.iter().map(|token| token.parse().unwrap()).collect(),
+            None,
+            None,
+        );
+        testing_env!(context.clone().attached_deposit(NearToken::from_near(5)).build());
+        contract.deposit_near(0, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_DEPOSIT_NOT_ACCEPTED")]
+    fn test_deposit_near_requires_contributor() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.deposit_near(0, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MIN_FIRST_DEPOSIT")]
+    fn test_deposit_near_first_liquidity_too_small() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(MIN_FIRST_DEPOSIT - 1)).build());
+        contract.deposit_near(0, Some(true));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NEAR_WITHDRAW_FAILED")]
+    fn test_failed_unwrap_fails_transfer() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        contract.on_near_unwrapped(wrap(), accounts(0), near(5), Err(PromiseError::Failed));
+    }
+
+    #[test]
+    fn test_bounced_transfer_is_wrapped_again() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        assert!(matches!(contract.on_near_sent(wrap(), near(5), Ok(())), PromiseOrValue::Value(())));
+        assert!(matches!(contract.on_near_sent(wrap(), near(5), Err(PromiseError::Failed)), PromiseOrValue::Promise(_)));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NEAR_TRANSFER_FAILED")]
+    fn test_rewrapped_near_fails_transfer() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context);
+        testing_env!(context.predecessor_account_id(env::current_account_id()).build());
+        contract.on_near_rewrapped(near(5), Ok(()));
+    }
+}
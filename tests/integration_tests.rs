@@ -1,13 +1,22 @@
 
 
-use near_sdk::NearToken;
+use near_sdk::json_types::U128;
+use near_sdk::{Gas, NearToken};
 use near_workspaces::Account;
+use near_workspaces::AccountId;
 use near_workspaces::Contract;
 use near_workspaces::network::Sandbox;
+use near_workspaces::operations::Function;
 use near_workspaces::Worker;
 use near_workspaces::Result;
 use serde_json::json;
 
+const MOCK_FT_WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/mock_ft.wasm");
+const MOCK_DEX_WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/mock_dex.wasm");
+const MOCK_WRAP_WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/mock_wrap.wasm");
+/// `DEPOSIT_SWAP_GAS` of src/dex.rs, the swap tests attach no more than that.
+const DEPOSIT_SWAP_GAS: Gas = Gas::from_tgas(160);
+
 
 async fn setup_env() -> Result<(Worker<Sandbox>, Account, Contract)> {
     let worker = near_workspaces::sandbox().await?;
@@ -17,11 +26,27 @@ async fn setup_env() -> Result<(Worker<Sandbox>, Account, Contract)> {
     Ok((worker, root, contract))
 }
 
+async fn storage_deposit(account: &Account, contract: &Contract) -> Result<()> {
+    let outcome = account
+        .call(contract.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(NearToken::from_near(1))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "storage_deposit failed: {:#?}", outcome);
+    Ok(())
+}
+
 async fn init() -> Result<(Worker<Sandbox>, Account, Contract)> {
     let (worker, root, contract) = setup_env().await?;
 
     // initialize the contract
-    let result = contract.call("new").transact().await?.into_result()?;
+    let result = contract
+        .call("new")
+        .args_json(json!({"owner_id": root.id(), "supported_tokens": ["usdt.token.near"]}))
+        .transact()
+        .await?
+        .into_result()?;
     assert!(result.outcome().is_success(), "Contract initialization failed");
     Ok((worker, root, contract))
 }
@@ -29,23 +54,23 @@ async fn init() -> Result<(Worker<Sandbox>, Account, Contract)> {
 #[tokio::test]
 async fn test_create_stash() -> Result<()> {
     let (_worker, root, contract) = init().await?;
+    storage_deposit(&root, &contract).await?;
 
     // Create a stash
     let outcome = root
-        .call(&contract.id(), "create_stash")
+        .call(contract.id(), "create_stash")
         .args_json(json!({"name": "Roommate slush funds"}))
-        .deposit(NearToken::from_yoctonear(1_000_000_000_000_000_000_000_000))
         .transact()
         .await?;
 
     assert!(outcome.is_success());
-    println!("root id is {}", &root.id());
+    println!("root id is {}", root.id());
 
-    let args = &json!({"account_id": &root.id()});
+    let args = &json!({"account_id": root.id()});
     println!("args are {:#?}", args);
 
     // Check the stash was created
-    let c = root.view(&contract.id(), "get_stashes_for_account")
+    let c = root.view(contract.id(), "get_stashes_for_account")
         .args_json(args)
         .await?;
     c.logs.iter().for_each(|log| println!("{}", log));
@@ -70,10 +95,11 @@ async fn test_add_token_to_stash() -> Result<()> {
     // TODO remove worker if truuely unused
     let (_worker, root, contract) = setup_env().await?;
 
+    storage_deposit(&root, &contract).await?;
+
     // Create a stash
-    let mut outcome  = root.call(&contract.id(), "create_stash")
+    let mut outcome  = root.call(contract.id(), "create_stash")
         .args_json(serde_json::json!({"name": "Close Friends"}))
-        .deposit(NearToken::from_yoctonear(1_000_000_000_000_000_000_000_000))
         .transact()
         .await?;
 
@@ -81,7 +107,7 @@ async fn test_add_token_to_stash() -> Result<()> {
 
     // Add a token to the stash
     outcome = root
-        .call(&contract.id(), "add_token_to_stash")
+        .call(contract.id(), "add_token_to_stash")
         .args_json(serde_json::json!({"stash_id": 0, "token_id": "usdt.token.near"}))
         .transact()
         .await?;
@@ -95,10 +121,11 @@ async fn test_add_token_to_stash() -> Result<()> {
 async fn test_remove_stash() -> Result<()> {
     let (_worker, root, contract) = setup_env().await?;
 
+    storage_deposit(&root, &contract).await?;
+
     // Create a stash
-    let mut outcome = root.call(&contract.id(), "create_stash")
+    let mut outcome = root.call(contract.id(), "create_stash")
         .args_json(serde_json::json!({"name": "Roommates"}))
-        .deposit(NearToken::from_yoctonear(1_000_000_000_000_000_000_000_000))
         .transact()
         .await?;
 
@@ -107,7 +134,7 @@ async fn test_remove_stash() -> Result<()> {
 
     // Remove the stash
     outcome = root
-        .call(&contract.id(),  "remove_stash")
+        .call(contract.id(),  "remove_stash")
         .args_json(serde_json::json!({"stash_id": 0}))
         .transact()
         .await?;
@@ -124,3 +151,540 @@ async fn test_remove_stash() -> Result<()> {
     assert_eq!(stashes.len(), 0);
     Ok(())
 }
+
+async fn deploy_mock_token(worker: &Worker<Sandbox>, symbol: &str) -> Result<AccountId> {
+    let token = worker.dev_deploy(MOCK_FT_WASM).await?;
+    token
+        .call("new")
+        .args_json(json!({"symbol": symbol, "decimals": 6}))
+        .transact()
+        .await?
+        .into_result()?;
+    Ok(token.id().clone())
+}
+
+async fn mint(root: &Account, token_id: &AccountId, account_id: &AccountId, amount: u128) -> Result<()> {
+    let outcome = root
+        .call(token_id, "mint")
+        .args_json(json!({"account_id": account_id, "amount": U128(amount)}))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "mint failed: {:#?}", outcome);
+    Ok(())
+}
+
+async fn ft_balance_of(worker: &Worker<Sandbox>, token_id: &AccountId, account_id: &AccountId) -> Result<u128> {
+    let balance: U128 = worker
+        .view(token_id, "ft_balance_of")
+        .args_json(json!({"account_id": account_id}))
+        .await?
+        .json()?;
+    Ok(balance.0)
+}
+
+async fn get_deposit(worker: &Worker<Sandbox>, contract: &Contract, stash_id: u64, account_id: &AccountId, token_id: &AccountId) -> Result<u128> {
+    let deposit: U128 = worker
+        .view(contract.id(), "get_deposit")
+        .args_json(json!({"stash_id": stash_id, "account_id": account_id, "token_id": token_id}))
+        .await?
+        .json()?;
+    Ok(deposit.0)
+}
+
+/// Stash 0 owned by root with usdt and usdc vaults and 1_000 usdt deposited by root,
+/// swappable 1:2 into usdc through pool 0 of the mock exchange.
+async fn init_swap() -> Result<(Worker<Sandbox>, Account, Contract, Contract, AccountId, AccountId)> {
+    init_swap_with(true).await
+}
+
+/// Same as `init_swap`, the contract is only registered with usdc if `register_usdc`.
+async fn init_swap_with(register_usdc: bool) -> Result<(Worker<Sandbox>, Account, Contract, Contract, AccountId, AccountId)> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let dex = worker.dev_deploy(MOCK_DEX_WASM).await?;
+    dex.call("new").transact().await?.into_result()?;
+    let usdt = deploy_mock_token(&worker, "USDT").await?;
+    let usdc = deploy_mock_token(&worker, "USDC").await?;
+
+    let wasm = include_bytes!("../target/wasm32-unknown-unknown/release/divvy_wealth.wasm");
+    let contract = worker.dev_deploy(wasm).await?;
+    contract
+        .call("new")
+        .args_json(json!({"owner_id": root.id(), "supported_tokens": [usdt, usdc], "dex_id": dex.id()}))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    mint(&root, &usdt, contract.id(), 0).await?;
+    if register_usdc {
+        mint(&root, &usdc, contract.id(), 0).await?;
+    }
+    for token_id in [&usdt, &usdc] {
+        mint(&root, token_id, dex.id(), 0).await?;
+    }
+    mint(&root, &usdt, root.id(), 1_000).await?;
+    mint(&root, &usdc, dex.id(), 1_000_000).await?;
+    root.call(dex.id(), "add_pool")
+        .args_json(json!({"token_in": usdt, "token_out": usdc, "rate_numerator": "2", "rate_denominator": "1"}))
+        .transact()
+        .await?
+        .into_result()?;
+
+    storage_deposit(&root, &contract).await?;
+    root.call(contract.id(), "create_stash")
+        .args_json(json!({"name": "Roommate slush funds"}))
+        .transact()
+        .await?
+        .into_result()?;
+    for token_id in [&usdt, &usdc] {
+        root.call(contract.id(), "add_token_to_stash")
+            .args_json(json!({"stash_id": 0, "token_id": token_id}))
+            .transact()
+            .await?
+            .into_result()?;
+    }
+    root.call(&usdt, "ft_transfer_call")
+        .args_json(json!({"receiver_id": contract.id(), "amount": U128(1_000), "msg": json!({"stash_id": 0}).to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    assert_eq!(get_deposit(&worker, &contract, 0, root.id(), &usdt).await?, 1_000);
+
+    Ok((worker, root, contract, dex, usdt, usdc))
+}
+
+#[tokio::test]
+async fn test_deposit_swap() -> Result<()> {
+    let (worker, root, contract, dex, usdt, usdc) = init_swap().await?;
+
+    let outcome = root
+        .call(contract.id(), "deposit_swap")
+        .args_json(json!({
+            "stash_id": 0,
+            "pool_id": 0,
+            "token_in": usdt,
+            "token_out": usdc,
+            "amount_in": U128(400),
+            "min_amount_out": U128(800),
+        }))
+        .deposit(NearToken::from_yoctonear(1))
+        .gas(DEPOSIT_SWAP_GAS)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "deposit_swap failed: {:#?}", outcome);
+    assert!(outcome.receipt_failures().is_empty(), "deposit_swap ran out of gas: {:#?}", outcome);
+    assert_eq!(outcome.json::<U128>()?.0, 800);
+
+    assert_eq!(get_deposit(&worker, &contract, 0, root.id(), &usdt).await?, 600);
+    assert_eq!(get_deposit(&worker, &contract, 0, root.id(), &usdc).await?, 800);
+    assert_eq!(ft_balance_of(&worker, &usdt, contract.id()).await?, 600);
+    assert_eq!(ft_balance_of(&worker, &usdc, contract.id()).await?, 800);
+    assert_eq!(ft_balance_of(&worker, &usdt, dex.id()).await?, 400);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_deposit_swap_refunds_on_slippage() -> Result<()> {
+    let (worker, root, contract, _dex, usdt, usdc) = init_swap().await?;
+
+    // the pool only returns 800 for 400
+    let outcome = root
+        .call(contract.id(), "deposit_swap")
+        .args_json(json!({
+            "stash_id": 0,
+            "pool_id": 0,
+            "token_in": usdt,
+            "token_out": usdc,
+            "amount_in": U128(400),
+            "min_amount_out": U128(801),
+        }))
+        .deposit(NearToken::from_yoctonear(1))
+        .gas(DEPOSIT_SWAP_GAS)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "deposit_swap failed: {:#?}", outcome);
+
+    assert_eq!(get_deposit(&worker, &contract, 0, root.id(), &usdt).await?, 1_000);
+    assert_eq!(get_deposit(&worker, &contract, 0, root.id(), &usdc).await?, 0);
+    assert_eq!(ft_balance_of(&worker, &usdt, contract.id()).await?, 1_000);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_retry_stranded_swap_withdraw() -> Result<()> {
+    let (worker, root, contract, dex, usdt, usdc) = init_swap_with(false).await?;
+
+    // the exchange can't send usdc back until the contract is registered with it
+    let outcome = root
+        .call(contract.id(), "deposit_swap")
+        .args_json(json!({
+            "stash_id": 0,
+            "pool_id": 0,
+            "token_in": usdt,
+            "token_out": usdc,
+            "amount_in": U128(400),
+            "min_amount_out": U128(800),
+        }))
+        .deposit(NearToken::from_yoctonear(1))
+        .gas(DEPOSIT_SWAP_GAS)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "deposit_swap failed: {:#?}", outcome);
+    assert_eq!(outcome.json::<U128>()?.0, 0);
+    assert_eq!(get_deposit(&worker, &contract, 0, root.id(), &usdc).await?, 0);
+    let stranded: serde_json::Value = worker
+        .view(contract.id(), "get_stranded_swaps")
+        .args_json(json!({"stash_id": 0, "account_id": root.id()}))
+        .await?
+        .json()?;
+    assert_eq!(stranded[0]["amount"], "800");
+
+    mint(&root, &usdc, contract.id(), 0).await?;
+    let outcome = root
+        .call(contract.id(), "retry_swap_withdraw")
+        .args_json(json!({"stash_id": 0, "token_id": usdc}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "retry_swap_withdraw failed: {:#?}", outcome);
+    assert_eq!(outcome.json::<U128>()?.0, 800);
+    assert_eq!(get_deposit(&worker, &contract, 0, root.id(), &usdc).await?, 800);
+    assert_eq!(ft_balance_of(&worker, &usdc, contract.id()).await?, 800);
+    assert_eq!(ft_balance_of(&worker, &usdc, dex.id()).await?, 1_000_000 - 800);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_supported_token_metadata() -> Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let usdt = deploy_mock_token(&worker, "USDT").await?;
+    let usdc = deploy_mock_token(&worker, "USDC").await?;
+
+    let wasm = include_bytes!("../target/wasm32-unknown-unknown/release/divvy_wealth.wasm");
+    let contract = worker.dev_deploy(wasm).await?;
+    contract
+        .call("new")
+        .args_json(json!({"owner_id": root.id(), "supported_tokens": [usdt]}))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // only the owner can list tokens
+    let outcome = contract
+        .as_account()
+        .call(contract.id(), "add_supported_token")
+        .args_json(json!({"token_id": usdc}))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure());
+
+    let outcome = root
+        .call(contract.id(), "add_supported_token")
+        .args_json(json!({"token_id": usdc}))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "add_supported_token failed: {:#?}", outcome);
+    assert!(outcome.json::<bool>()?);
+
+    let tokens: serde_json::Value = contract.view("get_supported_tokens").await?.json()?;
+    let tokens = tokens.as_array().unwrap();
+    assert_eq!(tokens.len(), 2);
+    for token in tokens {
+        let metadata = &token["metadata"];
+        assert_eq!(metadata["decimals"], 6);
+        let expected_symbol = if token["token_id"] == json!(usdt) { "USDT" } else { "USDC" };
+        assert_eq!(metadata["symbol"], expected_symbol);
+    }
+    Ok(())
+}
+
+const DIVVY_WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/divvy_wealth.wasm");
+
+/// Stashes 0 and 1 owned by root, each with a usdt vault where root pooled all but 1_000 of
+/// the 3_000 and 5_000 usdt deposited.
+async fn init_two_stashes() -> Result<(Worker<Sandbox>, Account, Contract, AccountId)> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let usdt = deploy_mock_token(&worker, "USDT").await?;
+
+    let contract = worker.dev_deploy(DIVVY_WASM).await?;
+    contract
+        .call("new")
+        .args_json(json!({"owner_id": root.id(), "supported_tokens": [usdt]}))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    mint(&root, &usdt, contract.id(), 0).await?;
+    mint(&root, &usdt, root.id(), 10_000).await?;
+    storage_deposit(&root, &contract).await?;
+
+    for (stash_id, name, amount) in [(0u64, "Roommates", 3_000u128), (1, "Close Friends", 5_000)] {
+        root.call(contract.id(), "create_stash")
+            .args_json(json!({"name": name}))
+            .transact()
+            .await?
+            .into_result()?;
+        root.call(contract.id(), "add_token_to_stash")
+            .args_json(json!({"stash_id": stash_id, "token_id": usdt}))
+            .transact()
+            .await?
+            .into_result()?;
+        root.call(&usdt, "ft_transfer_call")
+            .args_json(json!({"receiver_id": contract.id(), "amount": U128(amount), "msg": json!({"stash_id": stash_id}).to_string()}))
+            .deposit(NearToken::from_yoctonear(1))
+            .max_gas()
+            .transact()
+            .await?
+            .into_result()?;
+        root.call(contract.id(), "add_liquidity_to_stash")
+            .args_json(json!({"stash_id": stash_id, "token_id": usdt, "amount": amount - 1_000}))
+            .max_gas()
+            .transact()
+            .await?
+            .into_result()?;
+    }
+    Ok((worker, root, contract, usdt))
+}
+
+/// Checks the balances left by `init_two_stashes`.
+async fn assert_two_stashes(worker: &Worker<Sandbox>, root: &Account, contract: &Contract, usdt: &AccountId) -> Result<()> {
+    for (stash_id, shares) in [(0u64, 2_000_000u128), (1, 4_000_000)] {
+        assert_eq!(get_deposit(worker, contract, stash_id, root.id(), usdt).await?, 1_000);
+        let pooled: U128 = worker
+            .view(contract.id(), "get_shares")
+            .args_json(json!({"stash_id": stash_id, "token_id": usdt, "account_id": root.id()}))
+            .await?
+            .json()?;
+        assert_eq!(pooled.0, shares);
+    }
+
+    let stashes: Vec<u64> = contract
+        .view("get_stashes_for_account")
+        .args_json(json!({"account_id": root.id()}))
+        .await?
+        .json()?;
+    assert_eq!(stashes, vec![0, 1]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stashes_do_not_share_state() -> Result<()> {
+    let (worker, root, contract, usdt) = init_two_stashes().await?;
+    assert_two_stashes(&worker, &root, &contract, &usdt).await
+}
+
+#[tokio::test]
+async fn test_upgrade_keeps_balances() -> Result<()> {
+    let (worker, root, contract, usdt) = init_two_stashes().await?;
+
+    // only the owner can upgrade
+    let outcome = contract
+        .as_account()
+        .call(contract.id(), "upgrade")
+        .args(DIVVY_WASM.to_vec())
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure());
+
+    let outcome = root
+        .call(contract.id(), "upgrade")
+        .args(DIVVY_WASM.to_vec())
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "upgrade failed: {:#?}", outcome);
+    assert_two_stashes(&worker, &root, &contract, &usdt).await?;
+
+    // new stashes keep counting from the existing ones
+    let stash_id: u64 = root
+        .call(contract.id(), "create_stash")
+        .args_json(json!({"name": "Trip"}))
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(stash_id, 2);
+    Ok(())
+}
+
+/// Wasm of the first release, built from its commit by `scripts/build_v1_fixture.sh`.
+const DIVVY_V1_WASM_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/res/divvy_v1.wasm");
+
+#[tokio::test]
+async fn test_upgrade_from_v1() -> Result<()> {
+    let v1_wasm = std::fs::read(DIVVY_V1_WASM_PATH)
+        .unwrap_or_else(|err| panic!("{}: {}, run scripts/build_v1_fixture.sh first", DIVVY_V1_WASM_PATH, err));
+    let worker = near_workspaces::sandbox().await?;
+    let contract = worker.dev_deploy(&v1_wasm).await?;
+    contract.call("new").transact().await?.into_result()?;
+    let alice = worker.dev_create_account().await?;
+    let bob = worker.dev_create_account().await?;
+
+    for (account, name) in [(&alice, "Roommates"), (&bob, "Close Friends")] {
+        account
+            .call(contract.id(), "create_stash")
+            .args_json(json!({"name": name}))
+            .deposit(NearToken::from_near(1))
+            .transact()
+            .await?
+            .into_result()?;
+    }
+    alice
+        .call(contract.id(), "add_token_to_stash")
+        .args_json(json!({"stash_id": 0, "token_id": "usdt-token.near"}))
+        .deposit(NearToken::from_near(1))
+        .transact()
+        .await?
+        .into_result()?;
+
+    // the first release has no `upgrade`, the contract account deploys and migrates itself
+    let outcome = contract
+        .batch()
+        .deploy(DIVVY_WASM)
+        .call(Function::new("migrate").args_json(json!({})).gas(Gas::from_tgas(100)))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "migration failed: {:#?}", outcome);
+
+    // authorization was shared by all stashes, both creators administer both
+    for stash_id in [0u64, 1] {
+        let stash: serde_json::Value = contract.view("get_stash").args_json(json!({"stash_id": stash_id})).await?.json()?;
+        assert_eq!(stash["vaults"], json!(["usdt-token.near"]));
+        for account in [&alice, &bob] {
+            assert!(stash["members"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|member| member["account_id"] == json!(account.id()) && member["role"] == json!("Admin")));
+        }
+    }
+    let stashes: Vec<u64> =
+        contract.view("get_stashes_for_account").args_json(json!({"account_id": alice.id()})).await?.json()?;
+    assert_eq!(stashes, vec![0, 1]);
+
+    // the contract account owns the migrated contract and new ids follow the existing ones
+    storage_deposit(&alice, &contract).await?;
+    let stash_id: u64 = alice
+        .call(contract.id(), "create_stash")
+        .args_json(json!({"name": "Trip"}))
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(stash_id, 2);
+    let outcome = contract
+        .as_account()
+        .call(contract.id(), "upgrade")
+        .args(DIVVY_WASM.to_vec())
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "upgrade failed: {:#?}", outcome);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mt_transfer_call_refunds_without_receiver() -> Result<()> {
+    let (worker, root, contract, usdt) = init_two_stashes().await?;
+    let member = worker.dev_create_account().await?;
+    root.call(contract.id(), "invite_member")
+        .args_json(json!({"stash_id": 0, "account_id": member.id(), "role": "Contributor"}))
+        .transact()
+        .await?
+        .into_result()?;
+    storage_deposit(&member, &contract).await?;
+    member
+        .call(contract.id(), "accept_invite")
+        .args_json(json!({"stash_id": 0}))
+        .transact()
+        .await?
+        .into_result()?;
+
+    // the member has no contract to accept the shares, so they go back to root
+    let token_id = format!("0:{}", usdt);
+    let outcome = root
+        .call(contract.id(), "mt_transfer_call")
+        .args_json(json!({"receiver_id": member.id(), "token_id": token_id, "amount": U128(500_000), "msg": ""}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "mt_transfer_call failed: {:#?}", outcome);
+    let used: Vec<U128> = outcome.json()?;
+    assert_eq!(used, vec![U128(0)]);
+
+    let balances: Vec<U128> = contract
+        .view("mt_batch_balance_of")
+        .args_json(json!({"account_id": root.id(), "token_ids": [token_id, format!("1:{}", usdt)]}))
+        .await?
+        .json()?;
+    assert_eq!(balances, vec![U128(2_000_000), U128(4_000_000)]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_native_near_is_wrapped_and_unwrapped() -> Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let wrap = worker.dev_deploy(MOCK_WRAP_WASM).await?;
+    wrap.call("new").transact().await?.into_result()?;
+
+    let contract = worker.dev_deploy(DIVVY_WASM).await?;
+    contract
+        .call("new")
+        .args_json(json!({"owner_id": root.id(), "supported_tokens": [wrap.id()], "wrap_near_id": wrap.id()}))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    storage_deposit(&root, &contract).await?;
+    root.call(contract.id(), "create_stash")
+        .args_json(json!({"name": "Roommates"}))
+        .transact()
+        .await?
+        .into_result()?;
+    root.call(contract.id(), "add_token_to_stash")
+        .args_json(json!({"stash_id": 0, "token_id": wrap.id()}))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let outcome = root
+        .call(contract.id(), "deposit_near")
+        .args_json(json!({"stash_id": 0}))
+        .deposit(NearToken::from_near(5))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "deposit_near failed: {:#?}", outcome);
+    let five = NearToken::from_near(5).as_yoctonear();
+    assert_eq!(get_deposit(&worker, &contract, 0, root.id(), wrap.id()).await?, five);
+    assert_eq!(ft_balance_of(&worker, wrap.id(), contract.id()).await?, five);
+
+    let balance_before = root.view_account().await?.balance;
+    let two = NearToken::from_near(2).as_yoctonear();
+    let outcome = root
+        .call(contract.id(), "withdraw_from_stash")
+        .args_json(json!({"stash_id": 0, "token_id": wrap.id(), "amount": U128(two)}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "withdraw_from_stash failed: {:#?}", outcome);
+    let withdrawn: bool = outcome.json()?;
+    assert!(withdrawn);
+    assert_eq!(get_deposit(&worker, &contract, 0, root.id(), wrap.id()).await?, five - two);
+    assert_eq!(ft_balance_of(&worker, wrap.id(), contract.id()).await?, five - two);
+    // the 2 NEAR arrived, less the gas of the call
+    let received = root.view_account().await?.balance.as_yoctonear() - balance_before.as_yoctonear();
+    assert!(received > NearToken::from_millinear(1_900).as_yoctonear(), "received {}", received);
+    Ok(())
+}